@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE transactions
+    DROP COLUMN IF EXISTS reversed_by,
+    DROP COLUMN IF EXISTS reverses,
+    DROP COLUMN IF EXISTS exchange_rate;
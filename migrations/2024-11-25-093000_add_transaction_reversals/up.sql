@@ -0,0 +1,7 @@
+-- Your SQL goes here
+
+-- Link compensating transactions (reversals and refunds) to the transaction they undo
+ALTER TABLE transactions
+    ADD COLUMN exchange_rate DOUBLE PRECISION,
+    ADD COLUMN reverses UUID REFERENCES transactions(transaction_id),
+    ADD COLUMN reversed_by UUID REFERENCES transactions(transaction_id);
@@ -0,0 +1,12 @@
+-- This file should undo anything in `up.sql`
+
+-- Without a status, decided requests would look pending again; archive them before removing them
+CREATE TABLE IF NOT EXISTS pending_transactions_decided (LIKE pending_transactions);
+INSERT INTO pending_transactions_decided SELECT * FROM pending_transactions WHERE status <> 'pending';
+DELETE FROM pending_transactions WHERE status <> 'pending';
+
+ALTER TABLE pending_transactions
+    DROP COLUMN IF EXISTS decision_reason,
+    DROP COLUMN IF EXISTS decided_at,
+    DROP COLUMN IF EXISTS reviewed_by,
+    DROP COLUMN IF EXISTS status;
@@ -0,0 +1,8 @@
+-- Your SQL goes here
+
+-- Keep decided deposit requests around instead of deleting them on approval
+ALTER TABLE pending_transactions
+    ADD COLUMN status VARCHAR NOT NULL DEFAULT 'pending',
+    ADD COLUMN reviewed_by VARCHAR,
+    ADD COLUMN decided_at TIMESTAMP,
+    ADD COLUMN decision_reason VARCHAR;
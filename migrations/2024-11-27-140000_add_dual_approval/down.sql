@@ -0,0 +1,7 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE pending_transactions
+    DROP COLUMN IF EXISTS initiated_by;
+
+DROP TABLE IF EXISTS pending_transaction_approvals CASCADE;
+DROP TABLE IF EXISTS approval_thresholds CASCADE;
+DROP TABLE IF EXISTS operators CASCADE;
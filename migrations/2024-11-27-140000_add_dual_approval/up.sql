@@ -0,0 +1,37 @@
+-- Your SQL goes here
+
+-- Operators who can log into admin mode and review deposit requests
+CREATE TABLE operators (
+    username VARCHAR PRIMARY KEY,
+    passwd VARCHAR NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
+
+-- Deposits at or above these amounts need two distinct operators to approve them
+CREATE TABLE approval_thresholds (
+    currency VARCHAR PRIMARY KEY,
+    amount DOUBLE PRECISION NOT NULL
+);
+
+-- Every individual operator approval of a pending transaction
+CREATE TABLE pending_transaction_approvals (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    pending_transaction_id UUID NOT NULL REFERENCES pending_transactions(id) ON DELETE CASCADE,
+    approved_by VARCHAR NOT NULL,
+    approved_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    UNIQUE (pending_transaction_id, approved_by)
+);
+
+-- Operator who raised an admin adjustment, NULL for deposits requested by the account holder
+ALTER TABLE pending_transactions
+    ADD COLUMN initiated_by VARCHAR;
+
+INSERT INTO approval_thresholds (currency, amount)
+VALUES
+    ('USD', 10000.0),
+    ('EUR', 10000.0),
+    ('GBP', 10000.0),
+    ('JPY', 1500000.0),
+    ('INR', 800000.0),
+    ('SGD', 10000.0),
+    ('AUD', 10000.0);
@@ -0,0 +1,6 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE transactions
+    DROP COLUMN IF EXISTS fx_fee_bearer,
+    DROP COLUMN IF EXISTS fx_fee,
+    DROP COLUMN IF EXISTS received_currency,
+    DROP COLUMN IF EXISTS received_amount;
@@ -0,0 +1,8 @@
+-- Your SQL goes here
+
+-- Record the receiving leg of a conversion and who paid the FX spread
+ALTER TABLE transactions
+    ADD COLUMN received_amount DOUBLE PRECISION,
+    ADD COLUMN received_currency VARCHAR,
+    ADD COLUMN fx_fee DOUBLE PRECISION,
+    ADD COLUMN fx_fee_bearer VARCHAR;
@@ -0,0 +1,6 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE scheduled_transactions
+    DROP COLUMN IF EXISTS occurrences_executed,
+    DROP COLUMN IF EXISTS occurrence_count,
+    DROP COLUMN IF EXISTS end_date,
+    DROP COLUMN IF EXISTS recurrence;
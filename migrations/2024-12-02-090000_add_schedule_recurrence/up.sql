@@ -0,0 +1,12 @@
+-- Your SQL goes here
+
+-- Recurrence rule (subset of iCalendar RRULE) and optional end conditions for each schedule
+ALTER TABLE scheduled_transactions
+    ADD COLUMN recurrence VARCHAR NOT NULL DEFAULT 'FREQ=MONTHLY',
+    ADD COLUMN end_date TIMESTAMP,
+    ADD COLUMN occurrence_count INTEGER,
+    ADD COLUMN occurrences_executed INTEGER NOT NULL DEFAULT 0;
+
+-- Existing schedules were monthly on the day they were created for
+UPDATE scheduled_transactions
+SET recurrence = 'FREQ=MONTHLY;BYMONTHDAY=' || EXTRACT(DAY FROM scheduled_date)::INTEGER;
@@ -0,0 +1,7 @@
+-- This file should undo anything in `up.sql`
+DELETE FROM scheduled_transactions WHERE kind = 'one_off';
+
+ALTER TABLE scheduled_transactions
+    ALTER COLUMN recurrence SET DEFAULT 'FREQ=MONTHLY',
+    ALTER COLUMN recurrence SET NOT NULL,
+    DROP COLUMN IF EXISTS kind;
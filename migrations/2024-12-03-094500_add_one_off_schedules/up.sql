@@ -0,0 +1,7 @@
+-- Your SQL goes here
+
+-- One-off payments execute once and have no recurrence rule
+ALTER TABLE scheduled_transactions
+    ADD COLUMN kind VARCHAR NOT NULL DEFAULT 'recurring',
+    ALTER COLUMN recurrence DROP NOT NULL,
+    ALTER COLUMN recurrence DROP DEFAULT;
@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE scheduled_transactions
+    DROP COLUMN IF EXISTS catch_up_policy;
@@ -0,0 +1,5 @@
+-- Your SQL goes here
+
+-- What the processor does with occurrences it missed (execute_all, latest_only or skip_and_notify)
+ALTER TABLE scheduled_transactions
+    ADD COLUMN catch_up_policy VARCHAR NOT NULL DEFAULT 'execute_all';
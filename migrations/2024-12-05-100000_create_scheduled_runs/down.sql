@@ -0,0 +1,6 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE scheduled_transactions
+    DROP COLUMN IF EXISTS consecutive_failures,
+    DROP COLUMN IF EXISTS status;
+
+DROP TABLE IF EXISTS scheduled_runs CASCADE;
@@ -0,0 +1,20 @@
+-- Your SQL goes here
+
+-- One row per occurrence of a schedule the processor has handled
+CREATE TABLE scheduled_runs (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    scheduled_transaction_id UUID NOT NULL REFERENCES scheduled_transactions(id) ON DELETE CASCADE,
+    occurrence_date TIMESTAMP NOT NULL,
+    status VARCHAR NOT NULL,
+    attempt_count INTEGER NOT NULL DEFAULT 0,
+    last_error VARCHAR,
+    transaction_id UUID REFERENCES transactions(transaction_id),
+    created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    UNIQUE (scheduled_transaction_id, occurrence_date)
+);
+
+-- Schedules are paused automatically after repeated failures
+ALTER TABLE scheduled_transactions
+    ADD COLUMN status VARCHAR NOT NULL DEFAULT 'active',
+    ADD COLUMN consecutive_failures INTEGER NOT NULL DEFAULT 0;
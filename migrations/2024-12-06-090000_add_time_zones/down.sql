@@ -0,0 +1,34 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE scheduled_runs
+    ALTER COLUMN occurrence_date TYPE TIMESTAMP USING occurrence_date AT TIME ZONE 'UTC',
+    ALTER COLUMN created_at TYPE TIMESTAMP USING created_at AT TIME ZONE 'UTC',
+    ALTER COLUMN updated_at TYPE TIMESTAMP USING updated_at AT TIME ZONE 'UTC';
+
+ALTER TABLE scheduled_transactions
+    ALTER COLUMN scheduled_date TYPE TIMESTAMP USING scheduled_date AT TIME ZONE 'UTC',
+    ALTER COLUMN end_date TYPE TIMESTAMP USING end_date AT TIME ZONE 'UTC';
+
+ALTER TABLE pending_transaction_approvals
+    ALTER COLUMN approved_at TYPE TIMESTAMP USING approved_at AT TIME ZONE 'UTC';
+
+ALTER TABLE operators
+    ALTER COLUMN created_at TYPE TIMESTAMP USING created_at AT TIME ZONE 'UTC';
+
+ALTER TABLE pending_transactions
+    ALTER COLUMN transaction_date TYPE TIMESTAMP USING transaction_date AT TIME ZONE 'UTC',
+    ALTER COLUMN decided_at TYPE TIMESTAMP USING decided_at AT TIME ZONE 'UTC';
+
+ALTER TABLE transactions
+    ALTER COLUMN transaction_date TYPE TIMESTAMP USING transaction_date AT TIME ZONE 'UTC';
+
+ALTER TABLE sub_accounts
+    ALTER COLUMN created_at TYPE TIMESTAMP USING created_at AT TIME ZONE 'UTC';
+
+ALTER TABLE accounts
+    ALTER COLUMN created_at TYPE TIMESTAMP USING created_at AT TIME ZONE 'UTC';
+
+ALTER TABLE scheduled_transactions
+    DROP COLUMN IF EXISTS execution_time;
+
+ALTER TABLE accounts
+    DROP COLUMN IF EXISTS time_zone;
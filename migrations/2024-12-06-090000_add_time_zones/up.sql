@@ -0,0 +1,37 @@
+-- Your SQL goes here
+
+-- IANA time zone the account's schedules run in, and the local time of day each schedule executes at
+ALTER TABLE accounts
+    ADD COLUMN time_zone VARCHAR NOT NULL DEFAULT 'UTC';
+
+ALTER TABLE scheduled_transactions
+    ADD COLUMN execution_time TIME NOT NULL DEFAULT '00:00:00';
+
+-- Existing timestamps were all written in UTC
+ALTER TABLE accounts
+    ALTER COLUMN created_at TYPE TIMESTAMPTZ USING created_at AT TIME ZONE 'UTC';
+
+ALTER TABLE sub_accounts
+    ALTER COLUMN created_at TYPE TIMESTAMPTZ USING created_at AT TIME ZONE 'UTC';
+
+ALTER TABLE transactions
+    ALTER COLUMN transaction_date TYPE TIMESTAMPTZ USING transaction_date AT TIME ZONE 'UTC';
+
+ALTER TABLE pending_transactions
+    ALTER COLUMN transaction_date TYPE TIMESTAMPTZ USING transaction_date AT TIME ZONE 'UTC',
+    ALTER COLUMN decided_at TYPE TIMESTAMPTZ USING decided_at AT TIME ZONE 'UTC';
+
+ALTER TABLE operators
+    ALTER COLUMN created_at TYPE TIMESTAMPTZ USING created_at AT TIME ZONE 'UTC';
+
+ALTER TABLE pending_transaction_approvals
+    ALTER COLUMN approved_at TYPE TIMESTAMPTZ USING approved_at AT TIME ZONE 'UTC';
+
+ALTER TABLE scheduled_transactions
+    ALTER COLUMN scheduled_date TYPE TIMESTAMPTZ USING scheduled_date AT TIME ZONE 'UTC',
+    ALTER COLUMN end_date TYPE TIMESTAMPTZ USING end_date AT TIME ZONE 'UTC';
+
+ALTER TABLE scheduled_runs
+    ALTER COLUMN occurrence_date TYPE TIMESTAMPTZ USING occurrence_date AT TIME ZONE 'UTC',
+    ALTER COLUMN created_at TYPE TIMESTAMPTZ USING created_at AT TIME ZONE 'UTC',
+    ALTER COLUMN updated_at TYPE TIMESTAMPTZ USING updated_at AT TIME ZONE 'UTC';
@@ -0,0 +1,5 @@
+-- Your SQL goes here
+
+-- How a payment due on a weekend or holiday is moved: none, following, modified_following or preceding
+ALTER TABLE scheduled_transactions
+    ADD COLUMN business_day_convention VARCHAR NOT NULL DEFAULT 'none';
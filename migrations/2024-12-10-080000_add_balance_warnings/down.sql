@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE sub_accounts
+    DROP COLUMN IF EXISTS low_balance_warned_at,
+    DROP COLUMN IF EXISTS auto_top_up_from;
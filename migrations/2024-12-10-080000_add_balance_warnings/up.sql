@@ -0,0 +1,6 @@
+-- Your SQL goes here
+
+-- Sub-account that tops this one up before scheduled payments it cannot cover, and when its owner was last warned
+ALTER TABLE sub_accounts
+    ADD COLUMN auto_top_up_from UUID REFERENCES sub_accounts(id) ON DELETE SET NULL,
+    ADD COLUMN low_balance_warned_at TIMESTAMPTZ;
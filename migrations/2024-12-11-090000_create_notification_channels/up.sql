@@ -0,0 +1,12 @@
+-- Your SQL goes here
+
+-- Where each account wants its notifications sent: telegram (chat ID), email, webhook (URL) or file (path or stdout)
+CREATE TABLE notification_channels (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    account_id UUID NOT NULL REFERENCES accounts(id) ON DELETE CASCADE,
+    channel VARCHAR NOT NULL,
+    address VARCHAR NOT NULL,
+    enabled BOOLEAN NOT NULL DEFAULT TRUE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    UNIQUE (account_id, channel, address)
+);
@@ -0,0 +1,19 @@
+-- Your SQL goes here
+
+-- Events written in the same transaction as the change they describe, delivered later by the outbox relay
+CREATE TABLE outbox (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    event_type VARCHAR NOT NULL,
+    account_id UUID REFERENCES accounts(id) ON DELETE CASCADE,
+    subject VARCHAR NOT NULL,
+    message VARCHAR NOT NULL,
+    payload JSONB NOT NULL DEFAULT '{}',
+    status VARCHAR NOT NULL DEFAULT 'pending',
+    attempt_count INTEGER NOT NULL DEFAULT 0,
+    next_attempt_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    last_error VARCHAR,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    delivered_at TIMESTAMPTZ
+);
+
+CREATE INDEX outbox_pending_idx ON outbox (next_attempt_at) WHERE status = 'pending';
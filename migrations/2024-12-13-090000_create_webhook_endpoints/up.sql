@@ -0,0 +1,27 @@
+-- Your SQL goes here
+
+-- Integrator endpoints that receive signed account events; an empty event_types list means every event
+CREATE TABLE webhook_endpoints (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    account_id UUID NOT NULL REFERENCES accounts(id) ON DELETE CASCADE,
+    url VARCHAR NOT NULL,
+    secret VARCHAR NOT NULL,
+    event_types TEXT[] NOT NULL DEFAULT '{}',
+    enabled BOOLEAN NOT NULL DEFAULT TRUE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+-- One row per delivery attempt of an outbox event to an endpoint
+CREATE TABLE webhook_deliveries (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    endpoint_id UUID NOT NULL REFERENCES webhook_endpoints(id) ON DELETE CASCADE,
+    outbox_id UUID NOT NULL REFERENCES outbox(id) ON DELETE CASCADE,
+    replay BOOLEAN NOT NULL DEFAULT FALSE,
+    succeeded BOOLEAN NOT NULL,
+    status_code INTEGER,
+    error VARCHAR,
+    duration_ms INTEGER NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX webhook_deliveries_outbox_idx ON webhook_deliveries (outbox_id, endpoint_id);
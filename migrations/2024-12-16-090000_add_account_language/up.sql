@@ -0,0 +1,4 @@
+-- Your SQL goes here
+
+-- Language the account's notifications are written in
+ALTER TABLE accounts ADD COLUMN language VARCHAR NOT NULL DEFAULT 'en';
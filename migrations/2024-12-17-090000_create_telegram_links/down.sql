@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE telegram_transfers;
+DROP TABLE telegram_link_codes;
+DROP TABLE telegram_links;
@@ -0,0 +1,29 @@
+-- Your SQL goes here
+
+-- Telegram users linked to an account through a one-time code from the CLI
+CREATE TABLE telegram_links (
+    account_id UUID PRIMARY KEY REFERENCES accounts(id) ON DELETE CASCADE,
+    telegram_user_id BIGINT NOT NULL UNIQUE,
+    chat_id BIGINT NOT NULL,
+    linked_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE TABLE telegram_link_codes (
+    code VARCHAR PRIMARY KEY,
+    account_id UUID NOT NULL REFERENCES accounts(id) ON DELETE CASCADE,
+    expires_at TIMESTAMPTZ NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+-- Transfers started in the bot, executed only once the user confirms them
+CREATE TABLE telegram_transfers (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    from_account_id UUID NOT NULL REFERENCES accounts(id) ON DELETE CASCADE,
+    to_account_id UUID NOT NULL REFERENCES accounts(id) ON DELETE CASCADE,
+    amount FLOAT8 NOT NULL,
+    currency VARCHAR NOT NULL,
+    status VARCHAR NOT NULL DEFAULT 'awaiting',
+    transaction_id UUID REFERENCES transactions(transaction_id),
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    decided_at TIMESTAMPTZ
+);
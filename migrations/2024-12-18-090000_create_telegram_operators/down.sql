@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE telegram_approval_messages;
+DROP TABLE telegram_operators;
@@ -0,0 +1,17 @@
+-- Your SQL goes here
+
+-- Telegram users allowed to approve and reject deposit requests as an operator
+CREATE TABLE telegram_operators (
+    telegram_user_id BIGINT PRIMARY KEY,
+    operator VARCHAR NOT NULL UNIQUE REFERENCES operators(username) ON DELETE CASCADE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+-- The message each pending transaction was announced with in the operator chat, closed once it shows the decision
+CREATE TABLE telegram_approval_messages (
+    pending_transaction_id UUID PRIMARY KEY REFERENCES pending_transactions(id) ON DELETE CASCADE,
+    chat_id BIGINT NOT NULL,
+    message_id INTEGER NOT NULL,
+    sent_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    closed_at TIMESTAMPTZ
+);
@@ -0,0 +1,13 @@
+-- Your SQL goes here
+
+-- Each account's address book of people it pays, looked up by nickname
+CREATE TABLE payees (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    account_id UUID NOT NULL REFERENCES accounts(id) ON DELETE CASCADE,
+    nickname VARCHAR NOT NULL,
+    payee_account_id UUID NOT NULL REFERENCES accounts(id) ON DELETE CASCADE,
+    default_currency VARCHAR,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE UNIQUE INDEX payees_account_id_nickname ON payees (account_id, LOWER(nickname));
@@ -0,0 +1,8 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE sub_accounts
+    DROP COLUMN account_number;
+
+ALTER TABLE accounts
+    DROP COLUMN account_number;
+
+DROP SEQUENCE account_number_seq;
@@ -0,0 +1,33 @@
+-- Your SQL goes here
+
+-- Serial part of account numbers, shared by accounts and sub-accounts so no two numbers are the same
+CREATE SEQUENCE account_number_seq START 1000000;
+
+ALTER TABLE accounts
+    ADD COLUMN account_number VARCHAR UNIQUE;
+
+ALTER TABLE sub_accounts
+    ADD COLUMN account_number VARCHAR UNIQUE;
+
+-- Number existing accounts the way the application does: ten digits followed by two ISO 7064 MOD 97-10 check digits
+UPDATE accounts
+SET account_number = numbered.serial || LPAD((98 - (numbered.serial::NUMERIC * 100) % 97)::TEXT, 2, '0')
+FROM (
+    SELECT id, LPAD(NEXTVAL('account_number_seq')::TEXT, 10, '0') AS serial
+    FROM (SELECT id FROM accounts ORDER BY created_at) ordered
+) numbered
+WHERE accounts.id = numbered.id;
+
+UPDATE sub_accounts
+SET account_number = numbered.serial || LPAD((98 - (numbered.serial::NUMERIC * 100) % 97)::TEXT, 2, '0')
+FROM (
+    SELECT id, LPAD(NEXTVAL('account_number_seq')::TEXT, 10, '0') AS serial
+    FROM (SELECT id FROM sub_accounts ORDER BY created_at) ordered
+) numbered
+WHERE sub_accounts.id = numbered.id;
+
+ALTER TABLE accounts
+    ALTER COLUMN account_number SET NOT NULL;
+
+ALTER TABLE sub_accounts
+    ALTER COLUMN account_number SET NOT NULL;
@@ -0,0 +1,19 @@
+-- Your SQL goes here
+
+-- One account asking another for money; paying it executes a transfer from the payer to the requester
+CREATE TABLE payment_requests (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    requester_account_id UUID NOT NULL REFERENCES accounts(id) ON DELETE CASCADE,
+    payer_account_id UUID NOT NULL REFERENCES accounts(id) ON DELETE CASCADE,
+    amount FLOAT8 NOT NULL,
+    currency VARCHAR NOT NULL,
+    memo VARCHAR,
+    status VARCHAR NOT NULL DEFAULT 'open',
+    expires_at TIMESTAMPTZ NOT NULL,
+    transaction_id UUID REFERENCES transactions(transaction_id),
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    decided_at TIMESTAMPTZ
+);
+
+CREATE INDEX payment_requests_payer_account_id ON payment_requests (payer_account_id);
+CREATE INDEX payment_requests_requester_account_id ON payment_requests (requester_account_id);
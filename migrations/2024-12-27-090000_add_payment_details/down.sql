@@ -0,0 +1,14 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE transaction_categories;
+
+ALTER TABLE scheduled_transactions
+    DROP COLUMN reference,
+    DROP COLUMN memo;
+
+ALTER TABLE pending_transactions
+    DROP COLUMN reference,
+    DROP COLUMN memo;
+
+ALTER TABLE transactions
+    DROP COLUMN reference,
+    DROP COLUMN memo;
@@ -0,0 +1,23 @@
+-- Your SQL goes here
+ALTER TABLE transactions
+    ADD COLUMN memo VARCHAR NULL,
+    ADD COLUMN reference VARCHAR NULL;
+
+ALTER TABLE pending_transactions
+    ADD COLUMN memo VARCHAR NULL,
+    ADD COLUMN reference VARCHAR NULL;
+
+ALTER TABLE scheduled_transactions
+    ADD COLUMN memo VARCHAR NULL,
+    ADD COLUMN reference VARCHAR NULL;
+
+-- Each side of a transaction files it under its own category
+CREATE TABLE transaction_categories (
+    transaction_id UUID NOT NULL REFERENCES transactions(transaction_id),
+    account_id UUID NOT NULL REFERENCES accounts(id),
+    category VARCHAR NOT NULL,
+    categorized_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (transaction_id, account_id)
+);
+
+CREATE INDEX transaction_categories_account_id_category_idx ON transaction_categories (account_id, category);
@@ -0,0 +1,5 @@
+-- Your SQL goes here
+
+-- The customer account an operator holds, if any, so they cannot approve deposits into it
+ALTER TABLE operators
+    ADD COLUMN account_id UUID NULL REFERENCES accounts(id);
@@ -0,0 +1,7 @@
+-- This file should undo anything in `up.sql`
+
+ALTER TABLE transactions
+    DROP COLUMN IF EXISTS fx_fee_sub_account_id;
+
+DELETE FROM sub_accounts WHERE account_id = '00000000-0000-0000-0000-000000000001';
+DELETE FROM accounts WHERE id = '00000000-0000-0000-0000-000000000001';
@@ -0,0 +1,11 @@
+-- Your SQL goes here
+
+-- Account that collects the FX spread charged on cross-currency payments, with a sub-account per currency
+INSERT INTO accounts (id, account_holder_name, status, account_number)
+SELECT '00000000-0000-0000-0000-000000000001', 'SYSTEM_FX_FEES', 'active',
+    serial || LPAD((98 - (serial::NUMERIC * 100) % 97)::TEXT, 2, '0')
+FROM (SELECT LPAD(NEXTVAL('account_number_seq')::TEXT, 10, '0') AS serial) numbered;
+
+-- The fee sub-account a payment's fx_fee was credited to, or, on a reversal or refund, taken back from
+ALTER TABLE transactions
+    ADD COLUMN fx_fee_sub_account_id UUID REFERENCES sub_accounts(id);
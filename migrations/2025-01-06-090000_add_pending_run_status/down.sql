@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+
+UPDATE scheduled_runs SET status = 'retrying' WHERE status = 'pending';
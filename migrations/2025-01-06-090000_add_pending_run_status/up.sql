@@ -0,0 +1,4 @@
+-- Your SQL goes here
+
+-- Runs that were created but never attempted were recorded as retrying, which held back their first attempt
+UPDATE scheduled_runs SET status = 'pending' WHERE status = 'retrying' AND attempt_count = 0;
@@ -0,0 +1,6 @@
+-- Your SQL goes here
+-- File channels may now only name a file in the operator's notification directory; stop sending to the paths
+-- registered before
+UPDATE notification_channels
+SET enabled = false
+WHERE channel = 'file' AND address !~ '^[A-Za-z0-9_-][A-Za-z0-9._-]*$';
@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+
+UPDATE outbox SET status = 'pending' WHERE status = 'in_flight';
+DROP INDEX IF EXISTS outbox_in_flight_idx;
+ALTER TABLE outbox DROP COLUMN IF EXISTS lease_expires_at;
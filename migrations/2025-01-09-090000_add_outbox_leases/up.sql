@@ -0,0 +1,7 @@
+-- Your SQL goes here
+
+-- A relay marks the messages it is sending as in flight until the lease runs out; another relay takes over
+-- messages whose lease has expired
+ALTER TABLE outbox ADD COLUMN lease_expires_at TIMESTAMPTZ;
+
+CREATE INDEX outbox_in_flight_idx ON outbox (lease_expires_at) WHERE status = 'in_flight';
@@ -0,0 +1,10 @@
+-- Your SQL goes here
+
+-- The notification channels each outbox event has reached, so a retried event skips them
+CREATE TABLE notification_deliveries (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    channel_id UUID NOT NULL REFERENCES notification_channels(id) ON DELETE CASCADE,
+    outbox_id UUID NOT NULL REFERENCES outbox(id) ON DELETE CASCADE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    UNIQUE (outbox_id, channel_id)
+);
@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+-- Which channels were disabled is not recorded, so there is nothing to undo
+SELECT 1;
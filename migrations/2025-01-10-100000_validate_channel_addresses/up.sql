@@ -0,0 +1,6 @@
+-- Your SQL goes here
+-- Telegram and email addresses are now checked when a channel is added; stop sending to ones that cannot work
+UPDATE notification_channels
+SET enabled = false
+WHERE (channel = 'telegram' AND address !~ '^-?[1-9][0-9]{0,18}$')
+   OR (channel = 'email' AND address !~ '^[^@[:space:]<>]+@[^@[:space:]<>]+$');
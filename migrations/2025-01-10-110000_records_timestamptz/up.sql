@@ -0,0 +1,5 @@
+-- Your SQL goes here
+
+-- The last timestamp column not converted with the others; records predates the migrations, so it may be missing
+ALTER TABLE IF EXISTS records
+    ALTER COLUMN created_at TYPE TIMESTAMPTZ USING created_at AT TIME ZONE 'UTC';
@@ -0,0 +1,203 @@
+use diesel::prelude::*;
+use diesel::pg::PgConnection;
+use diesel::sql_types::BigInt;
+use std::env;
+use uuid::Uuid;
+
+// Account numbers are a ten-digit serial followed by two ISO 7064 MOD 97-10 check digits, the scheme IBANs use,
+// so a mistyped digit or two swapped digits are caught before the number is looked up
+const SERIAL_LENGTH: usize = 10;
+pub const ACCOUNT_NUMBER_LENGTH: usize = SERIAL_LENGTH + 2;
+
+// Country code of IBANs, from IBAN_COUNTRY_CODE (default SG)
+pub fn iban_country_code() -> String {
+    env::var("IBAN_COUNTRY_CODE")
+        .ok()
+        .map(|code| code.trim().to_uppercase())
+        .filter(|code| code.len() == 2 && code.chars().all(|c| c.is_ascii_uppercase()))
+        .unwrap_or_else(|| "SG".to_string())
+}
+
+// Bank code between the check digits and the account number in IBANs, from IBAN_BANK_CODE (default REZE)
+pub fn iban_bank_code() -> String {
+    env::var("IBAN_BANK_CODE")
+        .ok()
+        .map(|code| code.trim().to_uppercase())
+        .filter(|code| !code.is_empty() && code.len() <= 8 && code.chars().all(|c| c.is_ascii_alphanumeric()))
+        .unwrap_or_else(|| "REZE".to_string())
+}
+
+// Remainder of a string of digits and letters divided by 97, with letters counting as 10 (A) to 35 (Z)
+fn mod97(value: &str) -> u32 {
+    value.chars().fold(0, |remainder, c| match c.to_digit(36) {
+        Some(digit) if digit >= 10 => (remainder * 100 + digit) % 97,
+        Some(digit) => (remainder * 10 + digit) % 97,
+        None => remainder,
+    })
+}
+
+// The two digits that make `base` followed by them leave a remainder of 1 when divided by 97
+pub fn check_digits(base: &str) -> String {
+    format!("{:02}", 98 - mod97(&format!("{}00", base)))
+}
+
+pub fn account_number_from_serial(serial: i64) -> String {
+    let base = format!("{:0width$}", serial, width = SERIAL_LENGTH);
+    let digits = check_digits(&base);
+    base + &digits
+}
+
+// Takes the next serial from the sequence shared by accounts and sub-accounts
+pub fn next_account_number(conn: &mut PgConnection) -> Result<String, diesel::result::Error> {
+    let serial = diesel::select(diesel::dsl::sql::<BigInt>("NEXTVAL('account_number_seq')")).get_result::<i64>(conn)?;
+    Ok(account_number_from_serial(serial))
+}
+
+// Spaces and dashes are allowed between groups of digits in account numbers, IBANs and creditor references
+pub fn compact(input: &str) -> String {
+    input.chars().filter(|c| !c.is_whitespace() && *c != '-').collect::<String>().to_uppercase()
+}
+
+pub fn is_valid_account_number(input: &str) -> bool {
+    let number = compact(input);
+    number.len() == ACCOUNT_NUMBER_LENGTH && number.chars().all(|c| c.is_ascii_digit()) && mod97(&number) == 1
+}
+
+// The account number as an IBAN in groups of four, e.g. SG12 REZE 0001 0000 0114
+pub fn format_iban(account_number: &str) -> String {
+    let country = iban_country_code();
+    let bban = format!("{}{}", iban_bank_code(), compact(account_number));
+    let iban = format!("{}{}{}", country, check_digits(&format!("{}{}", bban, country)), bban);
+    iban.chars()
+        .collect::<Vec<_>>()
+        .chunks(4)
+        .map(|group| group.iter().collect::<String>())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+pub fn is_valid_iban(input: &str) -> bool {
+    let iban = compact(input);
+    iban.len() > 4 && iban.chars().all(|c| c.is_ascii_alphanumeric()) && mod97(&format!("{}{}", &iban[4..], &iban[..4])) == 1
+}
+
+// The account number in an account number or in one of this bank's IBANs, or None if it is malformed or its
+// check digits are wrong
+pub fn parse_account_number(input: &str) -> Option<String> {
+    let compacted = compact(input);
+    if is_valid_account_number(&compacted) {
+        return Some(compacted);
+    }
+    let prefix_length = 4 + iban_bank_code().len();
+    let from_this_bank = compacted.starts_with(&iban_country_code()) && compacted.get(4..prefix_length) == Some(iban_bank_code().as_str());
+    if from_this_bank && is_valid_iban(&compacted) && is_valid_account_number(&compacted[prefix_length..]) {
+        return Some(compacted[prefix_length..].to_string());
+    }
+    None
+}
+
+// The account with the number, or the account owning the sub-account with it
+pub fn find_account_by_number(conn: &mut PgConnection, number: &str) -> Result<Uuid, diesel::result::Error> {
+    use crate::schema::{accounts, sub_accounts};
+    let account = accounts::table
+        .filter(accounts::account_number.eq(number))
+        .select(accounts::id)
+        .first::<Uuid>(conn)
+        .optional()?;
+    match account {
+        Some(account) => Ok(account),
+        None => sub_accounts::table
+            .filter(sub_accounts::account_number.eq(number))
+            .select(sub_accounts::account_id)
+            .first::<Option<Uuid>>(conn)?
+            .ok_or(diesel::result::Error::NotFound),
+    }
+}
+
+// An account given by its ID, account number or IBAN. Input that is neither a UUID nor a number with valid check
+// digits is RollbackTransaction; a valid number that belongs to no account is NotFound.
+pub fn resolve_account_id(conn: &mut PgConnection, input: &str) -> Result<Uuid, diesel::result::Error> {
+    if let Ok(id) = input.trim().parse::<Uuid>() {
+        return Ok(id);
+    }
+    match parse_account_number(input) {
+        Some(number) => find_account_by_number(conn, &number),
+        None => Err(diesel::result::Error::RollbackTransaction),
+    }
+}
+
+// Whether the input is meant as an account number or IBAN rather than a name, whether or not its check digits match
+pub fn looks_like_account_number(input: &str) -> bool {
+    let compacted = compact(input);
+    compacted.len() >= ACCOUNT_NUMBER_LENGTH && compacted.chars().filter(|c| c.is_ascii_digit()).count() >= ACCOUNT_NUMBER_LENGTH
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Published example IBANs
+    const VALID_IBANS: [&str; 4] = ["GB82 WEST 1234 5698 7654 32", "DE89 3704 0044 0532 0130 00", "NL91ABNA0417164300", "be68-5390-0754-7034"];
+
+    #[test]
+    fn compact_drops_separators_and_uppercases() {
+        assert_eq!(compact(" gb82 west-1234\t5698 "), "GB82WEST12345698");
+    }
+
+    #[test]
+    fn mod97_counts_letters_as_two_digits() {
+        assert_eq!(mod97("3214282912345698765432161182"), 1);
+        assert_eq!(mod97("WEST12345698765432GB82"), 1);
+        assert_eq!(mod97("12345678901234567890") as u64, 12345678901234567890_u64 % 97);
+    }
+
+    #[test]
+    fn check_digits_match_known_ibans() {
+        assert_eq!(check_digits("WEST12345698765432GB"), "82");
+        assert_eq!(check_digits("370400440532013000DE"), "89");
+        assert_eq!(check_digits("ABNA0417164300NL"), "91");
+        // Single digit results are zero-padded
+        assert_eq!(check_digits("0000000001"), "95");
+    }
+
+    #[test]
+    fn validates_ibans() {
+        for iban in VALID_IBANS {
+            assert!(is_valid_iban(iban), "{} should be valid", iban);
+        }
+        assert!(!is_valid_iban("GB82 WEST 1234 5698 7654 33"));
+        assert!(!is_valid_iban("GB28 WEST 1234 5698 7654 32"));
+        assert!(!is_valid_iban("GB82 WEST 1234 5698 7654 3!"));
+        assert!(!is_valid_iban("GB82"));
+    }
+
+    #[test]
+    fn account_numbers_carry_check_digits() {
+        let number = account_number_from_serial(1);
+        assert_eq!(number, "000000000195");
+        assert!(is_valid_account_number("0000-0000-0195"));
+        assert!(!is_valid_account_number("000000000159"));
+        assert!(!is_valid_account_number("000000001095"));
+        assert!(!is_valid_account_number("00000000195"));
+        assert_eq!(account_number_from_serial(1_234_567_890).len(), ACCOUNT_NUMBER_LENGTH);
+        assert!(is_valid_account_number(&account_number_from_serial(1_234_567_890)));
+    }
+
+    #[test]
+    fn parses_account_numbers_and_this_banks_ibans() {
+        let number = account_number_from_serial(114);
+        let iban = format_iban(&number);
+        assert!(is_valid_iban(&iban));
+        assert_eq!(iban.split(' ').next().map(str::len), Some(4));
+        assert_eq!(parse_account_number(&number), Some(number.clone()));
+        assert_eq!(parse_account_number(&iban.to_lowercase()), Some(number.clone()));
+
+        // Another bank's IBAN, a wrong check digit or a name are not account numbers
+        assert_eq!(parse_account_number(VALID_IBANS[0]), None);
+        let mut tampered = iban.clone();
+        tampered.pop();
+        tampered.push(if iban.ends_with('0') { '1' } else { '0' });
+        assert_eq!(parse_account_number(&tampered), None);
+        assert_eq!(parse_account_number("alice"), None);
+    }
+}
@@ -0,0 +1,159 @@
+use diesel::prelude::*;
+use diesel::pg::PgConnection;
+use uuid::Uuid;
+use crate::models::{ApprovalThreshold, PendingTransaction, PendingTransactionApproval, NewPendingTransactionApproval, Operator, PENDING_STATUS_PENDING};
+use crate::database::{validate_operator, count_operators};
+
+pub fn get_approval_thresholds(
+    conn: &mut PgConnection
+) -> Result<Vec<ApprovalThreshold>, diesel::result::Error> {
+    use crate::schema::approval_thresholds::dsl::*;
+    approval_thresholds.order(currency.asc()).load::<ApprovalThreshold>(conn)
+}
+
+// A second operator signing off a change, identified by their own credentials
+pub struct CoSigner<'a> {
+    pub username: &'a str,
+    pub password: &'a str,
+}
+
+pub fn is_operator(conn: &mut PgConnection, name: &str) -> Result<bool, diesel::result::Error> {
+    use crate::schema::operators::dsl::*;
+    Ok(operators.find(name).first::<Operator>(conn).optional()?.is_some())
+}
+
+// Changes to operators and thresholds need the operator making them and a second operator who confirms with
+// their own password. Until two operators exist there is nobody to confirm, so the first ones can be added alone.
+pub fn check_second_operator(
+    conn: &mut PgConnection,
+    operator: &str,
+    co_signer: Option<&CoSigner>
+) -> Result<(), diesel::result::Error> {
+    if count_operators(conn)? < 2 {
+        return Ok(());
+    }
+    match co_signer {
+        Some(co_signer) if is_operator(conn, operator)? && co_signer.username != operator
+            && validate_operator(conn, co_signer.username, co_signer.password) => Ok(()),
+        _ => Err(diesel::result::Error::RollbackTransaction),
+    }
+}
+
+pub fn set_approval_threshold(
+    conn: &mut PgConnection,
+    operator: &str,
+    co_signer: Option<&CoSigner>,
+    currency_to_set: &str,
+    amount_to_set: f64
+) -> Result<ApprovalThreshold, diesel::result::Error> {
+    check_second_operator(conn, operator, co_signer)?;
+    use crate::schema::approval_thresholds::dsl::*;
+    let threshold = ApprovalThreshold {
+        currency: currency_to_set.to_string(),
+        amount: amount_to_set,
+    };
+
+    diesel::insert_into(approval_thresholds)
+        .values(&threshold)
+        .on_conflict(currency)
+        .do_update()
+        .set(amount.eq(amount_to_set))
+        .returning(ApprovalThreshold::as_returning())
+        .get_result(conn)
+}
+
+// Amounts at or above the currency's threshold need a second operator, anything else needs one
+pub fn required_approvals(
+    conn: &mut PgConnection,
+    currency_to_check: &str,
+    amount_to_check: f64
+) -> Result<usize, diesel::result::Error> {
+    use crate::schema::approval_thresholds::dsl::*;
+    let threshold = approval_thresholds
+        .find(currency_to_check)
+        .first::<ApprovalThreshold>(conn)
+        .optional()?;
+
+    match threshold {
+        Some(threshold) if amount_to_check >= threshold.amount => Ok(2),
+        _ => Ok(1),
+    }
+}
+
+// An operator may not approve an adjustment they raised or a deposit into the account they hold
+pub fn is_initiator(
+    conn: &mut PgConnection,
+    pending_transaction: &PendingTransaction,
+    reviewer: &str
+) -> Result<bool, diesel::result::Error> {
+    use crate::schema::operators::dsl::*;
+    if pending_transaction.initiated_by.as_deref() == Some(reviewer) {
+        return Ok(true);
+    }
+
+    let reviewer_operator = operators
+        .find(reviewer)
+        .first::<Operator>(conn)
+        .optional()?;
+
+    Ok(match reviewer_operator {
+        Some(operator) => operator.account_id.is_some() && operator.account_id == pending_transaction.account_id_to_add,
+        None => false,
+    })
+}
+
+pub fn record_approval(
+    conn: &mut PgConnection,
+    pending_transaction_id_to_approve: Uuid,
+    reviewer: &str
+) -> Result<PendingTransactionApproval, diesel::result::Error> {
+    use crate::schema::pending_transaction_approvals::dsl::*;
+    let new_approval = NewPendingTransactionApproval {
+        pending_transaction_id: pending_transaction_id_to_approve,
+        approved_by: reviewer,
+    };
+
+    // The same operator approving twice must not count as a second pair of eyes
+    match diesel::insert_into(pending_transaction_approvals)
+        .values(&new_approval)
+        .returning(PendingTransactionApproval::as_returning())
+        .get_result(conn)
+    {
+        Ok(approval) => Ok(approval),
+        Err(diesel::result::Error::DatabaseError(diesel::result::DatabaseErrorKind::UniqueViolation, _)) => {
+            Err(diesel::result::Error::RollbackTransaction)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+pub fn get_approvals(
+    conn: &mut PgConnection,
+    pending_transaction_id_to_view: Uuid
+) -> Result<Vec<PendingTransactionApproval>, diesel::result::Error> {
+    use crate::schema::pending_transaction_approvals::dsl::*;
+    pending_transaction_approvals
+        .filter(pending_transaction_id.eq(pending_transaction_id_to_view))
+        .order(approved_at.asc())
+        .load::<PendingTransactionApproval>(conn)
+}
+
+// Pending items that already have one approval recorded and are waiting on a second operator
+pub fn get_awaiting_second_approval(
+    conn: &mut PgConnection
+) -> Result<Vec<(PendingTransaction, Vec<PendingTransactionApproval>)>, diesel::result::Error> {
+    use crate::schema::pending_transactions::dsl::*;
+    let pending = pending_transactions
+        .filter(status.eq(PENDING_STATUS_PENDING))
+        .order(transaction_date.asc())
+        .load::<PendingTransaction>(conn)?;
+
+    let mut queue = Vec::new();
+    for pending_transaction in pending {
+        let approvals = get_approvals(conn, pending_transaction.id)?;
+        if !approvals.is_empty() {
+            queue.push((pending_transaction, approvals));
+        }
+    }
+    Ok(queue)
+}
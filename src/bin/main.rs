@@ -1,11 +1,30 @@
 use dotenvy::dotenv;
-use rust_eze::database::{establish_connection, create_account, create_sub_account, get_accounts, add_username_password, validate_username_password, get_scheduled_transactions, add_scheduled_transaction, delete_scheduled_transaction, view_scheduled_transactions};
-use rust_eze::moneytransfer::{transfer_between_sub_accounts, get_balance, transfer_money, get_transactions, add_money_to_sub_account, approve_pending_transaction, get_pending_transactions};
+use rust_eze::database::{establish_connection, get_account_holder_name, get_account, get_sub_accounts, validate_operator, count_operators, add_operator, create_account, create_sub_account, get_accounts, add_username_password, validate_username_password, get_scheduled_transactions, add_scheduled_transaction, delete_scheduled_transaction, view_scheduled_transactions, get_scheduled_runs, pause_scheduled_transaction, resume_scheduled_transaction, skip_next_scheduled_occurrence, update_scheduled_transaction, ScheduleUpdate, set_auto_top_up};
+use rust_eze::moneytransfer::{approve_pending_transaction, reject_pending_transaction, transfer_between_sub_accounts, get_balance, transfer_money, add_money_to_sub_account, get_pending_transactions, reverse_transaction, refund_transaction, cancel_pending_transaction, get_pending_transaction_history, get_account_pending_transactions, request_admin_adjustment, can_receive_currency, transaction_direction, MissingSubAccountPolicy, quote_cross_currency_payment, transfer_money_cross_currency, fx_spread, FxSpreadBearer};
+use rust_eze::approvals::{get_approval_thresholds, set_approval_threshold, get_awaiting_second_approval, CoSigner};
+use rust_eze::models::{ScheduledTransaction, PENDING_STATUS_PENDING, SCHEDULE_KIND_ONE_OFF, SCHEDULE_STATUS_PAUSED};
+use rust_eze::models::EVENT_DEPOSIT_REQUESTED;
 use clap::{Parser, Subcommand};
 use regex::Regex;
 use std::io::{self, Write};
 use uuid::Uuid;
-use chrono::NaiveDateTime;
+use chrono::{DateTime, NaiveDate, NaiveDateTime, NaiveTime, Utc};
+use chrono_tz::Tz;
+use rust_eze::timezones::{account_time_zone, set_account_time_zone, local_to_utc};
+use rust_eze::calendars::{BusinessDayConvention, HolidayCalendar};
+use rust_eze::notifications::{get_notification_channels, add_notification_channel, remove_notification_channel, set_notification_channel_enabled};
+use rust_eze::recurringpayments::{Recurrence, ScheduleOptions, CatchUpPolicy, upcoming_occurrences};
+use rust_eze::outbox::{enqueue_event, enqueue_payment_events, deposit_payload, get_stuck_outbox_messages, requeue_outbox_message};
+use rust_eze::webhooks::{get_webhook_endpoints, add_webhook_endpoint, remove_webhook_endpoint, get_webhook_deliveries, get_account_events, replay_webhook_event, is_valid_event_type, WebhookSender};
+use rust_eze::models::EVENT_TYPES;
+use rust_eze::templates::{account_language, set_account_language};
+use rust_eze::accountnumbers::{resolve_account_id, format_iban};
+use rust_eze::payees::{get_payees, add_payee, remove_payee, resolve_recipient, has_paid_before, Recipient};
+use rust_eze::paymentrequests::{create_payment_request, PaymentRequestTerms, get_incoming_payment_requests, get_outgoing_payment_requests, accept_payment_request, decline_payment_request, cancel_payment_request, payment_request_expiry_days, is_expired};
+use rust_eze::models::PaymentRequest;
+use rust_eze::transactiondetails::{PaymentDetails, MAX_MEMO_LENGTH, TransactionFilter, search_transactions, set_transaction_category, get_used_categories};
+use rust_eze::telegram::{create_telegram_link_code, get_telegram_link, unlink_telegram, LINK_CODE_TTL_MINUTES, get_telegram_operators, map_telegram_operator, unmap_telegram_operator};
+use diesel::Connection;
 #[derive(Parser)]
 #[command(name = "Account Manager")]
 #[command(about = "A CLI to create accounts and sub-accounts", long_about = None)]
@@ -41,6 +60,7 @@ fn validate_account_name(name: &str) -> bool {
     re.is_match(name)
 }
 
+#[allow(clippy::useless_vec, clippy::collapsible_if)]
 fn validate_currency(currency: &str) -> bool {
     let re = Regex::new(r"^[A-Z]{3}$").unwrap();
     let valid_currencies = vec!["USD", "EUR", "GBP", "JPY", "INR", "SGD", "EUR", "AUD"];
@@ -56,6 +76,7 @@ fn validate_amount(amount: f64) -> bool {
     amount > 0.0
 }
 
+#[allow(clippy::needless_borrow)]
 fn create_account_flow(conn: &mut diesel::PgConnection) {
     // Account creation flow
     let mut account_name = String::new();
@@ -70,6 +91,7 @@ fn create_account_flow(conn: &mut diesel::PgConnection) {
         match create_account(conn, account_name) {
             Ok(account) => {
                 let account_id = account.id;
+                println!("Your account number is {} (IBAN {})", account.account_number, format_iban(&account.account_number));
                 let mut username = String::new();
                 print!("Enter your username: ");
                 io::stdout().flush().unwrap();
@@ -85,6 +107,7 @@ fn create_account_flow(conn: &mut diesel::PgConnection) {
                     Ok(_) => println!("Username and password added"),
                     Err(e) => println!("Failed to add username and password: {:?}", e),
                 }
+                set_time_zone_flow(conn, account_id);
             }
             Err(e) => println!("Failed to create account: {:?}", e),    
         }
@@ -123,28 +146,104 @@ fn create_sub_account_flow(conn: &mut diesel::PgConnection, subaccount_insert_ac
     }
 }
 
-pub fn transfer_money_to_someone_else_flow(conn: &mut diesel::PgConnection, from_account_id: Uuid) {
-    // Transfer money to someone else flow
-    let mut to_account_id = String::new();
-    let mut amount = String::new();
-    let mut currency = String::new();
+// Asks who to pay, by nickname, username or account ID. The holder's name is shown, and a payment to someone
+// the account has never paid before must be confirmed.
+fn read_recipient(conn: &mut diesel::PgConnection, from_account_id: Uuid) -> Option<Recipient> {
+    let mut input = String::new();
+    print!("Enter the recipient (payee nickname, username, account number or account ID): ");
+    io::stdout().flush().unwrap();
+    io::stdin().read_line(&mut input).unwrap();
+    let recipient = match resolve_recipient(conn, from_account_id, &input) {
+        Ok(recipient) => recipient,
+        Err(diesel::result::Error::NotFound) => {
+            println!("No payee, user or account matches {:?}.", input.trim());
+            return None;
+        }
+        Err(diesel::result::Error::RollbackTransaction) => {
+            println!("{:?} is not a valid account number. Check it for typos.", input.trim());
+            return None;
+        }
+        Err(e) => {
+            println!("Failed to look up the recipient: {:?}", e);
+            return None;
+        }
+    };
+    if recipient.account_id == from_account_id {
+        println!("Use \"Transfer between sub-accounts\" to move money between your own sub-accounts.");
+        return None;
+    }
+
+    match has_paid_before(conn, from_account_id, recipient.account_id) {
+        Ok(true) => println!("Paying {} ({})", recipient.holder_name, recipient.account_id),
+        Ok(false) => {
+            println!("You have not paid {} ({}) before.", recipient.holder_name, recipient.account_id);
+            print!("Is this the right recipient? (y/n): ");
+            io::stdout().flush().unwrap();
+            let mut confirm = String::new();
+            io::stdin().read_line(&mut confirm).unwrap();
+            if !confirm.trim().eq_ignore_ascii_case("y") {
+                println!("Payment cancelled.");
+                return None;
+            }
+        }
+        Err(e) => {
+            println!("Failed to check your payment history: {:?}", e);
+            return None;
+        }
+    }
+    Some(recipient)
+}
 
-    // Get to account ID input
-    print!("Enter the recipient's account ID: ");
+// An optional memo and payment reference; None if either is unusable
+fn read_payment_details() -> Option<PaymentDetails> {
+    let mut memo = String::new();
+    print!("Enter a memo (press Enter to skip): ");
     io::stdout().flush().unwrap();
-    io::stdin().read_line(&mut to_account_id).unwrap();
-    let to_account_id: Uuid = match to_account_id.trim().parse() {
-        Ok(id) => id,
+    io::stdin().read_line(&mut memo).unwrap();
+
+    let mut reference = String::new();
+    print!("Enter a payment reference, e.g. an invoice number (press Enter to skip): ");
+    io::stdout().flush().unwrap();
+    io::stdin().read_line(&mut reference).unwrap();
+
+    match PaymentDetails::new(Some(&memo), Some(&reference)) {
+        Ok(details) => {
+            if let Some(reference) = details.reference() {
+                println!("Payment reference: {}", reference);
+            }
+            Some(details)
+        }
         Err(_) => {
-            println!("Invalid account ID. Please enter a valid number.");
-            return;
+            println!("Memos can be up to {} characters. References are RF creditor references, or up to 21 letters and digits.", MAX_MEMO_LENGTH);
+            None
         }
-    };
-    // Get currency input
-    print!("Enter the currency to transfer: ");
+    }
+}
+
+// The currency to pay in, defaulting to the payee's
+fn read_payment_currency(recipient: &Recipient) -> String {
+    let mut currency = String::new();
+    match &recipient.default_currency {
+        Some(default_currency) => print!("Enter the currency to transfer (press Enter for {}): ", default_currency),
+        None => print!("Enter the currency to transfer: "),
+    }
     io::stdout().flush().unwrap();
     io::stdin().read_line(&mut currency).unwrap();
-    let currency = currency.trim();
+    match (currency.trim(), &recipient.default_currency) {
+        ("", Some(default_currency)) => default_currency.clone(),
+        (currency, _) => currency.to_string(),
+    }
+}
+
+pub fn transfer_money_to_someone_else_flow(conn: &mut diesel::PgConnection, from_account_id: Uuid) {
+    // Transfer money to someone else flow
+    let mut amount = String::new();
+
+    let Some(recipient) = read_recipient(conn, from_account_id) else { return };
+    let to_account_id = recipient.account_id;
+    // Get currency input
+    let currency = read_payment_currency(&recipient);
+    let currency = currency.as_str();
 
     if !validate_currency(currency) {
         println!("Invalid currency format. Please enter a valid currency code (e.g., USD, EUR).");
@@ -168,9 +267,83 @@ pub fn transfer_money_to_someone_else_flow(conn: &mut diesel::PgConnection, from
         return;
     }
 
+    let Some(details) = read_payment_details() else { return };
+
+    // Get the currency the recipient should receive
+    let mut receive_currency = String::new();
+    print!("Enter the currency the recipient should receive (press Enter for {}): ", currency);
+    io::stdout().flush().unwrap();
+    io::stdin().read_line(&mut receive_currency).unwrap();
+    let receive_currency = receive_currency.trim();
+
+    if !receive_currency.is_empty() && receive_currency != currency {
+        cross_currency_transfer_flow(conn, from_account_id, to_account_id, currency, receive_currency, amount, &details);
+        return;
+    }
+
+    if !check_can_receive(conn, to_account_id, currency, "The recipient has") {
+        return;
+    }
+
     println!("Transferring {} {} to account {} from account {}", amount, currency, to_account_id, from_account_id);
 
-    match transfer_money(conn, from_account_id, to_account_id, amount, currency) {
+    let result = conn.transaction(|conn| {
+        let transaction = transfer_money(conn, from_account_id, to_account_id, amount, currency, &details, MissingSubAccountPolicy::from_env())?;
+        enqueue_payment_events(conn, &transaction, from_account_id, to_account_id)?;
+        Ok::<_, diesel::result::Error>(transaction)
+    });
+    match result {
+        Ok(transaction) => println!("Transaction successful: {:#?}", transaction),
+        Err(e) => println!("Transaction failed: {:?}", e),
+    }
+}
+
+fn cross_currency_transfer_flow(conn: &mut diesel::PgConnection, from_account_id: Uuid, to_account_id: Uuid, send_currency: &str, receive_currency: &str, amount: f64, details: &PaymentDetails) {
+    if !validate_currency(receive_currency) {
+        println!("Invalid currency format. Please enter a valid currency code (e.g., USD, EUR).");
+        return;
+    }
+
+    let mut bearer = String::new();
+    println!("Who should pay the {}% FX spread?", fx_spread() * 100.0);
+    println!("1. Me (the recipient receives the full converted amount)");
+    println!("2. The recipient (deducted from the converted amount)");
+    io::stdin().read_line(&mut bearer).unwrap();
+    let bearer = match bearer.trim() {
+        "1" => FxSpreadBearer::Sender,
+        "2" => FxSpreadBearer::Recipient,
+        _ => {
+            println!("Invalid choice.");
+            return;
+        }
+    };
+
+    let quote = match quote_cross_currency_payment(send_currency, receive_currency, amount, bearer) {
+        Ok(quote) => quote,
+        Err(e) => {
+            println!("Failed to get an exchange rate: {:?}", e);
+            return;
+        }
+    };
+
+    println!("You will be charged {:.2} {} (including an FX fee of {:.2} {} paid by the {})",
+        quote.amount_debited, quote.send_currency, quote.fx_fee, quote.send_currency, quote.bearer.as_str());
+    println!("The recipient will receive {:.2} {} (rate {:.6})", quote.amount_received, quote.receive_currency, quote.mid_rate);
+    print!("Confirm payment? (y/n): ");
+    io::stdout().flush().unwrap();
+    let mut confirm = String::new();
+    io::stdin().read_line(&mut confirm).unwrap();
+    if !confirm.trim().eq_ignore_ascii_case("y") {
+        println!("Payment cancelled.");
+        return;
+    }
+
+    let result = conn.transaction(|conn| {
+        let transaction = transfer_money_cross_currency(conn, from_account_id, to_account_id, &quote, details, MissingSubAccountPolicy::from_env())?;
+        enqueue_payment_events(conn, &transaction, from_account_id, to_account_id)?;
+        Ok::<_, diesel::result::Error>(transaction)
+    });
+    match result {
         Ok(transaction) => println!("Transaction successful: {:#?}", transaction),
         Err(e) => println!("Transaction failed: {:?}", e),
     }
@@ -248,14 +421,109 @@ fn get_balance_flow(conn: &mut diesel::PgConnection, from_account_id: Uuid) {
     }
 }
 
+// Reads an optional local date; the outer None means the input was not a date
+fn read_optional_date(prompt: &str) -> Option<Option<NaiveDate>> {
+    let mut date = String::new();
+    print!("{} (YYYY-MM-DD, press Enter to skip): ", prompt);
+    io::stdout().flush().unwrap();
+    io::stdin().read_line(&mut date).unwrap();
+    match date.trim() {
+        "" => Some(None),
+        date => match NaiveDate::parse_from_str(date, "%Y-%m-%d") {
+            Ok(date) => Some(Some(date)),
+            Err(_) => {
+                println!("Invalid date format. Please use YYYY-MM-DD.");
+                None
+            }
+        },
+    }
+}
+
+fn read_optional(prompt: &str) -> Option<String> {
+    let mut value = String::new();
+    print!("{} (press Enter to skip): ", prompt);
+    io::stdout().flush().unwrap();
+    io::stdin().read_line(&mut value).unwrap();
+    Some(value.trim().to_string()).filter(|v| !v.is_empty())
+}
+
 fn get_transactions_flow(conn: &mut diesel::PgConnection, account_id: Uuid) {
+    let text = read_optional("Search memos and references for");
+    let category = read_optional("Only show the category");
+    let currency = read_optional("Only show the currency");
+    let Some(from_date) = read_optional_date("From date") else { return };
+    let Some(to_date) = read_optional_date("To date") else { return };
+
+    // Dates are whole days in the account's time zone
+    let tz = account_time_zone(conn, account_id).unwrap_or(Tz::UTC);
+    let filter = TransactionFilter {
+        text,
+        category,
+        currency,
+        from_date: from_date.map(|date| local_to_utc(tz, date, NaiveTime::MIN)),
+        to_date: to_date.and_then(|date| date.succ_opt()).map(|date| local_to_utc(tz, date, NaiveTime::MIN)),
+    };
 
-    match get_transactions(conn, account_id) {
-        Ok(transactions) => println!("Transactions: {:#?}", transactions),
+    let own_subs: Vec<Uuid> = get_sub_accounts(conn, account_id).unwrap_or_default().iter().map(|sub| sub.id).collect();
+    match search_transactions(conn, account_id, &filter) {
+        Ok(entries) if entries.is_empty() => println!("No transactions match."),
+        Ok(entries) => {
+            for entry in entries {
+                let transaction = &entry.transaction;
+                let direction = transaction_direction(transaction, &own_subs);
+                let mut line = format!("  {} [{}] {} {} {}", format_local(conn, account_id, transaction.transaction_date),
+                    transaction.transaction_id, direction, transaction.amount, transaction.transfer_currency);
+                if let Some(memo) = &transaction.memo {
+                    line += &format!(" {:?}", memo);
+                }
+                if let Some(reference) = &transaction.reference {
+                    line += &format!(" ref {}", reference);
+                }
+                if let Some(category) = &entry.category {
+                    line += &format!(" #{}", category);
+                }
+                println!("{}", line);
+            }
+        }
         Err(e) => println!("Failed to get transactions: {:?}", e),
     }
 }
 
+pub fn categorize_transaction_flow(conn: &mut diesel::PgConnection, account_id: Uuid) {
+    match get_used_categories(conn, account_id) {
+        Ok(categories) if !categories.is_empty() => println!("Your categories: {}", categories.join(", ")),
+        Ok(_) => println!("Categories are free text, e.g. rent or groceries."),
+        Err(e) => println!("Failed to get your categories: {:?}", e),
+    }
+
+    let mut transaction_id = String::new();
+    print!("Enter the transaction ID: ");
+    io::stdout().flush().unwrap();
+    io::stdin().read_line(&mut transaction_id).unwrap();
+    let transaction_id = match transaction_id.trim().parse::<Uuid>() {
+        Ok(id) => id,
+        Err(_) => {
+            println!("Invalid transaction ID.");
+            return;
+        }
+    };
+
+    let mut category = String::new();
+    print!("Enter the category (press Enter to remove it): ");
+    io::stdout().flush().unwrap();
+    io::stdin().read_line(&mut category).unwrap();
+
+    match set_transaction_category(conn, account_id, transaction_id, Some(&category)) {
+        Ok(Some(category)) => println!("Transaction filed under {}", category),
+        Ok(None) => println!("Category removed"),
+        Err(diesel::result::Error::NotFound) => println!("None of your transactions has that ID."),
+        Err(diesel::result::Error::RollbackTransaction) => {
+            println!("Categories can be up to 32 letters, digits, spaces, dashes and ampersands.")
+        }
+        Err(e) => println!("Failed to set the category: {:?}", e),
+    }
+}
+
 pub fn get_accounts_flow(conn: &mut diesel::PgConnection) {
     match get_accounts(conn) {
         Ok(accounts) => println!("Accounts: {:#?}", accounts),
@@ -263,6 +531,7 @@ pub fn get_accounts_flow(conn: &mut diesel::PgConnection) {
     }
 }
 
+#[allow(clippy::redundant_pattern_matching)]
 pub fn validate_account_id(account_id: Uuid, conn: &mut diesel::PgConnection) -> bool {
     use diesel::prelude::*;
     use rust_eze::schema::accounts::dsl::*;
@@ -281,28 +550,64 @@ pub fn get_pending_transactions_flow(conn: &mut diesel::PgConnection) {
     }
 }
 
+// Shows an instant in the paying account's time zone
+fn format_local(conn: &mut diesel::PgConnection, account_id: Uuid, instant: DateTime<Utc>) -> String {
+    let tz = account_time_zone(conn, account_id).unwrap_or(Tz::UTC);
+    instant.with_timezone(&tz).format("%Y-%m-%d %H:%M %Z").to_string()
+}
+
+// Lists one-off payments and recurring standing orders separately
+fn print_scheduled_transactions(conn: &mut diesel::PgConnection, scheduled_transactions: &[ScheduledTransaction]) {
+    let (one_off, recurring): (Vec<&ScheduledTransaction>, Vec<&ScheduledTransaction>) = scheduled_transactions
+        .iter()
+        .partition(|t| t.kind == SCHEDULE_KIND_ONE_OFF);
+
+    println!("One-off payments:");
+    for transaction in one_off {
+        let state = if transaction.executed {
+            "executed"
+        } else if transaction.status == SCHEDULE_STATUS_PAUSED {
+            "paused"
+        } else {
+            "upcoming"
+        };
+        println!("  [{}] {} {} to {} on {} ({})", transaction.id, transaction.amount, transaction.currency,
+            transaction.to_account_id, format_local(conn, transaction.from_account_id, transaction.scheduled_date), state);
+    }
+
+    println!("Standing orders:");
+    for transaction in recurring {
+        let next = format_local(conn, transaction.from_account_id, transaction.scheduled_date);
+        let state = if transaction.executed {
+            "finished".to_string()
+        } else if transaction.status == SCHEDULE_STATUS_PAUSED && transaction.consecutive_failures > 0 {
+            format!("paused after {} failed payments", transaction.consecutive_failures)
+        } else if transaction.status == SCHEDULE_STATUS_PAUSED {
+            format!("paused, next on {}", next)
+        } else {
+            format!("next on {}", next)
+        };
+        println!("  [{}] {} {} to {} {} ({}, {} paid so far)", transaction.id, transaction.amount, transaction.currency,
+            transaction.to_account_id, transaction.recurrence.as_deref().unwrap_or(""), state, transaction.occurrences_executed);
+    }
+}
+
 pub fn get_scheduled_transactions_flow(conn: &mut diesel::PgConnection) {
     match get_scheduled_transactions(conn) {
-        Ok(scheduled_transactions) => println!("Scheduled transactions: {:#?}", scheduled_transactions),
+        Ok(scheduled_transactions) => print_scheduled_transactions(conn, &scheduled_transactions),
         Err(e) => println!("Failed to get scheduled transactions: {:?}", e),
     }
 }
 
+#[allow(clippy::needless_borrow)]
 pub fn add_scheduled_transaction_flow(conn: &mut diesel::PgConnection, from_account_id_temp: Uuid) {
     // Add a scheduled transaction flow
-    let mut to_account_id_temp = String::new();
     let mut amount_temp = String::new();
-    let mut currency_temp = String::new();
     let mut scheduled_date_temp = String::new();
 
     // Collect user input (this is just a placeholder; replace with actual input logic)
-    println!("Enter the to account ID:");
-    std::io::stdin().read_line(&mut to_account_id_temp).unwrap();
-    let to_account_id = Uuid::parse_str(to_account_id_temp.trim()).expect("Invalid UUID format");
-    if !validate_account_id(to_account_id, conn) {
-        println!("Invalid account ID. Please enter a valid number.");
-        return;
-    }
+    let Some(recipient) = read_recipient(conn, from_account_id_temp) else { return };
+    let to_account_id = recipient.account_id;
 
     println!("Enter the amount:");
     std::io::stdin().read_line(&mut amount_temp).unwrap();
@@ -312,20 +617,18 @@ pub fn add_scheduled_transaction_flow(conn: &mut diesel::PgConnection, from_acco
         return;
     }
 
-    println!("Enter the currency:");
-    std::io::stdin().read_line(&mut currency_temp).unwrap();
-    let currency = currency_temp.trim().to_string();
+    let currency = read_payment_currency(&recipient);
     if !validate_currency(&currency) {
         println!("Invalid currency format. Please enter a valid currency code (e.g., USD, EUR).");
         return;
     }
 
-    println!("Enter the scheduled date (YYYY-MM-DD HH:MM:SS format, e.g., 2024-11-21 15:30:00):");
+    println!("Enter the scheduled date and the local time to pay at (YYYY-MM-DD HH:MM:SS format, e.g., 2024-11-21 15:30:00):");
     std::io::stdin().read_line(&mut scheduled_date_temp).unwrap();
     let scheduled_date = match NaiveDateTime::parse_from_str(&scheduled_date_temp.trim(), "%Y-%m-%d %H:%M:%S") {
         Ok(date) => date,
         Err(_) => {
-            println!("Invalid date format. Please use YYYY-MM-DD HH:MM:SS format (your time doesnt matter, we will set it to 00:00:00) (e.g., 2024-11-21 00:00:00)");
+            println!("Invalid date format. Please use YYYY-MM-DD HH:MM:SS format in your account's time zone (e.g., 2024-11-21 09:00:00)");
             return;
         }
     };
@@ -338,12 +641,155 @@ pub fn add_scheduled_transaction_flow(conn: &mut diesel::PgConnection, from_acco
     //     return;
     // }
     
+    let mut options = match read_schedule_options(scheduled_date.date(), &currency) {
+        Some(options) => options,
+        None => return,
+    };
+    let Some(details) = read_payment_details() else { return };
+    options.details = details;
+
     // Insert the new scheduled transaction into the database
-    match add_scheduled_transaction(conn, from_account_id_temp, to_account_id, amount, &currency, scheduled_date) {
+    match add_scheduled_transaction(conn, from_account_id_temp, to_account_id, amount, &currency, scheduled_date, &options) {
         Ok(_) => println!("Scheduled transaction added"),
         Err(e) => println!("Failed to add scheduled transaction: {:?}", e),
     }
 }
+
+// Asks whether the payment is one-off or recurring, how it repeats and when it ends, then shows the
+// upcoming payment dates for confirmation
+fn read_schedule_options(start: NaiveDate, currency: &str) -> Option<ScheduleOptions> {
+    let mut kind = String::new();
+    println!("1. One-off payment");
+    println!("2. Recurring standing order");
+    std::io::stdin().read_line(&mut kind).unwrap();
+    let mut options = match kind.trim() {
+        "1" => ScheduleOptions::one_off(),
+        "2" => read_recurrence_options(start)?,
+        _ => {
+            println!("Invalid choice.");
+            return None;
+        }
+    };
+
+    let mut catch_up = String::new();
+    println!("If payments are missed because the scheduler was not running:");
+    println!("1. Execute every missed payment (default)");
+    println!("2. Execute only the most recent missed payment");
+    println!("3. Skip missed payments and notify me");
+    std::io::stdin().read_line(&mut catch_up).unwrap();
+    options.catch_up_policy = match catch_up.trim() {
+        "" | "1" => CatchUpPolicy::ExecuteAll,
+        "2" => CatchUpPolicy::LatestOnly,
+        "3" => CatchUpPolicy::SkipAndNotify,
+        _ => {
+            println!("Invalid choice.");
+            return None;
+        }
+    };
+
+    let mut convention = String::new();
+    println!("If a payment falls on a weekend or {} bank holiday:", currency);
+    println!("1. Pay on that day anyway (default)");
+    println!("2. Pay on the next business day");
+    println!("3. Pay on the next business day, or the previous one if that is in the next month");
+    println!("4. Pay on the previous business day");
+    std::io::stdin().read_line(&mut convention).unwrap();
+    options.business_day_convention = match convention.trim() {
+        "" | "1" => BusinessDayConvention::Unadjusted,
+        "2" => BusinessDayConvention::Following,
+        "3" => BusinessDayConvention::ModifiedFollowing,
+        "4" => BusinessDayConvention::Preceding,
+        _ => {
+            println!("Invalid choice.");
+            return None;
+        }
+    };
+    let calendar = match HolidayCalendar::for_currency(currency) {
+        Ok(calendar) => calendar,
+        Err(e) => {
+            println!("Failed to load the holiday calendar: {}", e);
+            return None;
+        }
+    };
+    // Show the date each payment is actually made on, and the date it was moved from
+    let format_occurrence = |occurrence: NaiveDate| {
+        let paid_on = calendar.adjust(occurrence, options.business_day_convention);
+        if paid_on == occurrence {
+            occurrence.format("%a %Y-%m-%d").to_string()
+        } else {
+            format!("{} (moved from {})", paid_on.format("%a %Y-%m-%d"), occurrence.format("%a %Y-%m-%d"))
+        }
+    };
+
+    match &options.recurrence {
+        Some(recurrence) => {
+            let occurrences = upcoming_occurrences(recurrence, start, options.end_date, options.occurrence_count, 5);
+            println!("Next payments ({}):", recurrence.to_rrule());
+            for occurrence in occurrences {
+                println!("  {}", format_occurrence(occurrence));
+            }
+        }
+        None => println!("Single payment on {}", format_occurrence(start)),
+    }
+    // Holidays past the end of the calendar are not known, so later payments could land on one
+    let last_payment = if options.recurrence.is_some() { options.end_date } else { Some(start) };
+    let past_calendar = calendar.covered_until().filter(|until| last_payment.is_none_or(|last| last > *until));
+    if let Some(until) = past_calendar.filter(|_| options.business_day_convention != BusinessDayConvention::Unadjusted) {
+        println!("Note: the {} holiday calendar ends {}; payments after that are only moved for weekends.", currency, until);
+    }
+    print!("Save this schedule? (y/n): ");
+    io::stdout().flush().unwrap();
+    let mut confirm = String::new();
+    io::stdin().read_line(&mut confirm).unwrap();
+    if !confirm.trim().eq_ignore_ascii_case("y") {
+        println!("Scheduled transaction not saved.");
+        return None;
+    }
+    Some(options)
+}
+
+fn read_recurrence_options(start: NaiveDate) -> Option<ScheduleOptions> {
+    let mut recurrence = String::new();
+    println!("Enter the recurrence (daily, weekly, biweekly, monthly, quarterly, yearly, or an RRULE such as FREQ=MONTHLY;BYMONTHDAY=-1):");
+    std::io::stdin().read_line(&mut recurrence).unwrap();
+    let recurrence = match Recurrence::parse(&recurrence) {
+        Ok(recurrence) => recurrence.anchored_to(start),
+        Err(e) => {
+            println!("Invalid recurrence: {}", e);
+            return None;
+        }
+    };
+    let mut options = ScheduleOptions::new(recurrence);
+
+    let mut end_date = String::new();
+    println!("Enter an end date (YYYY-MM-DD), or press Enter for none:");
+    std::io::stdin().read_line(&mut end_date).unwrap();
+    if !end_date.trim().is_empty() {
+        match NaiveDate::parse_from_str(end_date.trim(), "%Y-%m-%d") {
+            Ok(date) if date >= start => options.end_date = Some(date),
+            _ => {
+                println!("Invalid end date. It must be YYYY-MM-DD and not before the first payment.");
+                return None;
+            }
+        }
+    }
+
+    let mut occurrence_count = String::new();
+    println!("Enter the number of payments to make, or press Enter for no limit:");
+    std::io::stdin().read_line(&mut occurrence_count).unwrap();
+    if !occurrence_count.trim().is_empty() {
+        match occurrence_count.trim().parse::<i32>() {
+            Ok(count) if count > 0 => options.occurrence_count = Some(count),
+            _ => {
+                println!("Invalid number of payments. Please enter a positive number.");
+                return None;
+            }
+        }
+    }
+    Some(options)
+}
+
+#[allow(clippy::redundant_pattern_matching)]
 pub fn validate_scheduled_transaction(conn: &mut diesel::PgConnection, transaction_id_to_validate: Uuid) -> bool {
     use diesel::prelude::*;
     use rust_eze::schema::scheduled_transactions::dsl::*;
@@ -354,7 +800,7 @@ pub fn validate_scheduled_transaction(conn: &mut diesel::PgConnection, transacti
     }
 }
 
-pub fn delete_scheduled_transaction_flow(conn: &mut diesel::PgConnection) {
+pub fn delete_scheduled_transaction_flow(conn: &mut diesel::PgConnection, account_id: Uuid) {
     let mut transaction_id_to_delete = String::new();
     print!("Enter the ID of the scheduled transaction to delete: ");
     io::stdout().flush().unwrap();
@@ -372,132 +818,1280 @@ pub fn delete_scheduled_transaction_flow(conn: &mut diesel::PgConnection) {
         return;
     }
 
-    match delete_scheduled_transaction(conn, transaction_id_to_delete) {
+    match delete_scheduled_transaction(conn, account_id, transaction_id_to_delete) {
         Ok(_) => println!("Scheduled transaction deleted"),
+        Err(diesel::result::Error::RollbackTransaction) => println!("You can only delete scheduled transactions you pay from."),
         Err(e) => println!("Failed to delete scheduled transaction: {:?}", e),
     }
 }
-pub fn view_scheduled_transactions_flow(conn: &mut diesel::PgConnection, account_id: Uuid) {
-    match view_scheduled_transactions(conn, account_id) {
-        Ok(scheduled_transactions) => println!("Scheduled transactions: {:#?}", scheduled_transactions),
-        Err(e) => println!("Failed to get scheduled transactions: {:?}", e),
-    }
-}
-pub fn login_flow(conn: &mut diesel::PgConnection) {
-    let mut username = String::new();  
-    print!("Enter your username: ");
+
+// Pause, resume, skip or edit one of the user's own schedules without losing its history
+pub fn manage_scheduled_transaction_flow(conn: &mut diesel::PgConnection, account_id: Uuid) {
+    let mut schedule_id = String::new();
+    print!("Enter the ID of the scheduled transaction: ");
     io::stdout().flush().unwrap();
-    io::stdin().read_line(&mut username).unwrap();
-    let username = username.trim();
-    print!("Enter your password: ");
+    io::stdin().read_line(&mut schedule_id).unwrap();
+    let schedule_id: Uuid = match schedule_id.trim().parse() {
+        Ok(id) => id,
+        Err(_) => {
+            println!("Invalid scheduled transaction ID. Please enter a valid UUID.");
+            return;
+        }
+    };
+
+    println!("1. Pause");
+    println!("2. Resume");
+    println!("3. Skip the next payment");
+    println!("4. Edit amount, next date, time or recurrence");
+    let mut action = String::new();
+    print!("Enter your choice (1-4): ");
     io::stdout().flush().unwrap();
-    let password = rpassword::read_password().unwrap();
+    io::stdin().read_line(&mut action).unwrap();
 
-    match validate_username_password(conn, &username, &password) {
-        Some(account_id) => {
-            // Start the CLI loop
-            loop {
-                println!("What can we do for you?");
-                println!("1. Create Sub-Account");
-                println!("2. Transfer between sub-accounts");
-                println!("3. Transfer money to someone else");
-                println!("4. Get balance");
-                println!("5. Get transactions");
-                println!("6. Add money to sub-account");
-                println!("7. Add scheduled transaction");
-                println!("8. View scheduled transactions");
-                println!("9. Delete scheduled transaction");
-                println!("10. Exit");
-                
-                let mut choice = String::new();
-                print!("Enter your choice (1-8): ");
-                io::stdout().flush().unwrap();
-                io::stdin().read_line(&mut choice).unwrap();
-                let choice = choice.trim();
+    let result = match action.trim() {
+        "1" => pause_scheduled_transaction(conn, account_id, schedule_id),
+        "2" => resume_scheduled_transaction(conn, account_id, schedule_id),
+        "3" => skip_next_scheduled_occurrence(conn, account_id, schedule_id),
+        "4" => {
+            let changes = match read_schedule_update() {
+                Some(changes) => changes,
+                None => return,
+            };
+            update_scheduled_transaction(conn, account_id, schedule_id, &changes)
+        }
+        _ => {
+            println!("Invalid choice.");
+            return;
+        }
+    };
 
-                match choice {
-                    "1" => create_sub_account_flow(conn, account_id),
-                    "2" => transfer_between_sub_accounts_flow(conn, account_id),
-                    "3" => transfer_money_to_someone_else_flow(conn, account_id),
-                    "4" => get_balance_flow(conn, account_id),
-                    "5" => get_transactions_flow(conn, account_id),
-                    "6" => add_money_to_sub_account_flow(conn, account_id),
-                    "7" => add_scheduled_transaction_flow(conn, account_id),
-                    "8" => view_scheduled_transactions_flow(conn, account_id),
-                    "9" => delete_scheduled_transaction_flow(conn),
-                    "10" => {
-                        println!("Exiting... Goodbye!");
-                        break;
-                    }
-                    _ => println!("Invalid choice, please try again."),
-                }
-            }
-        },
-        None => println!("Invalid username or password"),
+    match result {
+        Ok(schedule) => {
+            println!("Scheduled transaction updated:");
+            print_scheduled_transactions(conn, &[schedule]);
+        }
+        Err(diesel::result::Error::RollbackTransaction) => println!("The change was not allowed. You can only change active schedules you pay from, the amount must be positive, and the next date must be from today and within the end date."),
+        Err(e) => println!("Failed to update scheduled transaction: {:?}", e),
     }
 }
 
-pub fn admin_flow(conn: &mut diesel::PgConnection) {
-    let mut username = String::new();
-    let mut password = String::new();
-    print!("Enter your username: ");
-    io::stdout().flush().unwrap();
-    io::stdin().read_line(&mut username).unwrap();
-    let username = username.trim();
-    print!("Enter your password: ");
-    io::stdout().flush().unwrap();
-    io::stdin().read_line(&mut password).unwrap();
-    let password = password.trim();
+fn read_schedule_update() -> Option<ScheduleUpdate> {
+    let mut changes = ScheduleUpdate::default();
 
-    if username == "admin" && password == "admin" {
-        loop {
-            println!("=== ADMIN MODE ===");
-            println!("1. Get pending transactions");
-            println!("2. Approve pending transaction");
-            println!("3. Get all accounts");
-            println!("4. Get scheduled transactions");
-            println!("5. Exit");
-            let mut choice = String::new();
-            io::stdin().read_line(&mut choice).unwrap();
-            let choice = choice.trim();
-            match choice {
-                "1" => get_pending_transactions_flow(conn),
-                "2" => approve_pending_transaction_flow(conn),
-                "3" => get_accounts_flow(conn),
-                "4" => get_scheduled_transactions_flow(conn),
-                "5" => {
-                    println!("Exiting... Goodbye!");
-                    break;
-                }
-                _ => println!("Invalid choice, please try again."),
+    let mut amount = String::new();
+    println!("Enter the new amount, or press Enter to keep it:");
+    io::stdin().read_line(&mut amount).unwrap();
+    if !amount.trim().is_empty() {
+        match amount.trim().parse::<f64>() {
+            Ok(value) if validate_amount(value) => changes.amount = Some(value),
+            _ => {
+                println!("Invalid amount. Please enter a valid number.");
+                return None;
             }
         }
-    } else {
-        println!("Invalid username or password");
     }
-}
 
-pub fn approve_pending_transaction_flow(conn: &mut diesel::PgConnection) {
-    let mut pending_transaction_id = String::new();
-    print!("Enter the ID of the pending transaction to approve: ");
-    io::stdout().flush().unwrap();
-    io::stdin().read_line(&mut pending_transaction_id).unwrap();
-    let pending_transaction_id: Uuid = match pending_transaction_id.trim().parse() {
-        Ok(id) => id,
-        Err(_) => {
-            println!("Invalid pending transaction ID. Please enter a valid number.");
-            return;
+    let mut next_date = String::new();
+    println!("Enter the new next payment date (YYYY-MM-DD), or press Enter to keep it:");
+    io::stdin().read_line(&mut next_date).unwrap();
+    if !next_date.trim().is_empty() {
+        match NaiveDate::parse_from_str(next_date.trim(), "%Y-%m-%d") {
+            Ok(date) => changes.next_date = Some(date),
+            Err(_) => {
+                println!("Invalid date format. Please use YYYY-MM-DD.");
+                return None;
+            }
         }
-    };
-    
-    match approve_pending_transaction(conn, pending_transaction_id) {
-        Ok(_) => println!("Pending transaction approved"),
-        Err(e) => println!("Failed to approve pending transaction: {:?}", e),
     }
-}
 
-pub fn add_money_to_sub_account_flow(conn: &mut diesel::PgConnection, account_id: Uuid) {
-    let mut amount = String::new();
+    let mut execution_time = String::new();
+    println!("Enter the new local time to pay at (HH:MM), or press Enter to keep it:");
+    io::stdin().read_line(&mut execution_time).unwrap();
+    if !execution_time.trim().is_empty() {
+        match NaiveTime::parse_from_str(execution_time.trim(), "%H:%M") {
+            Ok(time) => changes.execution_time = Some(time),
+            Err(_) => {
+                println!("Invalid time format. Please use HH:MM.");
+                return None;
+            }
+        }
+    }
+
+    let mut recurrence = String::new();
+    println!("Enter the new recurrence for a standing order, or press Enter to keep it:");
+    io::stdin().read_line(&mut recurrence).unwrap();
+    if !recurrence.trim().is_empty() {
+        match Recurrence::parse(&recurrence) {
+            Ok(rule) => changes.recurrence = Some(rule),
+            Err(e) => {
+                println!("Invalid recurrence: {}", e);
+                return None;
+            }
+        }
+    }
+    Some(changes)
+}
+pub fn view_scheduled_transactions_flow(conn: &mut diesel::PgConnection, account_id: Uuid) {
+    match view_scheduled_transactions(conn, account_id) {
+        Ok(scheduled_transactions) => print_scheduled_transactions(conn, &scheduled_transactions),
+        Err(e) => println!("Failed to get scheduled transactions: {:?}", e),
+    }
+}
+pub fn view_scheduled_runs_flow(conn: &mut diesel::PgConnection, account_id: Uuid) {
+    let mut schedule_id = String::new();
+    print!("Enter the ID of the scheduled transaction: ");
+    io::stdout().flush().unwrap();
+    io::stdin().read_line(&mut schedule_id).unwrap();
+    let schedule_id: Uuid = match schedule_id.trim().parse() {
+        Ok(id) => id,
+        Err(_) => {
+            println!("Invalid scheduled transaction ID. Please enter a valid UUID.");
+            return;
+        }
+    };
+
+    match get_scheduled_runs(conn, account_id, schedule_id) {
+        Ok(runs) => {
+            for run in runs {
+                println!("  {} {} (attempts: {}){}", format_local(conn, account_id, run.occurrence_date), run.status, run.attempt_count,
+                    run.last_error.map(|e| format!(" - {}", e)).unwrap_or_default());
+            }
+        }
+        Err(e) => println!("Failed to get run history: {:?}", e),
+    }
+}
+
+// List, add, remove, enable or disable the places this account's notifications are sent
+pub fn notification_channels_flow(conn: &mut diesel::PgConnection, account_id: Uuid) {
+    match get_notification_channels(conn, account_id) {
+        Ok(channels) => {
+            if channels.is_empty() {
+                println!("You have no notification channels.");
+            }
+            for channel in channels {
+                println!("  [{}] {} {} ({})", channel.id, channel.channel, channel.address, if channel.enabled { "enabled" } else { "disabled" });
+            }
+        }
+        Err(e) => {
+            println!("Failed to get notification channels: {:?}", e);
+            return;
+        }
+    }
+
+    println!("1. Add a channel");
+    println!("2. Remove a channel");
+    println!("3. Enable a channel");
+    println!("4. Disable a channel");
+    println!("5. Back");
+    let mut action = String::new();
+    print!("Enter your choice (1-5): ");
+    io::stdout().flush().unwrap();
+    io::stdin().read_line(&mut action).unwrap();
+
+    match action.trim() {
+        "1" => {
+            let mut channel = String::new();
+            print!("Enter the channel (telegram, email, webhook, file): ");
+            io::stdout().flush().unwrap();
+            io::stdin().read_line(&mut channel).unwrap();
+            let mut address = String::new();
+            print!("Enter the Telegram chat ID, email address, public webhook URL, notification file name, or stdout: ");
+            io::stdout().flush().unwrap();
+            io::stdin().read_line(&mut address).unwrap();
+            match add_notification_channel(conn, account_id, &channel.trim().to_lowercase(), address.trim()) {
+                Ok(_) => println!("Notification channel added"),
+                Err(diesel::result::Error::RollbackTransaction) => println!("Unknown channel, or an address it cannot use: Telegram chat IDs are numbers, emails need a valid address, webhooks must be public URLs and files must be stdout or set up by an operator."),
+                Err(e) => println!("Failed to add notification channel: {:?}", e),
+            }
+        }
+        "2" | "3" | "4" => {
+            let mut channel_id = String::new();
+            print!("Enter the channel ID: ");
+            io::stdout().flush().unwrap();
+            io::stdin().read_line(&mut channel_id).unwrap();
+            let channel_id: Uuid = match channel_id.trim().parse() {
+                Ok(id) => id,
+                Err(_) => {
+                    println!("Invalid channel ID. Please enter a valid UUID.");
+                    return;
+                }
+            };
+            let result = match action.trim() {
+                "2" => remove_notification_channel(conn, account_id, channel_id).map(|_| ()),
+                enable => set_notification_channel_enabled(conn, account_id, channel_id, enable == "3").map(|_| ()),
+            };
+            match result {
+                Ok(_) => println!("Notification channel updated"),
+                Err(e) => println!("Failed to update notification channel: {:?}", e),
+            }
+        }
+        _ => {}
+    }
+}
+
+fn read_uuid(prompt: &str) -> Option<Uuid> {
+    let mut input = String::new();
+    print!("{}", prompt);
+    io::stdout().flush().unwrap();
+    io::stdin().read_line(&mut input).unwrap();
+    match input.trim().parse() {
+        Ok(id) => Some(id),
+        Err(_) => {
+            println!("Invalid ID. Please enter a valid UUID.");
+            None
+        }
+    }
+}
+
+// An account entered as its ID, account number or IBAN
+fn read_account_id(conn: &mut diesel::PgConnection, prompt: &str) -> Option<Uuid> {
+    let mut input = String::new();
+    print!("{}", prompt);
+    io::stdout().flush().unwrap();
+    io::stdin().read_line(&mut input).unwrap();
+    match resolve_account_id(conn, &input) {
+        Ok(id) => Some(id),
+        Err(diesel::result::Error::NotFound) => {
+            println!("No account has the number {}.", input.trim());
+            None
+        }
+        Err(diesel::result::Error::RollbackTransaction) => {
+            println!("Invalid account. Please enter a UUID or an account number; check account numbers for typos.");
+            None
+        }
+        Err(e) => {
+            println!("Failed to look up the account: {:?}", e);
+            None
+        }
+    }
+}
+
+// Account numbers to give to people paying in, for the account and each sub-account
+pub fn view_account_numbers_flow(conn: &mut diesel::PgConnection, account_id: Uuid) {
+    match get_account(conn, account_id) {
+        Ok(account) => {
+            println!("Account {}: {} (IBAN {})", account.account_holder_name, account.account_number, format_iban(&account.account_number));
+        }
+        Err(e) => {
+            println!("Failed to get your account: {:?}", e);
+            return;
+        }
+    }
+    match get_sub_accounts(conn, account_id) {
+        Ok(subs) => {
+            for sub in subs {
+                println!("  {} sub-account: {} (IBAN {})", sub.currency, sub.account_number, format_iban(&sub.account_number));
+            }
+        }
+        Err(e) => println!("Failed to get your sub-accounts: {:?}", e),
+    }
+    println!("Any of these numbers can be used to pay you. Payments go to the sub-account of the currency they are made in.");
+}
+
+pub fn payees_flow(conn: &mut diesel::PgConnection, account_id: Uuid) {
+    match get_payees(conn, account_id) {
+        Ok(payees) => {
+            if payees.is_empty() {
+                println!("You have no payees.");
+            }
+            for payee in payees {
+                let holder = get_account_holder_name(conn, payee.payee_account_id).unwrap_or_default();
+                let currency = payee.default_currency.map(|c| format!(", pays in {}", c)).unwrap_or_default();
+                println!("  {}: {} ({}){}", payee.nickname, holder, payee.payee_account_id, currency);
+            }
+        }
+        Err(e) => {
+            println!("Failed to get payees: {:?}", e);
+            return;
+        }
+    }
+
+    println!("1. Add a payee");
+    println!("2. Remove a payee");
+    println!("3. Back");
+    let mut action = String::new();
+    print!("Enter your choice (1-3): ");
+    io::stdout().flush().unwrap();
+    io::stdin().read_line(&mut action).unwrap();
+
+    match action.trim() {
+        "1" => {
+            let mut who = String::new();
+            print!("Enter the payee's username, account number or account ID: ");
+            io::stdout().flush().unwrap();
+            io::stdin().read_line(&mut who).unwrap();
+            let recipient = match resolve_recipient(conn, account_id, &who) {
+                Ok(recipient) => recipient,
+                Err(diesel::result::Error::NotFound) => {
+                    println!("No user or account matches {:?}.", who.trim());
+                    return;
+                }
+                Err(diesel::result::Error::RollbackTransaction) => {
+                    println!("{:?} is not a valid account number. Check it for typos.", who.trim());
+                    return;
+                }
+                Err(e) => {
+                    println!("Failed to look up the payee: {:?}", e);
+                    return;
+                }
+            };
+            println!("This account belongs to {}", recipient.holder_name);
+
+            let mut nickname = String::new();
+            print!("Enter a nickname for them: ");
+            io::stdout().flush().unwrap();
+            io::stdin().read_line(&mut nickname).unwrap();
+
+            let mut currency = String::new();
+            print!("Enter the currency you usually pay them in (press Enter for none): ");
+            io::stdout().flush().unwrap();
+            io::stdin().read_line(&mut currency).unwrap();
+            let currency = currency.trim().to_uppercase();
+            if !currency.is_empty() && !validate_currency(&currency) {
+                println!("Invalid currency format. Please enter a valid currency code (e.g., USD, EUR).");
+                return;
+            }
+
+            match add_payee(conn, account_id, &nickname, recipient.account_id, Some(&currency)) {
+                Ok(payee) => println!("{} added to your payees", payee.nickname),
+                Err(diesel::result::Error::RollbackTransaction) => {
+                    println!("The nickname must be new, must not be an account ID or number, and cannot be used for your own account.")
+                }
+                Err(e) => println!("Failed to add payee: {:?}", e),
+            }
+        }
+        "2" => {
+            let mut nickname = String::new();
+            print!("Enter the nickname of the payee to remove: ");
+            io::stdout().flush().unwrap();
+            io::stdin().read_line(&mut nickname).unwrap();
+            match remove_payee(conn, account_id, &nickname) {
+                Ok(0) => println!("You have no payee called {}", nickname.trim()),
+                Ok(_) => println!("Payee removed"),
+                Err(e) => println!("Failed to remove payee: {:?}", e),
+            }
+        }
+        _ => {}
+    }
+}
+
+fn print_payment_request(conn: &mut diesel::PgConnection, request: &PaymentRequest, counterparty: Uuid) {
+    let name = get_account_holder_name(conn, counterparty).unwrap_or_default();
+    let status = if is_expired(request) { "expired" } else { request.status.as_str() };
+    let memo = request.memo.as_deref().map(|m| format!(" for {:?}", m)).unwrap_or_default();
+    println!(
+        "  {}: {} {} {}{}, {} (expires {})",
+        request.id, name, request.amount, request.currency, memo, status, request.expires_at.format("%Y-%m-%d %H:%M UTC")
+    );
+}
+
+fn read_payment_request_id() -> Option<Uuid> {
+    let mut request_id = String::new();
+    print!("Enter the payment request ID: ");
+    io::stdout().flush().unwrap();
+    io::stdin().read_line(&mut request_id).unwrap();
+    match request_id.trim().parse::<Uuid>() {
+        Ok(id) => Some(id),
+        Err(_) => {
+            println!("Invalid payment request ID.");
+            None
+        }
+    }
+}
+
+pub fn payment_requests_flow(conn: &mut diesel::PgConnection, account_id: Uuid) {
+    println!("Requests to you:");
+    match get_incoming_payment_requests(conn, account_id) {
+        Ok(requests) => {
+            if requests.is_empty() {
+                println!("  None");
+            }
+            for request in &requests {
+                print_payment_request(conn, request, request.requester_account_id);
+            }
+        }
+        Err(e) => println!("Failed to get payment requests: {:?}", e),
+    }
+    println!("Requests you sent:");
+    match get_outgoing_payment_requests(conn, account_id) {
+        Ok(requests) => {
+            if requests.is_empty() {
+                println!("  None");
+            }
+            for request in &requests {
+                print_payment_request(conn, request, request.payer_account_id);
+            }
+        }
+        Err(e) => println!("Failed to get payment requests: {:?}", e),
+    }
+
+    println!("1. Request money");
+    println!("2. Pay a request");
+    println!("3. Decline a request");
+    println!("4. Cancel a request I sent");
+    println!("5. Back");
+    let mut action = String::new();
+    print!("Enter your choice (1-5): ");
+    io::stdout().flush().unwrap();
+    io::stdin().read_line(&mut action).unwrap();
+
+    match action.trim() {
+        "1" => {
+            let mut who = String::new();
+            print!("Enter the payer's username, payee nickname, account number or account ID: ");
+            io::stdout().flush().unwrap();
+            io::stdin().read_line(&mut who).unwrap();
+            let payer = match resolve_recipient(conn, account_id, &who) {
+                Ok(payer) => payer,
+                Err(diesel::result::Error::NotFound) => {
+                    println!("No user or account matches {:?}.", who.trim());
+                    return;
+                }
+                Err(diesel::result::Error::RollbackTransaction) => {
+                    println!("{:?} is not a valid account number. Check it for typos.", who.trim());
+                    return;
+                }
+                Err(e) => {
+                    println!("Failed to look up the payer: {:?}", e);
+                    return;
+                }
+            };
+            if payer.account_id == account_id {
+                println!("You cannot request money from yourself.");
+                return;
+            }
+            println!("Requesting money from {}", payer.holder_name);
+
+            let currency = read_payment_currency(&payer).to_uppercase();
+            if !validate_currency(&currency) {
+                println!("Invalid currency format. Please enter a valid currency code (e.g., USD, EUR).");
+                return;
+            }
+
+            let mut amount = String::new();
+            print!("Enter the amount to request: ");
+            io::stdout().flush().unwrap();
+            io::stdin().read_line(&mut amount).unwrap();
+            let amount: f64 = match amount.trim().parse() {
+                Ok(a) if validate_amount(a) => a,
+                _ => {
+                    println!("Invalid amount. Please enter a valid number.");
+                    return;
+                }
+            };
+
+            let mut memo = String::new();
+            print!("Enter what the money is for (press Enter to skip): ");
+            io::stdout().flush().unwrap();
+            io::stdin().read_line(&mut memo).unwrap();
+
+            let default_days = payment_request_expiry_days();
+            let mut days = String::new();
+            print!("Enter the number of days the request stays open (press Enter for {}): ", default_days);
+            io::stdout().flush().unwrap();
+            io::stdin().read_line(&mut days).unwrap();
+            let days = match days.trim() {
+                "" => default_days,
+                days => match days.parse::<i64>() {
+                    Ok(days) if days >= 1 => days,
+                    _ => {
+                        println!("Invalid number of days.");
+                        return;
+                    }
+                },
+            };
+
+            let terms = PaymentRequestTerms { amount, currency: &currency, memo: Some(&memo), expires_in_days: days };
+            match create_payment_request(conn, account_id, payer.account_id, &terms, MissingSubAccountPolicy::from_env()) {
+                Ok(request) => println!("Payment request {} sent to {}", request.id, payer.holder_name),
+                Err(diesel::result::Error::RollbackTransaction) => {
+                    println!("You cannot receive {} with your current sub-accounts.", currency)
+                }
+                Err(e) => println!("Failed to create payment request: {:?}", e),
+            }
+        }
+        "2" => {
+            let Some(request_id) = read_payment_request_id() else { return };
+            match accept_payment_request(conn, account_id, request_id, MissingSubAccountPolicy::from_env()) {
+                Ok(transaction) => println!("Request paid: {:#?}", transaction),
+                Err(diesel::result::Error::NotFound) => println!("No open request to you has that ID. It may have expired or been decided already."),
+                Err(diesel::result::Error::RollbackTransaction) => println!("Payment failed: check that you have enough money in the requested currency."),
+                Err(e) => println!("Payment failed: {:?}", e),
+            }
+        }
+        "3" => {
+            let Some(request_id) = read_payment_request_id() else { return };
+            match decline_payment_request(conn, account_id, request_id) {
+                Ok(_) => println!("Request declined"),
+                Err(diesel::result::Error::NotFound) => println!("No open request to you has that ID. It may have expired or been decided already."),
+                Err(e) => println!("Failed to decline request: {:?}", e),
+            }
+        }
+        "4" => {
+            let Some(request_id) = read_payment_request_id() else { return };
+            match cancel_payment_request(conn, account_id, request_id) {
+                Ok(_) => println!("Request cancelled"),
+                Err(diesel::result::Error::NotFound) => println!("You have no open request with that ID."),
+                Err(e) => println!("Failed to cancel request: {:?}", e),
+            }
+        }
+        _ => {}
+    }
+}
+
+pub fn webhooks_flow(conn: &mut diesel::PgConnection, account_id: Uuid) {
+    match get_webhook_endpoints(conn, account_id) {
+        Ok(endpoints) => {
+            if endpoints.is_empty() {
+                println!("You have no webhook endpoints.");
+            }
+            for endpoint in endpoints {
+                let events = if endpoint.event_types.is_empty() { "all events".to_string() } else { endpoint.event_types.join(", ") };
+                println!("  [{}] {} ({})", endpoint.id, endpoint.url, events);
+            }
+        }
+        Err(e) => {
+            println!("Failed to get webhook endpoints: {:?}", e);
+            return;
+        }
+    }
+
+    println!("1. Add an endpoint");
+    println!("2. Remove an endpoint");
+    println!("3. View delivery attempts");
+    println!("4. Replay a past event");
+    println!("5. Back");
+    let mut action = String::new();
+    print!("Enter your choice (1-5): ");
+    io::stdout().flush().unwrap();
+    io::stdin().read_line(&mut action).unwrap();
+
+    match action.trim() {
+        "1" => {
+            let mut url = String::new();
+            print!("Enter the endpoint URL: ");
+            io::stdout().flush().unwrap();
+            io::stdin().read_line(&mut url).unwrap();
+            println!("Event types: {}", EVENT_TYPES.join(", "));
+            let mut events = String::new();
+            print!("Enter the event types to send, separated by commas (press Enter for all): ");
+            io::stdout().flush().unwrap();
+            io::stdin().read_line(&mut events).unwrap();
+            let events: Vec<String> = events.split(',').map(|e| e.trim().to_lowercase()).filter(|e| !e.is_empty()).collect();
+            if let Some(unknown) = events.iter().find(|e| !is_valid_event_type(e)) {
+                println!("Unknown event type: {}", unknown);
+                return;
+            }
+            match add_webhook_endpoint(conn, account_id, &url, &events) {
+                Ok(endpoint) => {
+                    println!("Webhook endpoint added");
+                    println!("Signing secret (shown once, store it now): {}", endpoint.secret);
+                }
+                Err(diesel::result::Error::RollbackTransaction) => println!("The URL must be a public http:// or https:// address."),
+                Err(e) => println!("Failed to add webhook endpoint: {:?}", e),
+            }
+        }
+        "2" => {
+            let Some(endpoint_id) = read_uuid("Enter the endpoint ID: ") else { return };
+            match remove_webhook_endpoint(conn, account_id, endpoint_id) {
+                Ok(_) => println!("Webhook endpoint removed"),
+                Err(e) => println!("Failed to remove webhook endpoint: {:?}", e),
+            }
+        }
+        "3" => {
+            let Some(endpoint_id) = read_uuid("Enter the endpoint ID: ") else { return };
+            match get_webhook_deliveries(conn, account_id, endpoint_id, 20) {
+                Ok(deliveries) if deliveries.is_empty() => println!("No delivery attempts yet"),
+                Ok(deliveries) => {
+                    for delivery in deliveries {
+                        let outcome = match (delivery.succeeded, delivery.status_code) {
+                            (true, Some(code)) => format!("ok ({})", code),
+                            _ => format!("failed: {}", delivery.error.as_deref().unwrap_or("-")),
+                        };
+                        println!("  {} event {}{} {} in {} ms", delivery.created_at.format("%Y-%m-%d %H:%M:%S UTC"), delivery.outbox_id,
+                            if delivery.replay { " (replay)" } else { "" }, outcome, delivery.duration_ms);
+                    }
+                }
+                Err(e) => println!("Failed to get delivery attempts: {:?}", e),
+            }
+        }
+        "4" => {
+            match get_account_events(conn, account_id, 20) {
+                Ok(events) => {
+                    for event in events {
+                        println!("  [{}] {} {} {}", event.id, event.created_at.format("%Y-%m-%d %H:%M:%S UTC"), event.event_type, event.subject);
+                    }
+                }
+                Err(e) => {
+                    println!("Failed to get events: {:?}", e);
+                    return;
+                }
+            }
+            let Some(event_id) = read_uuid("Enter the event ID to replay: ") else { return };
+            let Some(endpoint_id) = read_uuid("Enter the endpoint ID to send it to: ") else { return };
+            match replay_webhook_event(conn, &WebhookSender::new(), account_id, endpoint_id, event_id) {
+                Ok(delivery) if delivery.succeeded => println!("Event replayed"),
+                Ok(delivery) => println!("Replay failed: {}", delivery.error.as_deref().unwrap_or("-")),
+                Err(diesel::result::Error::NotFound) => println!("No such event or endpoint on your account"),
+                Err(e) => println!("Failed to replay event: {:?}", e),
+            }
+        }
+        _ => {}
+    }
+}
+
+// Tops a sub-account up from another one before scheduled payments it cannot cover
+pub fn auto_top_up_flow(conn: &mut diesel::PgConnection, account_id: Uuid) {
+    let mut target_currency = String::new();
+    print!("Enter the currency of the sub-account your scheduled payments are made from: ");
+    io::stdout().flush().unwrap();
+    io::stdin().read_line(&mut target_currency).unwrap();
+    let target_currency = target_currency.trim().to_uppercase();
+    if !validate_currency(&target_currency) {
+        println!("Invalid currency format. Please enter a valid currency code (e.g., USD, EUR).");
+        return;
+    }
+
+    let mut source_currency = String::new();
+    print!("Enter the currency of the sub-account to top it up from, or press Enter to turn automatic top-up off: ");
+    io::stdout().flush().unwrap();
+    io::stdin().read_line(&mut source_currency).unwrap();
+    let source_currency = source_currency.trim().to_uppercase();
+    if !source_currency.is_empty() && !validate_currency(&source_currency) {
+        println!("Invalid currency format. Please enter a valid currency code (e.g., USD, EUR).");
+        return;
+    }
+
+    let source = if source_currency.is_empty() { None } else { Some(source_currency.as_str()) };
+    match set_auto_top_up(conn, account_id, &target_currency, source) {
+        Ok(_) => match source {
+            Some(source) => println!("The {} sub-account will be topped up from {} before scheduled payments it cannot cover", target_currency, source),
+            None => println!("Automatic top-up turned off for the {} sub-account", target_currency),
+        },
+        Err(diesel::result::Error::NotFound) => println!("You need sub-accounts in both currencies."),
+        Err(diesel::result::Error::RollbackTransaction) => println!("A sub-account cannot top itself up."),
+        Err(e) => println!("Failed to set automatic top-up: {:?}", e),
+    }
+}
+
+// Scheduled payments run at their local time in this zone
+pub fn set_time_zone_flow(conn: &mut diesel::PgConnection, account_id: Uuid) {
+    let current = account_time_zone(conn, account_id).unwrap_or(Tz::UTC);
+    let mut zone = String::new();
+    print!("Enter your time zone (IANA name, e.g. Asia/Singapore), or press Enter to keep {}: ", current.name());
+    io::stdout().flush().unwrap();
+    io::stdin().read_line(&mut zone).unwrap();
+    if zone.trim().is_empty() {
+        return;
+    }
+
+    match set_account_time_zone(conn, account_id, zone.trim()) {
+        Ok(account) => println!("Time zone set to {}", account.time_zone),
+        Err(diesel::result::Error::RollbackTransaction) => println!("Unknown time zone. Please use an IANA name such as Europe/London or America/Los_Angeles."),
+        Err(e) => println!("Failed to set time zone: {:?}", e),
+    }
+}
+
+pub fn set_language_flow(conn: &mut diesel::PgConnection, account_id: Uuid) {
+    let current = account_language(conn, account_id).unwrap_or_else(|_| "en".to_string());
+    let mut language = String::new();
+    print!("Enter the language for your notifications (two-letter code, e.g. en or es), or press Enter to keep {}: ", current);
+    io::stdout().flush().unwrap();
+    io::stdin().read_line(&mut language).unwrap();
+    if language.trim().is_empty() {
+        return;
+    }
+
+    match set_account_language(conn, account_id, language.trim()) {
+        Ok(account) => println!("Notification language set to {}", account.language),
+        Err(diesel::result::Error::RollbackTransaction) => println!("No notification templates are available in that language."),
+        Err(e) => println!("Failed to set notification language: {:?}", e),
+    }
+}
+
+// Gives the user a one-time code to send to the bot, or unlinks their Telegram account
+pub fn link_telegram_flow(conn: &mut diesel::PgConnection, account_id: Uuid) {
+    match get_telegram_link(conn, account_id) {
+        Ok(Some(link)) => {
+            println!("Linked to Telegram user {} since {}", link.telegram_user_id, link.linked_at.format("%Y-%m-%d %H:%M UTC"));
+            print!("Unlink it? (y/n): ");
+            io::stdout().flush().unwrap();
+            let mut confirm = String::new();
+            io::stdin().read_line(&mut confirm).unwrap();
+            if confirm.trim().eq_ignore_ascii_case("y") {
+                match unlink_telegram(conn, account_id) {
+                    Ok(_) => println!("Telegram unlinked"),
+                    Err(e) => println!("Failed to unlink Telegram: {:?}", e),
+                }
+            }
+        }
+        Ok(None) => match create_telegram_link_code(conn, account_id) {
+            Ok(code) => println!("Send \"/link {}\" to the Rust EZE bot within {} minutes to link your Telegram account.", code, LINK_CODE_TTL_MINUTES),
+            Err(e) => println!("Failed to create a link code: {:?}", e),
+        },
+        Err(e) => println!("Failed to get your Telegram link: {:?}", e),
+    }
+}
+
+#[allow(clippy::needless_borrow)]
+pub fn login_flow(conn: &mut diesel::PgConnection) {
+    let mut username = String::new();  
+    print!("Enter your username: ");
+    io::stdout().flush().unwrap();
+    io::stdin().read_line(&mut username).unwrap();
+    let username = username.trim();
+    print!("Enter your password: ");
+    io::stdout().flush().unwrap();
+    let password = rpassword::read_password().unwrap();
+
+    match validate_username_password(conn, &username, &password) {
+        Some(account_id) => {
+            // Start the CLI loop
+            loop {
+                println!("What can we do for you?");
+                println!("1. Create Sub-Account");
+                println!("2. Transfer between sub-accounts");
+                println!("3. Transfer money to someone else");
+                println!("4. Get balance");
+                println!("5. Get transactions");
+                println!("6. Add money to sub-account");
+                println!("7. Add scheduled transaction");
+                println!("8. View scheduled transactions");
+                println!("9. Delete scheduled transaction");
+                println!("10. Refund a received transaction");
+                println!("11. View my deposit requests");
+                println!("12. Cancel a deposit request");
+                println!("13. View scheduled transaction run history");
+                println!("14. Pause, resume, skip or edit a scheduled transaction");
+                println!("15. Set my time zone");
+                println!("16. Set up automatic top-up for scheduled payments");
+                println!("17. Manage notification channels");
+                println!("18. Manage webhook endpoints");
+                println!("19. Set my notification language");
+                println!("20. Link Telegram");
+                println!("21. Manage payees");
+                println!("22. View my account numbers");
+                println!("23. Payment requests");
+                println!("24. Categorize a transaction");
+                println!("25. Exit");
+                
+                let mut choice = String::new();
+                print!("Enter your choice (1-25): ");
+                io::stdout().flush().unwrap();
+                io::stdin().read_line(&mut choice).unwrap();
+                let choice = choice.trim();
+
+                match choice {
+                    "1" => create_sub_account_flow(conn, account_id),
+                    "2" => transfer_between_sub_accounts_flow(conn, account_id),
+                    "3" => transfer_money_to_someone_else_flow(conn, account_id),
+                    "4" => get_balance_flow(conn, account_id),
+                    "5" => get_transactions_flow(conn, account_id),
+                    "6" => add_money_to_sub_account_flow(conn, account_id),
+                    "7" => add_scheduled_transaction_flow(conn, account_id),
+                    "8" => view_scheduled_transactions_flow(conn, account_id),
+                    "9" => delete_scheduled_transaction_flow(conn, account_id),
+                    "10" => refund_transaction_flow(conn, account_id),
+                    "11" => view_my_pending_transactions_flow(conn, account_id),
+                    "12" => cancel_pending_transaction_flow(conn, account_id),
+                    "13" => view_scheduled_runs_flow(conn, account_id),
+                    "14" => manage_scheduled_transaction_flow(conn, account_id),
+                    "15" => set_time_zone_flow(conn, account_id),
+                    "16" => auto_top_up_flow(conn, account_id),
+                    "17" => notification_channels_flow(conn, account_id),
+                    "18" => webhooks_flow(conn, account_id),
+                    "19" => set_language_flow(conn, account_id),
+                    "20" => link_telegram_flow(conn, account_id),
+                    "21" => payees_flow(conn, account_id),
+                    "22" => view_account_numbers_flow(conn, account_id),
+                    "23" => payment_requests_flow(conn, account_id),
+                    "24" => categorize_transaction_flow(conn, account_id),
+                    "25" => {
+                        println!("Exiting... Goodbye!");
+                        break;
+                    }
+                    _ => println!("Invalid choice, please try again."),
+                }
+            }
+        },
+        None => println!("Invalid username or password"),
+    }
+}
+
+pub fn admin_flow(conn: &mut diesel::PgConnection) {
+    let mut username = String::new();
+    let mut password = String::new();
+    print!("Enter your username: ");
+    io::stdout().flush().unwrap();
+    io::stdin().read_line(&mut username).unwrap();
+    let username = username.trim();
+    print!("Enter your password: ");
+    io::stdout().flush().unwrap();
+    io::stdin().read_line(&mut password).unwrap();
+    let password = password.trim();
+
+    let operator_count = match count_operators(conn) {
+        Ok(count) => count,
+        Err(e) => {
+            println!("Failed to look up operators: {:?}", e);
+            return;
+        }
+    };
+    // The built-in admin login only exists to add the first operator; it cannot review anything
+    if operator_count == 0 && username == "admin" && password == "admin" {
+        println!("No operators exist yet. Add the first operator, then log in with their credentials.");
+        add_operator_flow(conn, username);
+        return;
+    }
+
+    if validate_operator(conn, username, password) {
+        loop {
+            println!("=== ADMIN MODE ===");
+            println!("1. Get pending transactions");
+            println!("2. Approve pending transaction");
+            println!("3. Get all accounts");
+            println!("4. Get scheduled transactions");
+            println!("5. Reverse transaction");
+            println!("6. Reject pending transaction");
+            println!("7. View decided deposit requests");
+            println!("8. View items awaiting second approval");
+            println!("9. Create admin adjustment");
+            println!("10. View approval thresholds");
+            println!("11. Set approval threshold");
+            println!("12. Add operator");
+            println!("13. View stuck outbox messages");
+            println!("14. Retry dead outbox message");
+            println!("15. Map operator to Telegram user");
+            println!("16. Exit");
+            let mut choice = String::new();
+            io::stdin().read_line(&mut choice).unwrap();
+            let choice = choice.trim();
+            match choice {
+                "1" => get_pending_transactions_flow(conn),
+                "2" => approve_pending_transaction_flow(conn, username),
+                "3" => get_accounts_flow(conn),
+                "4" => get_scheduled_transactions_flow(conn),
+                "5" => reverse_transaction_flow(conn),
+                "6" => reject_pending_transaction_flow(conn, username),
+                "7" => get_pending_transaction_history_flow(conn),
+                "8" => get_awaiting_second_approval_flow(conn),
+                "9" => request_admin_adjustment_flow(conn, username),
+                "10" => get_approval_thresholds_flow(conn),
+                "11" => set_approval_threshold_flow(conn, username),
+                "12" => add_operator_flow(conn, username),
+                "13" => get_stuck_outbox_messages_flow(conn),
+                "14" => requeue_outbox_message_flow(conn),
+                "15" => map_telegram_operator_flow(conn),
+                "16" => {
+                    println!("Exiting... Goodbye!");
+                    break;
+                }
+                _ => println!("Invalid choice, please try again."),
+            }
+        }
+    } else {
+        println!("Invalid username or password");
+    }
+}
+
+pub fn approve_pending_transaction_flow(conn: &mut diesel::PgConnection, reviewer: &str) {
+    let mut pending_transaction_id = String::new();
+    print!("Enter the ID of the pending transaction to approve: ");
+    io::stdout().flush().unwrap();
+    io::stdin().read_line(&mut pending_transaction_id).unwrap();
+    let pending_transaction_id: Uuid = match pending_transaction_id.trim().parse() {
+        Ok(id) => id,
+        Err(_) => {
+            println!("Invalid pending transaction ID. Please enter a valid number.");
+            return;
+        }
+    };
+    
+    match approve_pending_transaction(conn, pending_transaction_id, reviewer, MissingSubAccountPolicy::from_env()) {
+        Ok(pending_transaction) if pending_transaction.status == PENDING_STATUS_PENDING => {
+            println!("Approval recorded. This transaction needs a second operator to approve it.")
+        }
+        Ok(_) => println!("Pending transaction approved"),
+        Err(e) => println!("Failed to approve pending transaction: {:?}", e),
+    }
+}
+
+pub fn reject_pending_transaction_flow(conn: &mut diesel::PgConnection, reviewer: &str) {
+    let mut pending_transaction_id = String::new();
+    print!("Enter the ID of the pending transaction to reject: ");
+    io::stdout().flush().unwrap();
+    io::stdin().read_line(&mut pending_transaction_id).unwrap();
+    let pending_transaction_id: Uuid = match pending_transaction_id.trim().parse() {
+        Ok(id) => id,
+        Err(_) => {
+            println!("Invalid pending transaction ID. Please enter a valid number.");
+            return;
+        }
+    };
+
+    let mut reason = String::new();
+    print!("Enter the reason for rejecting: ");
+    io::stdout().flush().unwrap();
+    io::stdin().read_line(&mut reason).unwrap();
+    let reason = reason.trim();
+    if reason.is_empty() {
+        println!("A reason is required to reject a pending transaction.");
+        return;
+    }
+
+    match reject_pending_transaction(conn, pending_transaction_id, reviewer, reason) {
+        Ok(_) => println!("Pending transaction rejected"),
+        Err(e) => println!("Failed to reject pending transaction: {:?}", e),
+    }
+}
+
+pub fn get_awaiting_second_approval_flow(conn: &mut diesel::PgConnection) {
+    match get_awaiting_second_approval(conn) {
+        Ok(queue) => {
+            if queue.is_empty() {
+                println!("No transactions are awaiting a second approval");
+            }
+            for (pending_transaction, approvals) in queue {
+                let approvers: Vec<&str> = approvals.iter().map(|a| a.approved_by.as_str()).collect();
+                println!("{:#?}", pending_transaction);
+                println!("Approved so far by: {}", approvers.join(", "));
+            }
+        }
+        Err(e) => println!("Failed to get approval queue: {:?}", e),
+    }
+}
+
+pub fn request_admin_adjustment_flow(conn: &mut diesel::PgConnection, operator: &str) {
+    let Some(account_id) = read_account_id(conn, "Enter the account number or account ID to credit: ") else { return };
+    if !validate_account_id(account_id, conn) {
+        println!("Account does not exist.");
+        return;
+    }
+
+    let mut currency = String::new();
+    print!("Enter the currency: ");
+    io::stdout().flush().unwrap();
+    io::stdin().read_line(&mut currency).unwrap();
+    let currency = currency.trim();
+    if !validate_currency(currency) {
+        println!("Invalid currency format. Please enter a valid currency code (e.g., USD, EUR).");
+        return;
+    }
+
+    let mut amount = String::new();
+    print!("Enter the amount: ");
+    io::stdout().flush().unwrap();
+    io::stdin().read_line(&mut amount).unwrap();
+    let amount: f64 = match amount.trim().parse() {
+        Ok(a) => a,
+        Err(_) => {
+            println!("Invalid amount. Please enter a valid number.");
+            return;
+        }
+    };
+    if !validate_amount(amount) {
+        println!("Invalid amount. Please enter a valid number.");
+        return;
+    }
+
+    match request_admin_adjustment(conn, operator, account_id, amount, currency, MissingSubAccountPolicy::from_env()) {
+        Ok(pending_transaction) => println!("Adjustment raised and awaiting approval by another operator: {:#?}", pending_transaction),
+        Err(e) => println!("Failed to raise adjustment: {:?}", e),
+    }
+}
+
+pub fn get_approval_thresholds_flow(conn: &mut diesel::PgConnection) {
+    match get_approval_thresholds(conn) {
+        Ok(thresholds) => println!("Dual approval thresholds: {:#?}", thresholds),
+        Err(e) => println!("Failed to get approval thresholds: {:?}", e),
+    }
+}
+
+pub fn get_stuck_outbox_messages_flow(conn: &mut diesel::PgConnection) {
+    match get_stuck_outbox_messages(conn) {
+        Ok(messages) if messages.is_empty() => println!("No stuck outbox messages"),
+        Ok(messages) => {
+            for message in messages {
+                println!("{} [{}] {} for {:?}: {} attempt(s), next attempt {}, last error: {}",
+                    message.id, message.status, message.event_type, message.account_id, message.attempt_count,
+                    message.next_attempt_at.format("%Y-%m-%d %H:%M:%S UTC"), message.last_error.as_deref().unwrap_or("-"));
+            }
+        }
+        Err(e) => println!("Failed to get outbox messages: {:?}", e),
+    }
+}
+
+pub fn requeue_outbox_message_flow(conn: &mut diesel::PgConnection) {
+    let mut message_id = String::new();
+    print!("Enter the ID of the dead outbox message to retry: ");
+    io::stdout().flush().unwrap();
+    io::stdin().read_line(&mut message_id).unwrap();
+    let message_id: Uuid = match message_id.trim().parse() {
+        Ok(id) => id,
+        Err(_) => {
+            println!("Invalid message ID.");
+            return;
+        }
+    };
+
+    match requeue_outbox_message(conn, message_id) {
+        Ok(_) => println!("Message queued for delivery again"),
+        Err(diesel::result::Error::NotFound) => println!("No dead outbox message with that ID"),
+        Err(e) => println!("Failed to retry outbox message: {:?}", e),
+    }
+}
+
+// A second operator's credentials, asked for once there are two operators to confirm a change
+fn read_co_signer(conn: &mut diesel::PgConnection) -> Option<(String, String)> {
+    if count_operators(conn).unwrap_or(0) < 2 {
+        return None;
+    }
+    let mut co_signer = String::new();
+    print!("A second operator must confirm this. Enter their username: ");
+    io::stdout().flush().unwrap();
+    io::stdin().read_line(&mut co_signer).unwrap();
+    print!("Enter their password: ");
+    io::stdout().flush().unwrap();
+    let password = rpassword::read_password().unwrap();
+    Some((co_signer.trim().to_string(), password))
+}
+
+pub fn set_approval_threshold_flow(conn: &mut diesel::PgConnection, operator: &str) {
+    let mut currency = String::new();
+    print!("Enter the currency: ");
+    io::stdout().flush().unwrap();
+    io::stdin().read_line(&mut currency).unwrap();
+    let currency = currency.trim();
+    if !validate_currency(currency) {
+        println!("Invalid currency format. Please enter a valid currency code (e.g., USD, EUR).");
+        return;
+    }
+
+    let mut amount = String::new();
+    print!("Enter the amount at or above which two approvals are required: ");
+    io::stdout().flush().unwrap();
+    io::stdin().read_line(&mut amount).unwrap();
+    let amount: f64 = match amount.trim().parse() {
+        Ok(a) => a,
+        Err(_) => {
+            println!("Invalid amount. Please enter a valid number.");
+            return;
+        }
+    };
+    if !validate_amount(amount) {
+        println!("Invalid amount. Please enter a valid number.");
+        return;
+    }
+
+    let co_signer = read_co_signer(conn);
+    let co_signer = co_signer.as_ref().map(|(username, password)| CoSigner { username, password });
+    match set_approval_threshold(conn, operator, co_signer.as_ref(), currency, amount) {
+        Ok(threshold) => println!("Approval threshold set: {:#?}", threshold),
+        Err(diesel::result::Error::RollbackTransaction) => {
+            println!("A different operator must confirm the change with their own password.")
+        }
+        Err(e) => println!("Failed to set approval threshold: {:?}", e),
+    }
+}
+
+pub fn add_operator_flow(conn: &mut diesel::PgConnection, operator: &str) {
+    let mut username = String::new();
+    print!("Enter the new operator's username: ");
+    io::stdout().flush().unwrap();
+    io::stdin().read_line(&mut username).unwrap();
+    let username = username.trim();
+    if username.is_empty() {
+        println!("Username cannot be empty.");
+        return;
+    }
+
+    print!("Enter the new operator's password: ");
+    io::stdout().flush().unwrap();
+    let password = rpassword::read_password().unwrap();
+
+    let mut holds_account = String::new();
+    print!("Does the new operator hold a customer account? (y/n): ");
+    io::stdout().flush().unwrap();
+    io::stdin().read_line(&mut holds_account).unwrap();
+    let account = if holds_account.trim().eq_ignore_ascii_case("y") {
+        match read_account_id(conn, "Enter their account ID or account number: ") {
+            Some(account) => Some(account),
+            None => return,
+        }
+    } else {
+        None
+    };
+
+    let co_signer = read_co_signer(conn);
+    let co_signer = co_signer.as_ref().map(|(username, password)| CoSigner { username, password });
+    match add_operator(conn, operator, co_signer.as_ref(), username, &password, account) {
+        Ok(_) => println!("Operator added"),
+        Err(diesel::result::Error::RollbackTransaction) => {
+            println!("A different operator must confirm the new operator with their own password.")
+        }
+        Err(e) => println!("Failed to add operator: {:?}", e),
+    }
+}
+
+// Lets an operator approve and reject deposit requests from the operator chat
+pub fn map_telegram_operator_flow(conn: &mut diesel::PgConnection) {
+    match get_telegram_operators(conn) {
+        Ok(mapped) => {
+            for mapping in mapped {
+                println!("{} -> Telegram user {}", mapping.operator, mapping.telegram_user_id);
+            }
+        }
+        Err(e) => println!("Failed to get Telegram operators: {:?}", e),
+    }
+
+    let mut operator = String::new();
+    print!("Enter the operator's username: ");
+    io::stdout().flush().unwrap();
+    io::stdin().read_line(&mut operator).unwrap();
+    let operator = operator.trim();
+    if operator.is_empty() {
+        println!("Username cannot be empty.");
+        return;
+    }
+
+    let mut user_id = String::new();
+    print!("Enter their Telegram user ID (the bot's /whoami shows it), or press Enter to unmap them: ");
+    io::stdout().flush().unwrap();
+    io::stdin().read_line(&mut user_id).unwrap();
+    let user_id = user_id.trim();
+    if user_id.is_empty() {
+        match unmap_telegram_operator(conn, operator) {
+            Ok(0) => println!("{} is not mapped to a Telegram user", operator),
+            Ok(_) => println!("{} can no longer decide deposit requests from Telegram", operator),
+            Err(e) => println!("Failed to unmap operator: {:?}", e),
+        }
+        return;
+    }
+    let user_id: i64 = match user_id.parse() {
+        Ok(id) => id,
+        Err(_) => {
+            println!("Invalid Telegram user ID. Please enter a number.");
+            return;
+        }
+    };
+
+    match map_telegram_operator(conn, operator, user_id) {
+        Ok(_) => println!("{} can now approve and reject deposit requests from Telegram", operator),
+        Err(diesel::result::Error::NotFound) => println!("There is no operator named {}", operator),
+        Err(e) => println!("Failed to map operator: {:?}", e),
+    }
+}
+
+pub fn get_pending_transaction_history_flow(conn: &mut diesel::PgConnection) {
+    match get_pending_transaction_history(conn) {
+        Ok(history) => println!("Decided deposit requests: {:#?}", history),
+        Err(e) => println!("Failed to get deposit request history: {:?}", e),
+    }
+}
+
+pub fn view_my_pending_transactions_flow(conn: &mut diesel::PgConnection, account_id: Uuid) {
+    match get_account_pending_transactions(conn, account_id) {
+        Ok(requests) => println!("Deposit requests: {:#?}", requests),
+        Err(e) => println!("Failed to get deposit requests: {:?}", e),
+    }
+}
+
+pub fn cancel_pending_transaction_flow(conn: &mut diesel::PgConnection, account_id: Uuid) {
+    let mut pending_transaction_id = String::new();
+    print!("Enter the ID of the deposit request to cancel: ");
+    io::stdout().flush().unwrap();
+    io::stdin().read_line(&mut pending_transaction_id).unwrap();
+    let pending_transaction_id: Uuid = match pending_transaction_id.trim().parse() {
+        Ok(id) => id,
+        Err(_) => {
+            println!("Invalid deposit request ID. Please enter a valid UUID.");
+            return;
+        }
+    };
+
+    match cancel_pending_transaction(conn, account_id, pending_transaction_id) {
+        Ok(_) => println!("Deposit request cancelled"),
+        Err(e) => println!("Failed to cancel deposit request: {:?}", e),
+    }
+}
+
+pub fn reverse_transaction_flow(conn: &mut diesel::PgConnection) {
+    let mut transaction_id = String::new();
+    print!("Enter the ID of the transaction to reverse: ");
+    io::stdout().flush().unwrap();
+    io::stdin().read_line(&mut transaction_id).unwrap();
+    let transaction_id: Uuid = match transaction_id.trim().parse() {
+        Ok(id) => id,
+        Err(_) => {
+            println!("Invalid transaction ID. Please enter a valid UUID.");
+            return;
+        }
+    };
+
+    match reverse_transaction(conn, transaction_id) {
+        Ok(reversal) => println!("Transaction reversed: {:#?}", reversal),
+        Err(e) => println!("Failed to reverse transaction: {:?}", e),
+    }
+}
+
+pub fn refund_transaction_flow(conn: &mut diesel::PgConnection, account_id: Uuid) {
+    let mut transaction_id = String::new();
+    print!("Enter the ID of the transaction to refund: ");
+    io::stdout().flush().unwrap();
+    io::stdin().read_line(&mut transaction_id).unwrap();
+    let transaction_id: Uuid = match transaction_id.trim().parse() {
+        Ok(id) => id,
+        Err(_) => {
+            println!("Invalid transaction ID. Please enter a valid UUID.");
+            return;
+        }
+    };
+
+    let mut amount = String::new();
+    print!("Enter the amount to refund (in the currency it was sent in): ");
+    io::stdout().flush().unwrap();
+    io::stdin().read_line(&mut amount).unwrap();
+    let amount: f64 = match amount.trim().parse() {
+        Ok(a) => a,
+        Err(_) => {
+            println!("Invalid amount. Please enter a valid number.");
+            return;
+        }
+    };
+
+    if !validate_amount(amount) {
+        println!("Invalid amount. Please enter a valid number.");
+        return;
+    }
+
+    match refund_transaction(conn, account_id, transaction_id, amount) {
+        Ok(refund) => println!("Refund successful: {:#?}", refund),
+        Err(e) => println!("Refund failed: {:?}", e),
+    }
+}
+
+// Explains up front why money in this currency cannot be credited to the account under the configured policy
+fn check_can_receive(conn: &mut diesel::PgConnection, account_id: Uuid, currency: &str, holder: &str) -> bool {
+    let policy = MissingSubAccountPolicy::from_env();
+    match can_receive_currency(conn, account_id, currency, policy) {
+        Ok(true) => true,
+        Ok(false) if policy == MissingSubAccountPolicy::ConvertToPrimary => {
+            println!("{} no sub-accounts to receive {} into. A sub-account must be created first.", holder, currency);
+            false
+        }
+        Ok(false) => {
+            println!("{} no {} sub-account. A {} sub-account must be created first.", holder, currency, currency);
+            false
+        }
+        Err(e) => {
+            println!("Failed to check destination sub-account: {:?}", e);
+            false
+        }
+    }
+}
+
+pub fn add_money_to_sub_account_flow(conn: &mut diesel::PgConnection, account_id: Uuid) {
+    let mut amount = String::new();
     print!("Enter the amount to add: ");
     io::stdout().flush().unwrap();
     io::stdin().read_line(&mut amount).unwrap();
@@ -513,7 +2107,20 @@ pub fn add_money_to_sub_account_flow(conn: &mut diesel::PgConnection, account_id
     io::stdout().flush().unwrap();
     io::stdin().read_line(&mut currency).unwrap();
     let currency = currency.trim();
-    match add_money_to_sub_account(conn, account_id, amount, currency) {
+    if !validate_currency(currency) {
+        println!("Invalid currency format. Please enter a valid currency code (e.g., USD, EUR).");
+        return;
+    }
+    if !check_can_receive(conn, account_id, currency, "You have") {
+        return;
+    }
+    let Some(details) = read_payment_details() else { return };
+    let result = conn.transaction(|conn| {
+        let pending_transaction = add_money_to_sub_account(conn, account_id, amount, currency, &details, MissingSubAccountPolicy::from_env())?;
+        enqueue_event(conn, EVENT_DEPOSIT_REQUESTED, Some(account_id), deposit_payload(pending_transaction.id, amount, currency))?;
+        Ok::<_, diesel::result::Error>(pending_transaction)
+    });
+    match result {
         Ok(_) => println!("Money added to sub-account"),
         Err(e) => println!("Failed to add money to sub-account: {:?}", e),
     }
@@ -0,0 +1,106 @@
+use rust_eze::notifications::NotificationDispatcher;
+use rust_eze::outbox::relay_outbox;
+use rust_eze::webhooks::WebhookSender;
+use rust_eze::recurringpayments::log_to_file;
+use clap::Parser;
+use diesel::prelude::*;
+use diesel::connection::SimpleConnection;
+use std::time::Duration;
+use tokio::signal::unix::{signal, SignalKind};
+
+#[derive(Parser)]
+#[command(name = "outbox_relay")]
+#[command(about = "Delivers queued notifications from the outbox", long_about = None)]
+struct Cli {
+    /// Deliver the messages that are due once and exit
+    #[arg(long)]
+    once: bool,
+    /// Seconds to wait between polls when the outbox has nothing due
+    #[arg(long, default_value_t = 5)]
+    poll_interval: u64,
+    /// Most messages to deliver in one batch
+    #[arg(long, default_value_t = 100)]
+    batch_size: i64,
+}
+
+fn connect() -> Option<PgConnection> {
+    dotenvy::dotenv().ok();
+    let database_url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+    match PgConnection::establish(&database_url) {
+        Ok(conn) => Some(conn),
+        Err(e) => {
+            log_to_file(&format!("Failed to connect to the database: {:?}", e));
+            None
+        }
+    }
+}
+
+// Delivers one batch; returns whether a full batch was found, meaning more may be waiting
+fn relay_batch(conn: &mut PgConnection, dispatcher: &NotificationDispatcher, webhooks: &WebhookSender, batch_size: i64) -> Result<bool, diesel::result::Error> {
+    // The notifiers block, so run them off the async worker
+    let stats = tokio::task::block_in_place(|| relay_outbox(conn, dispatcher, webhooks, batch_size))?;
+    if stats.delivered + stats.retrying + stats.dead > 0 {
+        log_to_file(&format!("Outbox relay: {} delivered, {} to retry, {} dead-lettered", stats.delivered, stats.retrying, stats.dead));
+    }
+    Ok((stats.delivered + stats.retrying + stats.dead) as i64 >= batch_size)
+}
+
+async fn run_daemon(dispatcher: NotificationDispatcher, webhooks: WebhookSender, poll_interval: Duration, batch_size: i64) {
+    log_to_file("Starting outbox relay");
+    let mut sigterm = signal(SignalKind::terminate()).expect("Failed to install SIGTERM handler");
+    let mut conn: Option<PgConnection> = None;
+
+    loop {
+        if conn.is_none() {
+            conn = connect();
+        }
+        let mut more_waiting = false;
+        if let Some(c) = conn.as_mut() {
+            match relay_batch(c, &dispatcher, &webhooks, batch_size) {
+                Ok(full_batch) => more_waiting = full_batch,
+                Err(e) => {
+                    log_to_file(&format!("Error relaying outbox messages: {:?}", e));
+                    if c.batch_execute("SELECT 1").is_err() {
+                        conn = None;
+                    }
+                }
+            }
+        }
+
+        let wait = if more_waiting { Duration::from_secs(0) } else { poll_interval };
+        tokio::select! {
+            _ = tokio::time::sleep(wait) => {}
+            _ = sigterm.recv() => break,
+            _ = tokio::signal::ctrl_c() => break,
+        }
+    }
+    log_to_file("Outbox relay stopped");
+}
+
+#[tokio::main]
+async fn main() {
+    let cli = Cli::parse();
+    let dispatcher = NotificationDispatcher::from_env();
+    let webhooks = WebhookSender::new();
+
+    if cli.once {
+        let mut conn = match connect() {
+            Some(conn) => conn,
+            None => std::process::exit(1),
+        };
+        // Keep going until nothing due is left
+        loop {
+            match relay_batch(&mut conn, &dispatcher, &webhooks, cli.batch_size) {
+                Ok(true) => continue,
+                Ok(false) => break,
+                Err(e) => {
+                    log_to_file(&format!("Error relaying outbox messages: {:?}", e));
+                    std::process::exit(1);
+                }
+            }
+        }
+        return;
+    }
+
+    run_daemon(dispatcher, webhooks, Duration::from_secs(cli.poll_interval), cli.batch_size).await;
+}
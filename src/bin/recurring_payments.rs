@@ -1,12 +1,242 @@
-use rust_eze::recurringpayments::{establish_connection, process_scheduled_transactions, log_to_file};
-use tokio;
-#[tokio::main]
-async fn main() {
+use rust_eze::moneytransfer::MissingSubAccountPolicy;
+use rust_eze::recurringpayments::{establish_connection, process_scheduled_transactions, log_to_file, try_acquire_scheduler_lock, holds_scheduler_lock, release_scheduler_lock, forecast_scheduled_transactions, last_day_of_month, check_upcoming_balances};
+use chrono::{Datelike, NaiveDate, NaiveTime, Utc};
+use clap::Parser;
+use diesel::prelude::*;
+use diesel::connection::SimpleConnection;
+use std::time::Duration;
+use tokio::signal::unix::{signal, SignalKind};
+
+#[derive(Parser)]
+#[command(name = "recurring_payments")]
+#[command(about = "Executes due scheduled transactions", long_about = None)]
+struct Cli {
+    /// Keep running and process scheduled transactions at the configured times
+    #[arg(long, conflicts_with_all = ["once", "dry_run"])]
+    daemon: bool,
+    /// Process due scheduled transactions once and exit (the default)
+    #[arg(long)]
+    once: bool,
+    /// Times of day (UTC, HH:MM) at which the daemon processes payments, e.g. --run-at 00:00,12:00
+    #[arg(long, value_delimiter = ',')]
+    run_at: Vec<String>,
+    /// Without --run-at, process payments every this many minutes so local execution times in every zone are met
+    #[arg(long, default_value_t = 60, conflicts_with = "run_at")]
+    every: u32,
+    /// Simulate the upcoming scheduled transactions without executing them and report predicted shortfalls
+    #[arg(long)]
+    dry_run: bool,
+    /// Last date (YYYY-MM-DD) to include in a dry run, defaults to the end of the current month
+    #[arg(long, requires = "dry_run")]
+    until: Option<NaiveDate>,
+    /// Seconds a standby instance waits before trying to take over the leader lock
+    #[arg(long, default_value_t = 60)]
+    standby_interval: u64,
+}
+
+// Executes due payments off the async workers; transfers may make blocking HTTP calls for exchange rates
+fn process_batch(conn: &mut PgConnection, policy: MissingSubAccountPolicy) -> Result<(), diesel::result::Error> {
+    tokio::task::block_in_place(|| process_scheduled_transactions(conn, policy))
+}
+
+async fn run_once(policy: MissingSubAccountPolicy) {
     log_to_file("Starting recurring payments process");
     let mut conn = establish_connection();
-    match process_scheduled_transactions(&mut conn).await {
+    // Never run alongside a daemon or another cron invocation
+    match try_acquire_scheduler_lock(&mut conn) {
+        Ok(true) => {}
+        Ok(false) => {
+            log_to_file("Another instance holds the scheduler lock, exiting");
+            return;
+        }
+        Err(e) => {
+            log_to_file(&format!("Failed to acquire scheduler lock: {:?}", e));
+            return;
+        }
+    }
+    match process_batch(&mut conn, policy) {
         Ok(_) => log_to_file("Scheduled transactions processed successfully"),
         Err(e) => log_to_file(&format!("Error processing scheduled transactions: {:?}", e)),
     }
+    check_balances(&mut conn, policy);
+    let _ = release_scheduler_lock(&mut conn);
     log_to_file("Recurring payments process completed");
-} 
\ No newline at end of file
+}
+
+// Warn about, or top up, sub-accounts that cannot cover the next few days of scheduled payments. Top-ups
+// convert between currencies with blocking HTTP calls, so this also runs off the async workers
+fn check_balances(conn: &mut PgConnection, policy: MissingSubAccountPolicy) {
+    match tokio::task::block_in_place(|| check_upcoming_balances(conn, policy)) {
+        Ok(_) => log_to_file("Upcoming scheduled payment balances checked"),
+        Err(e) => log_to_file(&format!("Error checking upcoming balances: {:?}", e)),
+    }
+}
+
+fn run_dry_run(until: Option<NaiveDate>, policy: MissingSubAccountPolicy) {
+    let today = Utc::now().naive_utc().date();
+    let until = until.unwrap_or_else(|| {
+        NaiveDate::from_ymd_opt(today.year(), today.month(), last_day_of_month(today.year(), today.month())).unwrap()
+    });
+    let mut conn = establish_connection();
+
+    let forecast = match forecast_scheduled_transactions(&mut conn, until, policy) {
+        Ok(forecast) => forecast,
+        Err(e) => {
+            eprintln!("Failed to forecast scheduled transactions: {:?}", e);
+            std::process::exit(1);
+        }
+    };
+
+    println!("Scheduled payments until {} (dry run, nothing executed):", until);
+    for entry in &forecast.entries {
+        let outcome = if entry.will_succeed { "ok" } else { "WILL FAIL" };
+        println!("  {} {} {:.2} {} from {} to {} -> {} (balance after: {:.2})", entry.date, entry.schedule_id,
+            entry.amount, entry.currency, entry.from_account_id, entry.to_account_id, outcome, entry.balance_after);
+    }
+
+    println!("Projected balances of paying sub-accounts:");
+    for account in &forecast.accounts {
+        println!("  {} {}: {:.2} -> {:.2}, {} failed payment(s), shortfall {:.2}", account.account_id, account.currency,
+            account.starting_balance, account.ending_balance, account.failed_payments, account.shortfall);
+    }
+}
+
+// Run times every `every` minutes from midnight UTC
+fn interval_run_times(every: u32) -> Vec<NaiveTime> {
+    let every = every.clamp(1, 24 * 60);
+    (0..24 * 60)
+        .step_by(every as usize)
+        .map(|minute| NaiveTime::from_hms_opt(minute / 60, minute % 60, 0).unwrap())
+        .collect()
+}
+
+// How long to sleep until the next configured run time
+fn until_next_run(run_times: &[NaiveTime]) -> Duration {
+    let now = Utc::now().naive_utc();
+    let next = run_times
+        .iter()
+        .map(|time| {
+            let today = now.date().and_time(*time);
+            if today > now { today } else { today + chrono::Duration::days(1) }
+        })
+        .min()
+        .unwrap();
+    (next - now).to_std().unwrap_or(Duration::from_secs(0))
+}
+
+fn connect() -> Option<PgConnection> {
+    dotenvy::dotenv().ok();
+    let database_url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+    match PgConnection::establish(&database_url) {
+        Ok(conn) => Some(conn),
+        Err(e) => {
+            log_to_file(&format!("Failed to connect to the database: {:?}", e));
+            None
+        }
+    }
+}
+
+async fn run_daemon(run_times: Vec<NaiveTime>, standby_interval: Duration, policy: MissingSubAccountPolicy) {
+    log_to_file(&format!("Starting recurring payments daemon, running at {:?} UTC", run_times));
+    let mut sigterm = signal(SignalKind::terminate()).expect("Failed to install SIGTERM handler");
+    let mut conn: Option<PgConnection> = None;
+    let mut leader = false;
+
+    loop {
+        // Become leader (or stay standby) before the next run so only one instance ever executes payments
+        if !leader {
+            if conn.is_none() {
+                conn = connect();
+            }
+            if let Some(c) = conn.as_mut() {
+                match try_acquire_scheduler_lock(c) {
+                    Ok(true) => {
+                        leader = true;
+                        log_to_file("Acquired scheduler lock, this instance is the leader");
+                    }
+                    Ok(false) => {}
+                    Err(e) => {
+                        log_to_file(&format!("Lost database connection while acquiring scheduler lock: {:?}", e));
+                        conn = None;
+                    }
+                }
+            }
+        }
+
+        let wait = if leader { until_next_run(&run_times) } else { standby_interval };
+        tokio::select! {
+            _ = tokio::time::sleep(wait) => {}
+            _ = sigterm.recv() => break,
+            _ = tokio::signal::ctrl_c() => break,
+        }
+
+        if !leader {
+            continue;
+        }
+        let c = conn.as_mut().unwrap();
+        // Another instance may have taken over since the last run, e.g. after the session was reset; only the holder pays
+        match holds_scheduler_lock(c) {
+            Ok(true) => {}
+            Ok(false) => {
+                log_to_file("Lost the scheduler lock, returning to standby");
+                leader = false;
+                continue;
+            }
+            Err(e) => {
+                log_to_file(&format!("Lost database connection while checking the scheduler lock: {:?}", e));
+                conn = None;
+                leader = false;
+                continue;
+            }
+        }
+        log_to_file("Processing scheduled transactions");
+        // A signal received while processing is handled after the batch finishes
+        match process_batch(c, policy) {
+            Ok(_) => {
+                log_to_file("Scheduled transactions processed successfully");
+                check_balances(c, policy);
+            }
+            Err(e) => {
+                log_to_file(&format!("Error processing scheduled transactions: {:?}", e));
+                // The lock may have gone with the connection; reconnect and contend for it again
+                if c.batch_execute("SELECT 1").is_err() {
+                    conn = None;
+                    leader = false;
+                }
+            }
+        }
+    }
+
+    if let (true, Some(c)) = (leader, conn.as_mut()) {
+        let _ = release_scheduler_lock(c);
+    }
+    log_to_file("Recurring payments daemon stopped");
+}
+
+#[tokio::main]
+async fn main() {
+    let cli = Cli::parse();
+
+    let policy = MissingSubAccountPolicy::from_env();
+    if cli.dry_run {
+        run_dry_run(cli.until, policy);
+        return;
+    }
+
+    if !cli.daemon {
+        run_once(policy).await;
+        return;
+    }
+
+    let mut run_times = if cli.run_at.is_empty() { interval_run_times(cli.every) } else { Vec::new() };
+    for time in &cli.run_at {
+        match NaiveTime::parse_from_str(time.trim(), "%H:%M") {
+            Ok(time) => run_times.push(time),
+            Err(_) => {
+                eprintln!("Invalid run time {:?}, expected HH:MM", time);
+                std::process::exit(2);
+            }
+        }
+    }
+    run_daemon(run_times, Duration::from_secs(cli.standby_interval), policy).await;
+}
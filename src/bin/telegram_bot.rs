@@ -0,0 +1,451 @@
+use rust_eze::approvals::{get_approvals, required_approvals};
+use rust_eze::database::{view_scheduled_transactions, get_account_holder_name, get_sub_accounts};
+use rust_eze::models::{PendingTransaction, PENDING_STATUS_PENDING, PENDING_STATUS_APPROVED, PENDING_STATUS_REJECTED, PENDING_STATUS_CANCELLED};
+use rust_eze::moneytransfer::{get_transactions, transaction_direction, get_pending_transaction, approve_pending_transaction, reject_pending_transaction, MissingSubAccountPolicy};
+use rust_eze::payees::{resolve_recipient, has_paid_before};
+use rust_eze::recurringpayments::log_to_file;
+use rust_eze::telegram::{telegram_bot, redeem_telegram_link_code, get_linked_account, unlink_telegram, create_telegram_transfer, confirm_telegram_transfer, decline_telegram_transfer, TRANSFER_CONFIRM_MINUTES};
+use rust_eze::telegram::{operator_chat_id, get_telegram_operator, get_unannounced_pending_transactions, record_approval_message, get_stale_approval_messages, close_approval_message};
+use rust_eze::templates::format_money;
+use diesel::prelude::*;
+use diesel::r2d2::{ConnectionManager, Pool};
+use diesel::result::{DatabaseErrorKind, Error as DieselError};
+use std::time::Duration;
+use teloxide::prelude::*;
+use teloxide::types::{InlineKeyboardButton, InlineKeyboardMarkup, MessageId};
+use teloxide::utils::command::BotCommands;
+use uuid::Uuid;
+
+// Updates are handled concurrently, each with its own connection from the pool
+type Db = Pool<ConnectionManager<PgConnection>>;
+
+// Most database connections the bot holds at once, from TELEGRAM_DB_POOL_SIZE (default 8)
+fn db_pool_size() -> u32 {
+    std::env::var("TELEGRAM_DB_POOL_SIZE").ok().and_then(|size| size.parse::<u32>().ok()).filter(|size| *size >= 1).unwrap_or(8)
+}
+// How many transactions /history shows
+const HISTORY_LENGTH: usize = 10;
+// How often new deposit requests are posted to the operator chat
+const ANNOUNCE_INTERVAL_SECONDS: u64 = 10;
+// Shown to the account holder when a deposit is rejected from Telegram, where there is no room to type a reason
+const TELEGRAM_REJECTION_REASON: &str = "Rejected by an operator";
+
+#[derive(BotCommands, Clone)]
+#[command(rename_rule = "lowercase", description = "Rust EZE commands:")]
+enum Command {
+    #[command(description = "show this help")]
+    Help,
+    #[command(description = "how to link your account")]
+    Start,
+    #[command(description = "link your account with the code from the CLI: /link CODE")]
+    Link(String),
+    #[command(description = "show your balances")]
+    Balance,
+    #[command(description = "show your recent transactions")]
+    History,
+    #[command(description = "show your scheduled payments")]
+    Scheduled,
+    #[command(description = "send money: /transfer RECIPIENT AMOUNT CURRENCY, by payee nickname, username, account number or account ID")]
+    Transfer(String),
+    #[command(description = "unlink this Telegram account")]
+    Unlink,
+    #[command(description = "show your Telegram user ID, which an admin needs to map you to an operator")]
+    Whoami,
+}
+
+// Runs a database call on a pooled connection off the async workers; transfers may also make blocking HTTP calls
+// for exchange rates. Failing to get a connection is reported like a database error.
+fn with_db<T>(db: &Db, f: impl FnOnce(&mut PgConnection) -> Result<T, DieselError>) -> Result<T, DieselError> {
+    tokio::task::block_in_place(|| {
+        let mut conn = db.get().map_err(|e| DieselError::DatabaseError(DatabaseErrorKind::ClosedConnection, Box::new(e.to_string())))?;
+        f(&mut conn)
+    })
+}
+
+const NOT_LINKED: &str = "This Telegram account is not linked. Choose \"Link Telegram\" in the Rust EZE CLI and send /link CODE here.";
+
+fn command_reply(db: &Db, user_id: i64, chat_id: i64, command: &Command) -> (String, Option<InlineKeyboardMarkup>) {
+    let account = match command {
+        Command::Help => return (Command::descriptions().to_string(), None),
+        Command::Start => return (format!("Welcome to Rust EZE. {}", NOT_LINKED), None),
+        Command::Whoami => return (format!("Your Telegram user ID is {}", user_id), None),
+        Command::Link(code) => {
+            let reply = match with_db(db, |conn| redeem_telegram_link_code(conn, code, user_id, chat_id)) {
+                Ok(_) => "Your account is linked. Try /balance.".to_string(),
+                Err(diesel::result::Error::NotFound) => "That code is wrong or has expired. Get a new one from the CLI.".to_string(),
+                Err(e) => format!("Failed to link your account: {:?}", e),
+            };
+            return (reply, None);
+        }
+        _ => match with_db(db, |conn| get_linked_account(conn, user_id)) {
+            Ok(Some(account)) => account,
+            Ok(None) => return (NOT_LINKED.to_string(), None),
+            Err(e) => return (format!("Something went wrong: {:?}", e), None),
+        },
+    };
+
+    let reply = match command {
+        Command::Balance => match with_db(db, |conn| get_sub_accounts(conn, account)) {
+            Ok(subs) if subs.is_empty() => "You have no sub-accounts yet.".to_string(),
+            Ok(subs) => subs.iter().map(|sub| format!("{}: {}", sub.currency, format_money(sub.balance, &sub.currency))).collect::<Vec<_>>().join("\n"),
+            Err(e) => format!("Failed to get your balances: {:?}", e),
+        },
+        Command::History => match with_db(db, |conn| Ok((get_transactions(conn, account)?, get_sub_accounts(conn, account)?))) {
+            Ok((mut transactions, subs)) if !transactions.is_empty() => {
+                let own_subs: Vec<Uuid> = subs.iter().map(|sub| sub.id).collect();
+                transactions.sort_by_key(|t| std::cmp::Reverse(t.transaction_date));
+                transactions.iter().take(HISTORY_LENGTH)
+                    .map(|t| {
+                        let line = format!("{} {} {} {}", t.transaction_date.format("%Y-%m-%d %H:%M"), transaction_direction(t, &own_subs),
+                            format_money(t.amount, &t.transfer_currency), t.transfer_currency);
+                        match &t.memo {
+                            Some(memo) => format!("{} {}", line, memo),
+                            None => line,
+                        }
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            }
+            Ok(_) => "You have no transactions yet.".to_string(),
+            Err(e) => format!("Failed to get your transactions: {:?}", e),
+        },
+        Command::Scheduled => match with_db(db, |conn| view_scheduled_transactions(conn, account)) {
+            Ok(schedules) if !schedules.is_empty() => schedules.iter()
+                .map(|s| format!("{} {} to {} next on {} ({})", format_money(s.amount, &s.currency), s.currency, s.to_account_id,
+                    s.scheduled_date.format("%Y-%m-%d %H:%M UTC"), s.recurrence.as_deref().unwrap_or("once")))
+                .collect::<Vec<_>>()
+                .join("\n"),
+            Ok(_) => "You have no scheduled payments.".to_string(),
+            Err(e) => format!("Failed to get your scheduled payments: {:?}", e),
+        },
+        Command::Transfer(args) => return transfer_reply(db, account, args),
+        Command::Unlink => match with_db(db, |conn| unlink_telegram(conn, account)) {
+            Ok(_) => "This Telegram account is no longer linked.".to_string(),
+            Err(e) => format!("Failed to unlink: {:?}", e),
+        },
+        Command::Help | Command::Start | Command::Link(_) | Command::Whoami => unreachable!(),
+    };
+    (reply, None)
+}
+
+// Records the transfer and asks the user to confirm it with inline buttons
+fn transfer_reply(db: &Db, account: Uuid, args: &str) -> (String, Option<InlineKeyboardMarkup>) {
+    const USAGE: &str = "Usage: /transfer RECIPIENT AMOUNT CURRENCY, where RECIPIENT is a payee nickname, username, account number or account ID. The currency can be left out for payees with a default currency.";
+    let parts: Vec<&str> = args.split_whitespace().collect();
+    let (to, amount, currency) = match parts.as_slice() {
+        [to, amount, currency] => (*to, amount.parse::<f64>().ok(), Some(currency.to_string())),
+        [to, amount] => (*to, amount.parse::<f64>().ok(), None),
+        _ => return (USAGE.to_string(), None),
+    };
+    let Some(amount) = amount else { return (USAGE.to_string(), None) };
+
+    let recipient = match with_db(db, |conn| resolve_recipient(conn, account, to)) {
+        Ok(recipient) => recipient,
+        Err(diesel::result::Error::NotFound) => return ("No payee, user or account matches that recipient.".to_string(), None),
+        Err(e) => return (format!("Failed to look up the recipient: {:?}", e), None),
+    };
+    let Some(currency) = currency.or(recipient.default_currency.clone()) else { return (USAGE.to_string(), None) };
+    let first_payment = !with_db(db, |conn| has_paid_before(conn, account, recipient.account_id)).unwrap_or(false);
+
+    match with_db(db, |conn| create_telegram_transfer(conn, account, recipient.account_id, amount, &currency)) {
+        Ok(transfer) => {
+            let buttons = InlineKeyboardMarkup::new(vec![vec![
+                InlineKeyboardButton::callback("Confirm", format!("confirm:{}", transfer.id)),
+                InlineKeyboardButton::callback("Decline", format!("decline:{}", transfer.id)),
+            ]]);
+            let warning = if first_payment { "\nYou have not paid them before." } else { "" };
+            (format!("Send {} {} to {} ({})?{}\nThis request expires in {} minutes.",
+                format_money(transfer.amount, &transfer.currency), transfer.currency, recipient.holder_name, transfer.to_account_id, warning, TRANSFER_CONFIRM_MINUTES), Some(buttons))
+        }
+        Err(diesel::result::Error::NotFound) => ("There is no account with that ID.".to_string(), None),
+        Err(diesel::result::Error::RollbackTransaction) => ("The amount must be positive and the recipient must be another account.".to_string(), None),
+        Err(e) => (format!("Failed to start the transfer: {:?}", e), None),
+    }
+}
+
+async fn handle_command(bot: Bot, msg: Message, command: Command, db: Db) -> ResponseResult<()> {
+    let user_id = match msg.from() {
+        Some(user) => user.id.0 as i64,
+        None => return Ok(()),
+    };
+    let (reply, buttons) = command_reply(&db, user_id, msg.chat.id.0, &command);
+    let request = bot.send_message(msg.chat.id, reply);
+    match buttons {
+        Some(buttons) => request.reply_markup(buttons).await?,
+        None => request.await?,
+    };
+    Ok(())
+}
+
+async fn handle_callback(bot: Bot, query: CallbackQuery, db: Db) -> ResponseResult<()> {
+    let decision = query.data.as_deref()
+        .and_then(|data| data.split_once(':'))
+        .and_then(|(action, id)| id.parse::<Uuid>().ok().map(|id| (action.to_string(), id)));
+    match decision {
+        Some((action, pending_transaction_id)) if action == "approve" || action == "reject" => {
+            handle_review(bot, query, db, &action, pending_transaction_id).await
+        }
+        decision => handle_transfer_decision(bot, query, db, decision).await,
+    }
+}
+
+// Confirm and Decline buttons of a transfer; only the account that started it can decide it
+async fn handle_transfer_decision(bot: Bot, query: CallbackQuery, db: Db, decision: Option<(String, Uuid)>) -> ResponseResult<()> {
+    let user_id = query.from.id.0 as i64;
+    let outcome = match (decision, with_db(&db, |conn| get_linked_account(conn, user_id))) {
+        (Some((action, transfer_id)), Ok(Some(account))) => with_db(&db, |conn| Ok(match action.as_str() {
+            "confirm" => match confirm_telegram_transfer(conn, account, transfer_id, MissingSubAccountPolicy::from_env()) {
+                Ok(transaction) => format!("Sent {} {}. Transaction {}", format_money(transaction.amount, &transaction.transfer_currency), transaction.transfer_currency, transaction.transaction_id),
+                Err(diesel::result::Error::NotFound) => "This transfer was already decided or has expired.".to_string(),
+                Err(e) => format!("The transfer failed: {:?}", e),
+            },
+            "decline" => match decline_telegram_transfer(conn, account, transfer_id) {
+                Ok(_) => "Transfer declined.".to_string(),
+                Err(diesel::result::Error::NotFound) => "This transfer was already decided or has expired.".to_string(),
+                Err(e) => format!("Failed to decline the transfer: {:?}", e),
+            },
+            _ => "Unknown action.".to_string(),
+        }))
+        .unwrap_or_else(|e| format!("Something went wrong: {:?}", e)),
+        (_, Ok(None)) => NOT_LINKED.to_string(),
+        (None, _) => "Unknown action.".to_string(),
+        (_, Err(e)) => format!("Something went wrong: {:?}", e),
+    };
+
+    bot.answer_callback_query(query.id.clone()).await?;
+    // Replace the question so the buttons cannot be pressed again
+    match query.message {
+        Some(message) => bot.edit_message_text(message.chat.id, message.id, outcome).await?,
+        None => bot.send_message(query.from.id, outcome).await?,
+    };
+    Ok(())
+}
+
+// What is being asked for, without its approvals
+fn review_summary(conn: &mut PgConnection, pending_transaction: &PendingTransaction) -> String {
+    let kind = match &pending_transaction.initiated_by {
+        Some(operator) => format!("Admin adjustment raised by {}", operator),
+        None => "Deposit request".to_string(),
+    };
+    let holder = pending_transaction.account_id_to_add
+        .map(|account| match get_account_holder_name(conn, account) {
+            Ok(name) => format!("{} ({})", name, account),
+            Err(_) => account.to_string(),
+        })
+        .unwrap_or_else(|| "unknown account".to_string());
+    format!("{} {}\n{} {} for {}\nRequested at {}",
+        kind, pending_transaction.id, format_money(pending_transaction.amount, &pending_transaction.transfer_currency), pending_transaction.transfer_currency, holder,
+        pending_transaction.transaction_date.format("%Y-%m-%d %H:%M UTC"))
+}
+
+fn approvals_text(conn: &mut PgConnection, pending_transaction_id: Uuid) -> String {
+    get_approvals(conn, pending_transaction_id)
+        .unwrap_or_default()
+        .iter()
+        .map(|approval| format!("\nApproved by {} at {}", approval.approved_by, approval.approved_at.format("%Y-%m-%d %H:%M UTC")))
+        .collect()
+}
+
+// The message a pending transaction is posted to the operator chat with
+fn review_request_text(conn: &mut PgConnection, pending_transaction: &PendingTransaction) -> String {
+    let mut text = review_summary(conn, pending_transaction);
+    if let Ok(2) = required_approvals(conn, &pending_transaction.transfer_currency, pending_transaction.amount) {
+        text.push_str("\nNeeds approval from two operators");
+    }
+    text.push_str(&approvals_text(conn, pending_transaction.id));
+    text
+}
+
+// The request followed by who decided it and when
+fn review_outcome_text(conn: &mut PgConnection, pending_transaction: &PendingTransaction) -> String {
+    let mut text = review_summary(conn, pending_transaction);
+    if pending_transaction.status == PENDING_STATUS_APPROVED {
+        text.push_str(&approvals_text(conn, pending_transaction.id));
+        return text;
+    }
+    let decided = pending_transaction.decided_at
+        .map(|decided| format!(" at {}", decided.format("%Y-%m-%d %H:%M UTC")))
+        .unwrap_or_default();
+    match (pending_transaction.status.as_str(), &pending_transaction.reviewed_by) {
+        (PENDING_STATUS_REJECTED, Some(reviewer)) => text.push_str(&format!("\nRejected by {}{}", reviewer, decided)),
+        (PENDING_STATUS_CANCELLED, _) => text.push_str(&format!("\nCancelled by the account holder{}", decided)),
+        (other, _) => text.push_str(&format!("\n{}{}", other, decided)),
+    }
+    if let Some(reason) = &pending_transaction.decision_reason {
+        text.push_str(&format!(": {}", reason));
+    }
+    text
+}
+
+fn review_buttons(pending_transaction_id: Uuid) -> InlineKeyboardMarkup {
+    InlineKeyboardMarkup::new(vec![vec![
+        InlineKeyboardButton::callback("Approve", format!("approve:{}", pending_transaction_id)),
+        InlineKeyboardButton::callback("Reject", format!("reject:{}", pending_transaction_id)),
+    ]])
+}
+
+// Approve and Reject buttons in the operator chat; only Telegram users mapped to an operator can use them
+async fn handle_review(bot: Bot, query: CallbackQuery, db: Db, action: &str, pending_transaction_id: Uuid) -> ResponseResult<()> {
+    let user_id = query.from.id.0 as i64;
+    let operator = match with_db(&db, |conn| get_telegram_operator(conn, user_id)) {
+        Ok(Some(operator)) => operator,
+        Ok(None) => {
+            bot.answer_callback_query(query.id).text("Only operators can decide deposit requests.").show_alert(true).await?;
+            return Ok(());
+        }
+        Err(e) => {
+            bot.answer_callback_query(query.id).text(format!("Something went wrong: {:?}", e)).show_alert(true).await?;
+            return Ok(());
+        }
+    };
+
+    let result = with_db(&db, |conn| match action {
+        "approve" => approve_pending_transaction(conn, pending_transaction_id, &operator, MissingSubAccountPolicy::from_env()),
+        _ => reject_pending_transaction(conn, pending_transaction_id, &operator, TELEGRAM_REJECTION_REASON),
+    });
+    let pending_transaction = match result {
+        Ok(pending_transaction) => {
+            bot.answer_callback_query(query.id).await?;
+            pending_transaction
+        }
+        Err(diesel::result::Error::RollbackTransaction) => {
+            bot.answer_callback_query(query.id)
+                .text("This request was already decided, or you raised it or it pays into your own account.")
+                .show_alert(true)
+                .await?;
+            // Bring the message up to date if someone else decided it in the meantime
+            match with_db(&db, |conn| get_pending_transaction(conn, pending_transaction_id)) {
+                Ok(pending_transaction) if pending_transaction.status != PENDING_STATUS_PENDING => pending_transaction,
+                _ => return Ok(()),
+            }
+        }
+        Err(e) => {
+            bot.answer_callback_query(query.id).text(format!("Failed to decide the request: {:?}", e)).show_alert(true).await?;
+            return Ok(());
+        }
+    };
+
+    let message = match query.message {
+        Some(message) => message,
+        None => return Ok(()),
+    };
+    // A first approval of a two-person review keeps the buttons for the second operator
+    if pending_transaction.status == PENDING_STATUS_PENDING {
+        let text = match with_db(&db, |conn| Ok(review_request_text(conn, &pending_transaction))) {
+            Ok(text) => text,
+            Err(e) => {
+                log_to_file(&format!("Failed to update the approval message of {}: {:?}", pending_transaction.id, e));
+                return Ok(());
+            }
+        };
+        bot.edit_message_text(message.chat.id, message.id, text).reply_markup(review_buttons(pending_transaction.id)).await?;
+    } else {
+        // Left for the next announcement round to bring up to date
+        let text = match with_db(&db, |conn| Ok(review_outcome_text(conn, &pending_transaction))) {
+            Ok(text) => text,
+            Err(e) => {
+                log_to_file(&format!("Failed to update the approval message of {}: {:?}", pending_transaction.id, e));
+                return Ok(());
+            }
+        };
+        bot.edit_message_text(message.chat.id, message.id, text).await?;
+        if let Err(e) = with_db(&db, |conn| close_approval_message(conn, pending_transaction.id)) {
+            log_to_file(&format!("Failed to close the approval message of {}: {:?}", pending_transaction.id, e));
+        }
+    }
+    Ok(())
+}
+
+// Posts pending transactions to the operator chat, and updates the messages of those decided elsewhere
+async fn announce_pending_transactions(bot: &Bot, db: &Db, chat: ChatId) {
+    match with_db(db, get_unannounced_pending_transactions) {
+        Ok(pending) => {
+            for pending_transaction in pending {
+                let text = match with_db(db, |conn| Ok(review_request_text(conn, &pending_transaction))) {
+                    Ok(text) => text,
+                    Err(e) => {
+                        log_to_file(&format!("Failed to describe deposit request {}: {:?}", pending_transaction.id, e));
+                        continue;
+                    }
+                };
+                match bot.send_message(chat, text).reply_markup(review_buttons(pending_transaction.id)).await {
+                    Ok(message) => {
+                        if let Err(e) = with_db(db, |conn| record_approval_message(conn, pending_transaction.id, chat.0, message.id.0)) {
+                            log_to_file(&format!("Failed to record the approval message of {}: {:?}", pending_transaction.id, e));
+                        }
+                    }
+                    Err(e) => {
+                        // Tried again on the next round; the other requests are still posted
+                        log_to_file(&format!("Failed to post deposit request {} to the operator chat: {:?}", pending_transaction.id, e));
+                        continue;
+                    }
+                }
+            }
+        }
+        Err(e) => log_to_file(&format!("Failed to get pending transactions: {:?}", e)),
+    }
+
+    match with_db(db, get_stale_approval_messages) {
+        Ok(stale) => {
+            for (message, pending_transaction) in stale {
+                let text = match with_db(db, |conn| Ok(review_outcome_text(conn, &pending_transaction))) {
+                    Ok(text) => text,
+                    Err(e) => {
+                        log_to_file(&format!("Failed to update the approval message of {}: {:?}", pending_transaction.id, e));
+                        continue;
+                    }
+                };
+                match bot.edit_message_text(ChatId(message.chat_id), MessageId(message.message_id), text).await {
+                    Ok(_) => {
+                        if let Err(e) = with_db(db, |conn| close_approval_message(conn, pending_transaction.id)) {
+                            log_to_file(&format!("Failed to close the approval message of {}: {:?}", pending_transaction.id, e));
+                        }
+                    }
+                    Err(e) => log_to_file(&format!("Failed to update the approval message of {}: {:?}", pending_transaction.id, e)),
+                }
+            }
+        }
+        Err(e) => log_to_file(&format!("Failed to get decided approval messages: {:?}", e)),
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    dotenvy::dotenv().ok();
+    let token = std::env::var("TELEGRAM_BOT_TOKEN").expect("TELEGRAM_BOT_TOKEN must be set");
+    let database_url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+    let db: Db = Pool::builder()
+        .max_size(db_pool_size())
+        .build(ConnectionManager::new(database_url.as_str()))
+        .unwrap_or_else(|_| panic!("Error connecting to {}", database_url));
+    let bot = telegram_bot(&token);
+
+    if let Err(e) = bot.set_my_commands(Command::bot_commands()).await {
+        log_to_file(&format!("Failed to register bot commands: {:?}", e));
+    }
+
+    match operator_chat_id() {
+        Some(chat) => {
+            let (bot, db) = (bot.clone(), db.clone());
+            tokio::spawn(async move {
+                loop {
+                    announce_pending_transactions(&bot, &db, ChatId(chat)).await;
+                    tokio::time::sleep(Duration::from_secs(ANNOUNCE_INTERVAL_SECONDS)).await;
+                }
+            });
+        }
+        None => log_to_file("TELEGRAM_OPERATOR_CHAT_ID is not set; deposit requests are not posted to Telegram"),
+    }
+
+    log_to_file("Starting Telegram bot");
+    let handler = dptree::entry()
+        .branch(Update::filter_message().filter_command::<Command>().endpoint(handle_command))
+        .branch(Update::filter_callback_query().endpoint(handle_callback));
+    Dispatcher::builder(bot, handler)
+        .dependencies(dptree::deps![db])
+        .enable_ctrlc_handler()
+        .build()
+        .dispatch()
+        .await;
+    log_to_file("Telegram bot stopped");
+}
@@ -0,0 +1,68 @@
+use actix_web::{web, App, HttpRequest, HttpResponse, HttpServer};
+use clap::Parser;
+use rust_eze::webhooks::{verify_signature, SIGNATURE_HEADER, TIMESTAMP_HEADER, EVENT_HEADER, DELIVERY_HEADER};
+
+#[derive(Parser)]
+#[command(name = "webhook_receiver")]
+#[command(about = "A local webhook endpoint that checks signatures and prints the events it receives", long_about = None)]
+struct Cli {
+    /// Address to listen on
+    #[arg(long, default_value = "127.0.0.1:8088")]
+    listen: String,
+    /// The endpoint's secret, shown when it was registered; falls back to WEBHOOK_SECRET
+    #[arg(long)]
+    secret: Option<String>,
+    /// Answer every delivery with this status instead of 200, to exercise retries
+    #[arg(long)]
+    fail_with: Option<u16>,
+}
+
+struct ReceiverConfig {
+    secret: String,
+    fail_with: Option<u16>,
+}
+
+fn header<'a>(request: &'a HttpRequest, name: &str) -> Option<&'a str> {
+    request.headers().get(name).and_then(|value| value.to_str().ok())
+}
+
+async fn receive(request: HttpRequest, body: String, config: web::Data<ReceiverConfig>) -> HttpResponse {
+    let timestamp = header(&request, TIMESTAMP_HEADER).and_then(|t| t.parse::<i64>().ok());
+    let signature = header(&request, SIGNATURE_HEADER);
+    let valid = match (timestamp, signature) {
+        (Some(timestamp), Some(signature)) => verify_signature(&config.secret, timestamp, &body, signature),
+        _ => false,
+    };
+    let event = header(&request, EVENT_HEADER).unwrap_or("-");
+    let delivery = header(&request, DELIVERY_HEADER).unwrap_or("-");
+
+    if !valid {
+        println!("REJECTED {} {}: invalid or stale signature", event, delivery);
+        return HttpResponse::Unauthorized().body("invalid signature");
+    }
+    println!("{} {} {}", event, delivery, body);
+    match config.fail_with.and_then(|code| actix_web::http::StatusCode::from_u16(code).ok()) {
+        Some(code) => HttpResponse::build(code).finish(),
+        None => HttpResponse::Ok().finish(),
+    }
+}
+
+#[actix_web::main]
+async fn main() -> std::io::Result<()> {
+    dotenvy::dotenv().ok();
+    let cli = Cli::parse();
+    let secret = match cli.secret.or_else(|| std::env::var("WEBHOOK_SECRET").ok()) {
+        Some(secret) => secret,
+        None => {
+            eprintln!("Pass --secret or set WEBHOOK_SECRET");
+            std::process::exit(2);
+        }
+    };
+    let config = web::Data::new(ReceiverConfig { secret, fail_with: cli.fail_with });
+
+    println!("Listening for webhooks on http://{}/", cli.listen);
+    HttpServer::new(move || App::new().app_data(config.clone()).default_service(web::post().to(receive)))
+        .bind(&cli.listen)?
+        .run()
+        .await
+}
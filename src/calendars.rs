@@ -0,0 +1,213 @@
+use chrono::{Datelike, Duration, NaiveDate, Weekday};
+use anyhow::{anyhow, Result as AnyResult};
+use std::collections::HashSet;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+// Longest a payment can be moved by a business-day convention; bounds how far ahead the processor looks
+pub const MAX_BUSINESS_DAY_ROLL: i64 = 10;
+
+// How a payment falling on a weekend or holiday is moved to a business day
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BusinessDayConvention {
+    // Pay on the nominal date even if it is not a business day
+    Unadjusted,
+    // Pay on the next business day
+    Following,
+    // Pay on the next business day unless that is in the next month, then the previous one
+    ModifiedFollowing,
+    // Pay on the previous business day
+    Preceding,
+}
+
+impl BusinessDayConvention {
+    pub fn parse(value: &str) -> Option<BusinessDayConvention> {
+        match value {
+            "none" => Some(BusinessDayConvention::Unadjusted),
+            "following" => Some(BusinessDayConvention::Following),
+            "modified_following" => Some(BusinessDayConvention::ModifiedFollowing),
+            "preceding" => Some(BusinessDayConvention::Preceding),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BusinessDayConvention::Unadjusted => "none",
+            BusinessDayConvention::Following => "following",
+            BusinessDayConvention::ModifiedFollowing => "modified_following",
+            BusinessDayConvention::Preceding => "preceding",
+        }
+    }
+}
+
+// Weekends plus the bank holidays of one currency
+#[derive(Debug, Clone, Default)]
+pub struct HolidayCalendar {
+    holidays: HashSet<NaiveDate>,
+    // The end of the last year the holiday list has dates for; None for weekends only
+    covered_until: Option<NaiveDate>,
+}
+
+// Directory holding one <CURRENCY>.txt holiday file per currency, from HOLIDAY_CALENDAR_DIR (default "calendars")
+pub fn calendar_dir() -> PathBuf {
+    PathBuf::from(env::var("HOLIDAY_CALENDAR_DIR").unwrap_or_else(|_| "calendars".to_string()))
+}
+
+impl HolidayCalendar {
+    pub fn weekends_only() -> Self {
+        HolidayCalendar::default()
+    }
+
+    // Holiday files list one YYYY-MM-DD date per line; blank lines and lines starting with '#' are ignored.
+    // A currency without a file only treats weekends as non-business days.
+    pub fn for_currency(currency: &str) -> AnyResult<HolidayCalendar> {
+        let path = calendar_dir().join(format!("{}.txt", currency.to_uppercase()));
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(HolidayCalendar::weekends_only()),
+            Err(e) => return Err(anyhow!("Failed to read {}: {}", path.display(), e)),
+        };
+        HolidayCalendar::parse(&contents).map_err(|e| anyhow!("{}: {}", path.display(), e))
+    }
+
+    pub fn parse(contents: &str) -> AnyResult<HolidayCalendar> {
+        let mut holidays = HashSet::new();
+        for (number, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let date = NaiveDate::parse_from_str(line, "%Y-%m-%d")
+                .map_err(|_| anyhow!("line {}: invalid date {:?}, expected YYYY-MM-DD", number + 1, line))?;
+            holidays.insert(date);
+        }
+        let covered_until = holidays.iter().map(|d| d.year()).max().and_then(|year| NaiveDate::from_ymd_opt(year, 12, 31));
+        Ok(HolidayCalendar { holidays, covered_until })
+    }
+
+    pub fn covered_until(&self) -> Option<NaiveDate> {
+        self.covered_until
+    }
+
+    // Whether the holiday list reaches the date; past it only weekends are known not to be business days
+    pub fn covers(&self, date: NaiveDate) -> bool {
+        self.covered_until.is_none_or(|until| date <= until)
+    }
+
+    pub fn is_business_day(&self, date: NaiveDate) -> bool {
+        !matches!(date.weekday(), Weekday::Sat | Weekday::Sun) && !self.holidays.contains(&date)
+    }
+
+    fn roll(&self, date: NaiveDate, step: i64) -> NaiveDate {
+        let mut current = date;
+        while !self.is_business_day(current) && (current - date).num_days().abs() < MAX_BUSINESS_DAY_ROLL {
+            current += Duration::days(step);
+        }
+        current
+    }
+
+    // The date a payment due on `date` is actually made under the convention
+    pub fn adjust(&self, date: NaiveDate, convention: BusinessDayConvention) -> NaiveDate {
+        match convention {
+            BusinessDayConvention::Unadjusted => date,
+            BusinessDayConvention::Following => self.roll(date, 1),
+            BusinessDayConvention::Preceding => self.roll(date, -1),
+            BusinessDayConvention::ModifiedFollowing => {
+                let following = self.roll(date, 1);
+                if following.month() == date.month() { following } else { self.roll(date, -1) }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap()
+    }
+
+    // Good Friday and Easter Monday 2026, a month-end holiday and Christmas
+    fn calendar() -> HolidayCalendar {
+        HolidayCalendar::parse("# test holidays\n2026-04-03\n2026-04-06\n\n2026-08-31\n2026-12-25\n").unwrap()
+    }
+
+    #[test]
+    fn parses_holidays_and_rejects_bad_lines() {
+        let calendar = calendar();
+        assert!(!calendar.is_business_day(date(2026, 4, 3)));
+        assert!(!calendar.is_business_day(date(2026, 4, 4)));
+        assert!(calendar.is_business_day(date(2026, 4, 7)));
+
+        let error = HolidayCalendar::parse("2026-01-01\n2026-13-01\n").unwrap_err();
+        assert!(error.to_string().contains("line 2"));
+    }
+
+    #[test]
+    fn unadjusted_keeps_the_date() {
+        assert_eq!(calendar().adjust(date(2026, 4, 3), BusinessDayConvention::Unadjusted), date(2026, 4, 3));
+    }
+
+    #[test]
+    fn following_rolls_over_a_holiday_weekend() {
+        let calendar = calendar();
+        // Good Friday through Easter Monday
+        assert_eq!(calendar.adjust(date(2026, 4, 3), BusinessDayConvention::Following), date(2026, 4, 7));
+        assert_eq!(calendar.adjust(date(2026, 4, 2), BusinessDayConvention::Following), date(2026, 4, 2));
+        // Saturday 2026-10-31 rolls into November
+        assert_eq!(calendar.adjust(date(2026, 10, 31), BusinessDayConvention::Following), date(2026, 11, 2));
+    }
+
+    #[test]
+    fn modified_following_stays_in_the_month() {
+        let calendar = calendar();
+        assert_eq!(calendar.adjust(date(2026, 4, 3), BusinessDayConvention::ModifiedFollowing), date(2026, 4, 7));
+        // Saturday 2026-10-31 would roll into November, so it moves back to Friday the 30th
+        assert_eq!(calendar.adjust(date(2026, 10, 31), BusinessDayConvention::ModifiedFollowing), date(2026, 10, 30));
+        // Saturday 2026-08-29 through the Monday 31st holiday would reach September, so back to the 28th
+        assert_eq!(calendar.adjust(date(2026, 8, 29), BusinessDayConvention::ModifiedFollowing), date(2026, 8, 28));
+    }
+
+    #[test]
+    fn preceding_rolls_back_across_a_month_start() {
+        let calendar = calendar();
+        assert_eq!(calendar.adjust(date(2026, 4, 6), BusinessDayConvention::Preceding), date(2026, 4, 2));
+        // Sunday 2026-11-01 goes back into October
+        assert_eq!(calendar.adjust(date(2026, 11, 1), BusinessDayConvention::Preceding), date(2026, 10, 30));
+        assert_eq!(calendar.adjust(date(2026, 12, 25), BusinessDayConvention::Preceding), date(2026, 12, 24));
+    }
+
+    #[test]
+    fn weekends_only_calendar() {
+        let calendar = HolidayCalendar::weekends_only();
+        assert_eq!(calendar.adjust(date(2026, 12, 25), BusinessDayConvention::Following), date(2026, 12, 25));
+        assert_eq!(calendar.adjust(date(2026, 12, 26), BusinessDayConvention::Following), date(2026, 12, 28));
+        assert!(calendar.covers(date(2099, 1, 1)));
+        assert_eq!(calendar.covered_until(), None);
+    }
+
+    #[test]
+    fn coverage_ends_with_the_last_listed_year() {
+        let calendar = calendar();
+        assert_eq!(calendar.covered_until(), Some(date(2026, 12, 31)));
+        assert!(calendar.covers(date(2026, 12, 31)));
+        assert!(!calendar.covers(date(2027, 1, 1)));
+    }
+
+    #[test]
+    fn shipped_calendars_cover_2024_to_2027() {
+        // Pinned to the years shipped; extending the files means extending this range. Running past the last year
+        // is reported at runtime rather than failing the build.
+        for currency in ["USD", "EUR", "GBP"] {
+            let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("calendars").join(format!("{}.txt", currency));
+            let calendar = HolidayCalendar::parse(&fs::read_to_string(&path).unwrap()).unwrap();
+            assert_eq!(calendar.covered_until(), Some(date(2027, 12, 31)), "{} holidays end", currency);
+            for year in 2024..=2027 {
+                assert!(calendar.holidays.iter().any(|d| d.year() == year), "{} has no holidays in {}", currency, year);
+            }
+        }
+    }
+}
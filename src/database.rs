@@ -3,11 +3,17 @@ use diesel::pg::PgConnection;
 use uuid::Uuid;
 use dotenvy::dotenv;
 use std::env;
-use crate::models::{Account, SubAccount, UsernamePassword, ScheduledTransaction};
+use crate::models::{Account, SubAccount, UsernamePassword, ScheduledTransaction, ScheduledRun, NewScheduledRun, Operator};
+use crate::models::{SCHEDULE_KIND_RECURRING, SCHEDULE_STATUS_ACTIVE, SCHEDULE_STATUS_PAUSED, RUN_STATUS_SKIPPED};
 use crate::otp_implement::{generate_totp_secret, verify_totp_flow};
 use bcrypt::{hash, verify, DEFAULT_COST};
-use chrono::NaiveDateTime;
+use chrono::{NaiveDate, NaiveDateTime, NaiveTime, Utc};
+use crate::recurringpayments::{ScheduleOptions, Recurrence, schedule_rule};
+use crate::timezones::{account_time_zone, local_to_utc, local_date};
+use crate::accountnumbers::next_account_number;
+use crate::approvals::{CoSigner, check_second_operator};
 
+#[allow(clippy::expect_fun_call)]
 pub fn establish_connection() -> PgConnection {
     dotenv().ok();
     let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
@@ -20,10 +26,12 @@ pub fn create_account(conn: &mut PgConnection, holder_name: &str) -> Result<Acco
         return Err(diesel::result::Error::RollbackTransaction);
     }
 
+    let new_number = next_account_number(conn)?;
     use crate::schema::accounts::dsl::*;
     let new_account = crate::models::NewAccount {
         account_holder_name: holder_name,
         status: "active",
+        account_number: &new_number,
     };
 
     Ok(diesel::insert_into(accounts)
@@ -38,10 +46,12 @@ pub fn create_sub_account(conn: &mut PgConnection, account_id_temp: Uuid, curren
     if check_duplicate_sub_account(conn, account_id_temp, currency_temp) {
         return Err(diesel::result::Error::RollbackTransaction);
     }
+    let new_number = next_account_number(conn)?;
     let new_sub_account = crate::models::NewSubAccount {
         account_id: Some(account_id_temp),
         currency: currency_temp,
         balance:  balance_temp,
+        account_number: &new_number,
     };
 
     Ok(diesel::insert_into(sub_accounts)
@@ -51,6 +61,30 @@ pub fn create_sub_account(conn: &mut PgConnection, account_id_temp: Uuid, curren
         .expect("Error saving new sub account"))
 }
 
+// Chooses another of the account's sub-accounts to top this one up from before scheduled payments, or none
+pub fn set_auto_top_up(conn: &mut PgConnection, account_id_temp: Uuid, currency_temp: &str, source_currency: Option<&str>) -> Result<SubAccount, diesel::result::Error> {
+    use crate::schema::sub_accounts::dsl::*;
+    let target = sub_accounts
+        .filter(account_id.eq(account_id_temp))
+        .filter(currency.eq(currency_temp))
+        .first::<SubAccount>(conn)?;
+    let source_id = match source_currency {
+        Some(source_currency) if source_currency == currency_temp => return Err(diesel::result::Error::RollbackTransaction),
+        Some(source_currency) => Some(sub_accounts
+            .filter(account_id.eq(account_id_temp))
+            .filter(currency.eq(source_currency))
+            .select(id)
+            .first::<Uuid>(conn)?),
+        None => None,
+    };
+
+    diesel::update(sub_accounts.find(target.id))
+        .set(auto_top_up_from.eq(source_id))
+        .returning(SubAccount::as_returning())
+        .get_result(conn)
+}
+
+#[allow(clippy::len_zero, clippy::needless_bool, clippy::needless_return)]
 pub fn check_duplicate_sub_account(conn: &mut PgConnection, account_id_temp: Uuid, currency_temp: &str) -> bool {
     use crate::schema::sub_accounts::dsl::*;
     let result = sub_accounts
@@ -66,6 +100,7 @@ pub fn check_duplicate_sub_account(conn: &mut PgConnection, account_id_temp: Uui
     }
 }
 
+#[allow(clippy::len_zero, clippy::needless_bool, clippy::needless_return)]
 pub fn check_duplicate_account(conn: &mut PgConnection, holder_name: &str) -> bool {
     use crate::schema::accounts::dsl::*;
     let result = accounts
@@ -86,8 +121,30 @@ pub fn get_accounts(conn: &mut PgConnection) -> Result<Vec<Account>, diesel::res
     accounts.load::<Account>(conn)
 }
 
+pub fn get_account(conn: &mut PgConnection, account_id_to_find: Uuid) -> Result<Account, diesel::result::Error> {
+    use crate::schema::accounts::dsl::*;
+    accounts.find(account_id_to_find).first::<Account>(conn)
+}
+
+pub fn get_sub_accounts(conn: &mut PgConnection, account_id_to_view: Uuid) -> Result<Vec<SubAccount>, diesel::result::Error> {
+    use crate::schema::sub_accounts::dsl::*;
+    sub_accounts
+        .filter(account_id.eq(account_id_to_view))
+        .order(currency.asc())
+        .load::<SubAccount>(conn)
+}
+
+pub fn get_account_holder_name(conn: &mut PgConnection, account_id_to_find: Uuid) -> Result<String, diesel::result::Error> {
+    use crate::schema::accounts::dsl::*;
+    accounts
+        .find(account_id_to_find)
+        .select(account_holder_name)
+        .first(conn)
+}
+
 
 
+#[allow(clippy::get_first)]
 pub fn validate_username_password(conn: &mut PgConnection, username_to_validate: &str, password_to_validate: &str) -> Option<Uuid> {
     use crate::schema::username_password::dsl::*;
     
@@ -149,17 +206,80 @@ pub fn add_username_password(conn: &mut PgConnection, username_to_add: &str, pas
         .expect("Error saving new username password"))
 }
 
-pub fn add_scheduled_transaction(conn: &mut PgConnection, from_account_id_temp: Uuid, to_account_id_temp: Uuid, amount_temp: f64, currency_temp: &str, scheduled_date_temp: NaiveDateTime) -> Result<ScheduledTransaction, diesel::result::Error> {
+pub fn validate_operator(conn: &mut PgConnection, username_to_validate: &str, password_to_validate: &str) -> bool {
+    use crate::schema::operators::dsl::*;
+
+    match operators.find(username_to_validate).first::<Operator>(conn) {
+        Ok(operator) => verify(password_to_validate, &operator.passwd).unwrap_or(false),
+        Err(_) => false,
+    }
+}
+
+pub fn count_operators(conn: &mut PgConnection) -> Result<i64, diesel::result::Error> {
+    use crate::schema::operators::dsl::*;
+    operators.count().get_result(conn)
+}
+
+// Adds an operator, signed off by a second operator once there are two. The account is the customer account the
+// new operator holds, if any; they will not be able to approve deposits into it.
+pub fn add_operator(
+    conn: &mut PgConnection,
+    operator: &str,
+    co_signer: Option<&CoSigner>,
+    username_to_add: &str,
+    password_to_add: &str,
+    account_to_link: Option<Uuid>
+) -> Result<Operator, diesel::result::Error> {
+    check_second_operator(conn, operator, co_signer)?;
+    use crate::schema::operators::dsl::*;
+
+    let hashed_password = hash(password_to_add.as_bytes(), DEFAULT_COST)
+        .map_err(|_| diesel::result::Error::RollbackTransaction)?;
+
+    if operators.find(username_to_add).first::<Operator>(conn).is_ok() {
+        return Err(diesel::result::Error::RollbackTransaction);
+    }
+
+    let new_operator = crate::models::NewOperator {
+        username: username_to_add,
+        passwd: &hashed_password,
+        account_id: account_to_link,
+    };
+
+    diesel::insert_into(operators)
+        .values(&new_operator)
+        .returning(Operator::as_returning())
+        .get_result(conn)
+}
+
+pub fn add_scheduled_transaction(conn: &mut PgConnection, from_account_id_temp: Uuid, to_account_id_temp: Uuid, amount_temp: f64, currency_temp: &str, scheduled_date_temp: NaiveDateTime, options: &ScheduleOptions) -> Result<ScheduledTransaction, diesel::result::Error> {
     use crate::schema::scheduled_transactions::dsl::*;
-    let date_to_set = scheduled_date_temp.date();
-    let date_to_set_with_time = date_to_set.and_hms_opt(0, 0, 0).unwrap();
+    // The date and time are local to the paying account's time zone
+    let tz = account_time_zone(conn, from_account_id_temp)?;
+    let time_to_set = scheduled_date_temp.time();
+    let rule_to_set = options.recurrence.clone().map(|rule| rule.anchored_to(scheduled_date_temp.date()));
+    // The rule may not fall on the start date itself, e.g. a Mondays-only rule started on a Wednesday
+    let date_to_set = rule_to_set.as_ref().map_or(scheduled_date_temp.date(), |rule| rule.first_on_or_after(scheduled_date_temp.date()));
+    if options.occurrence_count.is_some_and(|count| count < 1) || options.end_date.is_some_and(|end| end < date_to_set) {
+        return Err(diesel::result::Error::RollbackTransaction);
+    }
+    let recurrence_to_set = rule_to_set.map(|rule| rule.to_rrule());
     let new_scheduled_transaction_temp = crate::models::NewScheduledTransaction {
         from_account_id: from_account_id_temp,
         to_account_id: to_account_id_temp,
         amount: amount_temp,
         currency: currency_temp,
-        scheduled_date: date_to_set_with_time,
+        scheduled_date: local_to_utc(tz, date_to_set, time_to_set),
         executed: false,
+        recurrence: recurrence_to_set.as_deref(),
+        end_date: options.end_date.map(|end| local_to_utc(tz, end, time_to_set)),
+        occurrence_count: options.occurrence_count,
+        kind: options.kind(),
+        catch_up_policy: options.catch_up_policy.as_str(),
+        execution_time: time_to_set,
+        business_day_convention: options.business_day_convention.as_str(),
+        memo: options.details.memo(),
+        reference: options.details.reference(),
     };
 
     Ok(diesel::insert_into(scheduled_transactions)
@@ -181,8 +301,159 @@ pub fn view_scheduled_transactions(conn: &mut PgConnection, account_id_to_view:
         .load::<ScheduledTransaction>(conn)
 }
 
-pub fn delete_scheduled_transaction(conn: &mut PgConnection, transaction_id_to_delete: Uuid) -> Result<usize, diesel::result::Error> {
+pub fn delete_scheduled_transaction(conn: &mut PgConnection, account_id_to_delete: Uuid, transaction_id_to_delete: Uuid) -> Result<usize, diesel::result::Error> {
     use crate::schema::scheduled_transactions::dsl::*;
+    get_owned_scheduled_transaction(conn, account_id_to_delete, transaction_id_to_delete)?;
     diesel::delete(scheduled_transactions.find(transaction_id_to_delete))
         .execute(conn)
-}
\ No newline at end of file
+}
+
+// Only the paying account may change a schedule
+fn get_owned_scheduled_transaction(conn: &mut PgConnection, account_id_to_check: Uuid, schedule_id: Uuid) -> Result<ScheduledTransaction, diesel::result::Error> {
+    use crate::schema::scheduled_transactions::dsl::*;
+    scheduled_transactions
+        .find(schedule_id)
+        .filter(from_account_id.eq(account_id_to_check))
+        .first::<ScheduledTransaction>(conn)
+        .optional()?
+        .ok_or(diesel::result::Error::RollbackTransaction)
+}
+
+// Finished schedules can no longer be changed
+fn get_open_scheduled_transaction(conn: &mut PgConnection, account_id_to_check: Uuid, schedule_id: Uuid) -> Result<ScheduledTransaction, diesel::result::Error> {
+    let schedule = get_owned_scheduled_transaction(conn, account_id_to_check, schedule_id)?;
+    if schedule.executed {
+        return Err(diesel::result::Error::RollbackTransaction);
+    }
+    Ok(schedule)
+}
+
+pub fn pause_scheduled_transaction(conn: &mut PgConnection, account_id_to_pause: Uuid, schedule_id: Uuid) -> Result<ScheduledTransaction, diesel::result::Error> {
+    use crate::schema::scheduled_transactions::dsl::*;
+    get_open_scheduled_transaction(conn, account_id_to_pause, schedule_id)?;
+    diesel::update(scheduled_transactions.find(schedule_id))
+        .set(status.eq(SCHEDULE_STATUS_PAUSED))
+        .returning(ScheduledTransaction::as_returning())
+        .get_result(conn)
+}
+
+// Resuming also clears the failure streak that may have paused the schedule
+pub fn resume_scheduled_transaction(conn: &mut PgConnection, account_id_to_resume: Uuid, schedule_id: Uuid) -> Result<ScheduledTransaction, diesel::result::Error> {
+    use crate::schema::scheduled_transactions::dsl::*;
+    get_open_scheduled_transaction(conn, account_id_to_resume, schedule_id)?;
+    diesel::update(scheduled_transactions.find(schedule_id))
+        .set((status.eq(SCHEDULE_STATUS_ACTIVE), consecutive_failures.eq(0)))
+        .returning(ScheduledTransaction::as_returning())
+        .get_result(conn)
+}
+
+// Skips the next occurrence, recording it as a skipped run; skipping a one-off payment finishes it
+pub fn skip_next_scheduled_occurrence(conn: &mut PgConnection, account_id_to_skip: Uuid, schedule_id: Uuid) -> Result<ScheduledTransaction, diesel::result::Error> {
+    use crate::schema::scheduled_transactions::dsl::*;
+    conn.transaction(|conn| {
+        let schedule = get_open_scheduled_transaction(conn, account_id_to_skip, schedule_id)?;
+        let tz = account_time_zone(conn, account_id_to_skip)?;
+        let skipped_date = schedule.scheduled_date;
+
+        {
+            use crate::schema::scheduled_runs::dsl::{scheduled_runs, scheduled_transaction_id, occurrence_date, status as run_status, updated_at};
+            let skipped_run = NewScheduledRun {
+                scheduled_transaction_id: schedule_id,
+                occurrence_date: skipped_date,
+                status: RUN_STATUS_SKIPPED,
+            };
+            diesel::insert_into(scheduled_runs)
+                .values(&skipped_run)
+                .on_conflict((scheduled_transaction_id, occurrence_date))
+                .do_update()
+                .set((run_status.eq(RUN_STATUS_SKIPPED), updated_at.eq(Utc::now())))
+                .execute(conn)?;
+        }
+
+        let next_date = schedule_rule(&schedule, tz).map(|rule| rule.next_after(local_date(tz, skipped_date)));
+        let finished = match next_date {
+            None => true,
+            Some(next) => schedule.end_date.is_some_and(|end| next > local_date(tz, end)),
+        };
+        diesel::update(scheduled_transactions.find(schedule_id))
+            .set((
+                scheduled_date.eq(next_date.map(|d| local_to_utc(tz, d, schedule.execution_time)).unwrap_or(skipped_date)),
+                executed.eq(finished),
+            ))
+            .returning(ScheduledTransaction::as_returning())
+            .get_result(conn)
+    })
+}
+
+// Changes to a schedule; fields left as None keep their current value
+#[derive(Debug, Clone, Default)]
+pub struct ScheduleUpdate {
+    pub amount: Option<f64>,
+    pub next_date: Option<NaiveDate>,
+    pub execution_time: Option<NaiveTime>,
+    pub recurrence: Option<Recurrence>,
+}
+
+// Edits a schedule in place so it keeps its ID and run history. Dates and times are local to the account's
+// time zone. A new recurrence, or the current one when the next date moves, is anchored to the schedule's next date;
+// a recurrence can only be set on standing orders.
+pub fn update_scheduled_transaction(conn: &mut PgConnection, account_id_to_update: Uuid, schedule_id: Uuid, changes: &ScheduleUpdate) -> Result<ScheduledTransaction, diesel::result::Error> {
+    use crate::schema::scheduled_transactions::dsl::*;
+    conn.transaction(|conn| {
+        let schedule = get_open_scheduled_transaction(conn, account_id_to_update, schedule_id)?;
+        let tz = account_time_zone(conn, account_id_to_update)?;
+        let amount_to_set = changes.amount.unwrap_or(schedule.amount);
+        let date_to_set = changes.next_date.unwrap_or(local_date(tz, schedule.scheduled_date));
+        let rule_to_set = match (&changes.recurrence, changes.next_date) {
+            (Some(rule), _) => Some(rule.clone().anchored_to(date_to_set)),
+            // A new date moves the day the schedule repeats on, as if it had been created on that date
+            (None, Some(new_date)) => schedule_rule(&schedule, tz).map(|rule| rule.reanchored_to(new_date)),
+            (None, None) => schedule_rule(&schedule, tz),
+        };
+        let date_to_set = rule_to_set.as_ref().map_or(date_to_set, |rule| rule.first_on_or_after(date_to_set));
+        let time_to_set = changes.execution_time.unwrap_or(schedule.execution_time);
+        let end_to_set = schedule.end_date.map(|end| local_date(tz, end));
+        if amount_to_set <= 0.0
+            || (changes.next_date.is_some() && date_to_set < local_date(tz, Utc::now()))
+            || end_to_set.is_some_and(|end| date_to_set > end)
+            || (changes.recurrence.is_some() && schedule.kind != SCHEDULE_KIND_RECURRING)
+        {
+            return Err(diesel::result::Error::RollbackTransaction);
+        }
+        let recurrence_to_set = match (&changes.recurrence, changes.next_date) {
+            (None, None) => schedule.recurrence.clone(),
+            _ => rule_to_set.map(|rule| rule.to_rrule()),
+        };
+
+        diesel::update(scheduled_transactions.find(schedule_id))
+            .set((
+                amount.eq(amount_to_set),
+                scheduled_date.eq(local_to_utc(tz, date_to_set, time_to_set)),
+                execution_time.eq(time_to_set),
+                end_date.eq(end_to_set.map(|end| local_to_utc(tz, end, time_to_set))),
+                recurrence.eq(recurrence_to_set),
+            ))
+            .returning(ScheduledTransaction::as_returning())
+            .get_result(conn)
+    })
+}
+
+pub fn get_scheduled_runs(conn: &mut PgConnection, account_id_to_view: Uuid, schedule_id_to_view: Uuid) -> Result<Vec<ScheduledRun>, diesel::result::Error> {
+    use crate::schema::scheduled_runs::dsl::*;
+    use crate::schema::scheduled_transactions::dsl::{scheduled_transactions, from_account_id};
+
+    // Only the paying account can see a schedule's run history
+    let owned = scheduled_transactions
+        .find(schedule_id_to_view)
+        .filter(from_account_id.eq(account_id_to_view))
+        .first::<ScheduledTransaction>(conn)
+        .optional()?;
+    if owned.is_none() {
+        return Err(diesel::result::Error::RollbackTransaction);
+    }
+
+    scheduled_runs
+        .filter(scheduled_transaction_id.eq(schedule_id_to_view))
+        .order(occurrence_date.desc())
+        .load::<ScheduledRun>(conn)
+}
@@ -4,4 +4,16 @@ pub mod database;
 pub mod moneytransfer;
 pub mod otp_implement;
 pub mod sendalert; 
-pub mod recurringpayments;
\ No newline at end of file
+pub mod recurringpayments;
+pub mod approvals;
+pub mod timezones;
+pub mod calendars;
+pub mod notifications;
+pub mod outbox;
+pub mod webhooks;
+pub mod templates;
+pub mod telegram;
+pub mod payees;
+pub mod accountnumbers;
+pub mod paymentrequests;
+pub mod transactiondetails;
@@ -1,22 +1,110 @@
 use diesel::prelude::*;
 use uuid::Uuid;
-use chrono::NaiveDateTime;
+use chrono::{DateTime, NaiveTime, Utc};
 use crate::schema::accounts;
 use crate::schema::sub_accounts;
 use crate::schema::transactions;
 use crate::schema::pending_transactions;
+use crate::schema::pending_transaction_approvals;
+use crate::schema::approval_thresholds;
+use crate::schema::operators;
+use crate::schema::notification_channels;
+use crate::schema::notification_deliveries;
+use crate::schema::outbox;
 use crate::schema::scheduled_transactions;
+use crate::schema::scheduled_runs;
 use crate::schema::username_password;
+use crate::schema::telegram_links;
+use crate::schema::telegram_link_codes;
+use crate::schema::telegram_transfers;
+use crate::schema::telegram_operators;
+use crate::schema::telegram_approval_messages;
+use crate::schema::transaction_categories;
+use crate::schema::payees;
+use crate::schema::payment_requests;
+use crate::schema::webhook_endpoints;
+use crate::schema::webhook_deliveries;
 
 pub const ADMIN_ACCOUNT_ID: Uuid = Uuid::from_u128(0);
+// Collects the FX spread charged on cross-currency payments
+pub const FX_FEE_ACCOUNT_ID: Uuid = Uuid::from_u128(1);
+
+// Lifecycle of a deposit request in `pending_transactions`
+pub const PENDING_STATUS_PENDING: &str = "pending";
+pub const PENDING_STATUS_APPROVED: &str = "approved";
+pub const PENDING_STATUS_REJECTED: &str = "rejected";
+pub const PENDING_STATUS_CANCELLED: &str = "cancelled";
+
+// Kinds of entries in `scheduled_transactions`
+pub const SCHEDULE_KIND_ONE_OFF: &str = "one_off";
+pub const SCHEDULE_KIND_RECURRING: &str = "recurring";
+
+pub const SCHEDULE_STATUS_ACTIVE: &str = "active";
+pub const SCHEDULE_STATUS_PAUSED: &str = "paused";
+
+// Outcome of one occurrence in `scheduled_runs`; a pending run has not been attempted yet
+pub const RUN_STATUS_PENDING: &str = "pending";
+pub const RUN_STATUS_SUCCEEDED: &str = "succeeded";
+pub const RUN_STATUS_RETRYING: &str = "retrying";
+pub const RUN_STATUS_FAILED: &str = "failed";
+pub const RUN_STATUS_SKIPPED: &str = "skipped";
+
+// Channels in `notification_channels`
+pub const CHANNEL_TELEGRAM: &str = "telegram";
+pub const CHANNEL_EMAIL: &str = "email";
+pub const CHANNEL_WEBHOOK: &str = "webhook";
+pub const CHANNEL_FILE: &str = "file";
+
+// Lifecycle of a transfer started in the Telegram bot
+pub const TELEGRAM_TRANSFER_AWAITING: &str = "awaiting";
+pub const TELEGRAM_TRANSFER_CONFIRMED: &str = "confirmed";
+pub const TELEGRAM_TRANSFER_DECLINED: &str = "declined";
+
+// Lifecycle of a request for money from another account. Open requests past their expiry can no longer be paid.
+pub const PAYMENT_REQUEST_OPEN: &str = "open";
+pub const PAYMENT_REQUEST_PAID: &str = "paid";
+pub const PAYMENT_REQUEST_DECLINED: &str = "declined";
+pub const PAYMENT_REQUEST_CANCELLED: &str = "cancelled";
+
+// Delivery state of a message in `outbox`
+pub const OUTBOX_STATUS_PENDING: &str = "pending";
+pub const OUTBOX_STATUS_IN_FLIGHT: &str = "in_flight";
+pub const OUTBOX_STATUS_DELIVERED: &str = "delivered";
+pub const OUTBOX_STATUS_DEAD: &str = "dead";
+
+// Event types written to `outbox`
+pub const EVENT_PAYMENT_SENT: &str = "payment.sent";
+pub const EVENT_PAYMENT_RECEIVED: &str = "payment.received";
+pub const EVENT_DEPOSIT_REQUESTED: &str = "deposit.requested";
+pub const EVENT_DEPOSIT_APPROVED: &str = "deposit.approved";
+pub const EVENT_DEPOSIT_REJECTED: &str = "deposit.rejected";
+pub const EVENT_SCHEDULE_EXECUTED: &str = "schedule.executed";
+pub const EVENT_SCHEDULE_FAILED: &str = "schedule.failed";
+pub const EVENT_SCHEDULE_PAUSED: &str = "schedule.paused";
+pub const EVENT_SCHEDULE_SKIPPED: &str = "schedule.skipped";
+pub const EVENT_BALANCE_LOW: &str = "balance.low";
+pub const EVENT_TOP_UP_SUCCEEDED: &str = "top_up.succeeded";
+pub const EVENT_TOP_UP_FAILED: &str = "top_up.failed";
+pub const EVENT_PAYMENT_REQUEST_RECEIVED: &str = "payment_request.received";
+pub const EVENT_PAYMENT_REQUEST_PAID: &str = "payment_request.paid";
+pub const EVENT_PAYMENT_REQUEST_DECLINED: &str = "payment_request.declined";
+pub const EVENT_TYPES: &[&str] = &[
+    EVENT_PAYMENT_SENT, EVENT_PAYMENT_RECEIVED, EVENT_DEPOSIT_REQUESTED, EVENT_DEPOSIT_APPROVED, EVENT_DEPOSIT_REJECTED,
+    EVENT_SCHEDULE_EXECUTED, EVENT_SCHEDULE_FAILED, EVENT_SCHEDULE_PAUSED, EVENT_SCHEDULE_SKIPPED,
+    EVENT_BALANCE_LOW, EVENT_TOP_UP_SUCCEEDED, EVENT_TOP_UP_FAILED,
+    EVENT_PAYMENT_REQUEST_RECEIVED, EVENT_PAYMENT_REQUEST_PAID, EVENT_PAYMENT_REQUEST_DECLINED,
+];
 
 #[derive(Queryable, Debug, QueryableByName, Selectable)]
 #[diesel(table_name = accounts)]
 pub struct Account {
    pub id: Uuid,
    pub account_holder_name: String,
-   pub created_at: NaiveDateTime,
+   pub created_at: DateTime<Utc>,
    pub status: String,
+   pub time_zone: String,
+   pub language: String,
+   pub account_number: String,
 }
 
 #[derive(Insertable)]
@@ -24,6 +112,7 @@ pub struct Account {
 pub struct NewAccount<'a> {
     pub account_holder_name: &'a str,
     pub status: &'a str,
+    pub account_number: &'a str,
 }
 
 #[derive(Queryable, Debug, QueryableByName, Selectable)]
@@ -33,7 +122,10 @@ pub struct SubAccount {
     pub account_id: Option<Uuid>,
     pub currency: String,
     pub balance: f64,
-    pub created_at: NaiveDateTime,
+    pub created_at: DateTime<Utc>,
+    pub auto_top_up_from: Option<Uuid>,
+    pub low_balance_warned_at: Option<DateTime<Utc>>,
+    pub account_number: String,
 }
 
 #[derive(Insertable)]
@@ -42,6 +134,7 @@ pub struct NewSubAccount<'a> {
     pub account_id: Option<Uuid>,
     pub currency: &'a str,
     pub balance: f64,
+    pub account_number: &'a str,
 }
 
 #[derive(Queryable, Debug, QueryableByName, Selectable)]
@@ -52,7 +145,17 @@ pub struct Transaction {
     pub sub_account_id_to: Option<Uuid>,
     pub amount: f64,
     pub transfer_currency: String,
-    pub transaction_date: NaiveDateTime,
+    pub transaction_date: DateTime<Utc>,
+    pub exchange_rate: Option<f64>,
+    pub reverses: Option<Uuid>,
+    pub reversed_by: Option<Uuid>,
+    pub received_amount: Option<f64>,
+    pub received_currency: Option<String>,
+    pub fx_fee: Option<f64>,
+    pub fx_fee_bearer: Option<String>,
+    pub memo: Option<String>,
+    pub reference: Option<String>,
+    pub fx_fee_sub_account_id: Option<Uuid>,
 }
 
 #[derive(Insertable)]
@@ -62,6 +165,15 @@ pub struct NewTransaction<'a> {
     pub sub_account_id_to: Option<Uuid>,
     pub amount: f64,
     pub transfer_currency: &'a str,
+    pub exchange_rate: Option<f64>,
+    pub reverses: Option<Uuid>,
+    pub received_amount: Option<f64>,
+    pub received_currency: Option<&'a str>,
+    pub fx_fee: Option<f64>,
+    pub fx_fee_bearer: Option<&'a str>,
+    pub memo: Option<&'a str>,
+    pub reference: Option<&'a str>,
+    pub fx_fee_sub_account_id: Option<Uuid>,
 }
 
 #[derive(Queryable, Debug, QueryableByName, Selectable)]
@@ -71,7 +183,14 @@ pub struct PendingTransaction {
     pub account_id_to_add: Option<Uuid>,
     pub amount: f64,
     pub transfer_currency: String,
-    pub transaction_date: NaiveDateTime,
+    pub transaction_date: DateTime<Utc>,
+    pub status: String,
+    pub reviewed_by: Option<String>,
+    pub decided_at: Option<DateTime<Utc>>,
+    pub decision_reason: Option<String>,
+    pub initiated_by: Option<String>,
+    pub memo: Option<String>,
+    pub reference: Option<String>,
 }
 
 #[derive(Insertable)]
@@ -80,6 +199,49 @@ pub struct NewPendingTransaction<'a> {
     pub account_id_to_add: Option<Uuid>,
     pub amount: f64,
     pub transfer_currency: &'a str,
+    pub initiated_by: Option<&'a str>,
+    pub memo: Option<&'a str>,
+    pub reference: Option<&'a str>,
+}
+
+#[derive(Queryable, Debug, Selectable)]
+#[diesel(table_name = pending_transaction_approvals)]
+pub struct PendingTransactionApproval {
+    pub id: Uuid,
+    pub pending_transaction_id: Uuid,
+    pub approved_by: String,
+    pub approved_at: DateTime<Utc>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = pending_transaction_approvals)]
+pub struct NewPendingTransactionApproval<'a> {
+    pub pending_transaction_id: Uuid,
+    pub approved_by: &'a str,
+}
+
+#[derive(Queryable, Debug, Selectable, Insertable, AsChangeset)]
+#[diesel(table_name = approval_thresholds)]
+pub struct ApprovalThreshold {
+    pub currency: String,
+    pub amount: f64,
+}
+
+#[derive(Queryable, Debug, Selectable)]
+#[diesel(table_name = operators)]
+pub struct Operator {
+    pub username: String,
+    pub passwd: String,
+    pub created_at: DateTime<Utc>,
+    pub account_id: Option<Uuid>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = operators)]
+pub struct NewOperator<'a> {
+    pub username: &'a str,
+    pub passwd: &'a str,
+    pub account_id: Option<Uuid>,
 }
 
 #[derive(Queryable, Debug, QueryableByName, Selectable)]
@@ -108,8 +270,20 @@ pub struct ScheduledTransaction {
     pub to_account_id: Uuid,
     pub amount: f64,
     pub currency: String,
-    pub scheduled_date: NaiveDateTime,
+    pub scheduled_date: DateTime<Utc>,
     pub executed: bool,
+    pub recurrence: Option<String>,
+    pub end_date: Option<DateTime<Utc>>,
+    pub occurrence_count: Option<i32>,
+    pub occurrences_executed: i32,
+    pub kind: String,
+    pub catch_up_policy: String,
+    pub status: String,
+    pub consecutive_failures: i32,
+    pub execution_time: NaiveTime,
+    pub business_day_convention: String,
+    pub memo: Option<String>,
+    pub reference: Option<String>,
 }
 
 
@@ -120,6 +294,292 @@ pub struct NewScheduledTransaction<'a> {
     pub to_account_id: Uuid,
     pub amount: f64,
     pub currency: &'a str,
-    pub scheduled_date: NaiveDateTime,
+    pub scheduled_date: DateTime<Utc>,
     pub executed: bool,
-}
\ No newline at end of file
+    pub recurrence: Option<&'a str>,
+    pub end_date: Option<DateTime<Utc>>,
+    pub occurrence_count: Option<i32>,
+    pub kind: &'a str,
+    pub catch_up_policy: &'a str,
+    pub execution_time: NaiveTime,
+    pub business_day_convention: &'a str,
+    pub memo: Option<&'a str>,
+    pub reference: Option<&'a str>,
+}
+
+#[derive(Queryable, Selectable, Debug)]
+#[diesel(table_name = scheduled_runs)]
+pub struct ScheduledRun {
+    pub id: Uuid,
+    pub scheduled_transaction_id: Uuid,
+    pub occurrence_date: DateTime<Utc>,
+    pub status: String,
+    pub attempt_count: i32,
+    pub last_error: Option<String>,
+    pub transaction_id: Option<Uuid>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = scheduled_runs)]
+pub struct NewScheduledRun<'a> {
+    pub scheduled_transaction_id: Uuid,
+    pub occurrence_date: DateTime<Utc>,
+    pub status: &'a str,
+}
+
+#[derive(Queryable, Selectable, Debug)]
+#[diesel(table_name = notification_channels)]
+pub struct NotificationChannel {
+    pub id: Uuid,
+    pub account_id: Uuid,
+    pub channel: String,
+    pub address: String,
+    pub enabled: bool,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = notification_channels)]
+pub struct NewNotificationChannel<'a> {
+    pub account_id: Uuid,
+    pub channel: &'a str,
+    pub address: &'a str,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = notification_deliveries)]
+pub struct NewNotificationDelivery {
+    pub channel_id: Uuid,
+    pub outbox_id: Uuid,
+}
+
+#[derive(Queryable, Selectable, Debug)]
+#[diesel(table_name = outbox)]
+pub struct OutboxMessage {
+    pub id: Uuid,
+    pub event_type: String,
+    pub account_id: Option<Uuid>,
+    pub subject: String,
+    pub message: String,
+    pub payload: serde_json::Value,
+    pub status: String,
+    pub attempt_count: i32,
+    pub next_attempt_at: DateTime<Utc>,
+    pub last_error: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub delivered_at: Option<DateTime<Utc>>,
+    pub lease_expires_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = outbox)]
+pub struct NewOutboxMessage<'a> {
+    pub event_type: &'a str,
+    pub account_id: Option<Uuid>,
+    pub subject: &'a str,
+    pub message: &'a str,
+    pub payload: serde_json::Value,
+}
+
+#[derive(Queryable, Selectable, Debug)]
+#[diesel(table_name = webhook_endpoints)]
+pub struct WebhookEndpoint {
+    pub id: Uuid,
+    pub account_id: Uuid,
+    pub url: String,
+    pub secret: String,
+    pub event_types: Vec<String>,
+    pub enabled: bool,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = webhook_endpoints)]
+pub struct NewWebhookEndpoint<'a> {
+    pub account_id: Uuid,
+    pub url: &'a str,
+    pub secret: &'a str,
+    pub event_types: Vec<String>,
+}
+
+#[derive(Queryable, Selectable, Debug)]
+#[diesel(table_name = webhook_deliveries)]
+pub struct WebhookDelivery {
+    pub id: Uuid,
+    pub endpoint_id: Uuid,
+    pub outbox_id: Uuid,
+    pub replay: bool,
+    pub succeeded: bool,
+    pub status_code: Option<i32>,
+    pub error: Option<String>,
+    pub duration_ms: i32,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = webhook_deliveries)]
+pub struct NewWebhookDelivery<'a> {
+    pub endpoint_id: Uuid,
+    pub outbox_id: Uuid,
+    pub replay: bool,
+    pub succeeded: bool,
+    pub status_code: Option<i32>,
+    pub error: Option<&'a str>,
+    pub duration_ms: i32,
+}
+
+#[derive(Queryable, Selectable, Debug)]
+#[diesel(table_name = telegram_links)]
+pub struct TelegramLink {
+    pub account_id: Uuid,
+    pub telegram_user_id: i64,
+    pub chat_id: i64,
+    pub linked_at: DateTime<Utc>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = telegram_links)]
+pub struct NewTelegramLink {
+    pub account_id: Uuid,
+    pub telegram_user_id: i64,
+    pub chat_id: i64,
+}
+
+#[derive(Queryable, Selectable, Debug)]
+#[diesel(table_name = telegram_link_codes)]
+pub struct TelegramLinkCode {
+    pub code: String,
+    pub account_id: Uuid,
+    pub expires_at: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = telegram_link_codes)]
+pub struct NewTelegramLinkCode<'a> {
+    pub code: &'a str,
+    pub account_id: Uuid,
+    pub expires_at: DateTime<Utc>,
+}
+
+#[derive(Queryable, Selectable, Debug)]
+#[diesel(table_name = telegram_transfers)]
+pub struct TelegramTransfer {
+    pub id: Uuid,
+    pub from_account_id: Uuid,
+    pub to_account_id: Uuid,
+    pub amount: f64,
+    pub currency: String,
+    pub status: String,
+    pub transaction_id: Option<Uuid>,
+    pub created_at: DateTime<Utc>,
+    pub decided_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = telegram_transfers)]
+pub struct NewTelegramTransfer<'a> {
+    pub from_account_id: Uuid,
+    pub to_account_id: Uuid,
+    pub amount: f64,
+    pub currency: &'a str,
+}
+
+#[derive(Queryable, Selectable, Debug)]
+#[diesel(table_name = telegram_operators)]
+pub struct TelegramOperator {
+    pub telegram_user_id: i64,
+    pub operator: String,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = telegram_operators)]
+pub struct NewTelegramOperator<'a> {
+    pub telegram_user_id: i64,
+    pub operator: &'a str,
+}
+
+#[derive(Queryable, Selectable, Debug)]
+#[diesel(table_name = telegram_approval_messages)]
+pub struct TelegramApprovalMessage {
+    pub pending_transaction_id: Uuid,
+    pub chat_id: i64,
+    pub message_id: i32,
+    pub sent_at: DateTime<Utc>,
+    pub closed_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = telegram_approval_messages)]
+pub struct NewTelegramApprovalMessage {
+    pub pending_transaction_id: Uuid,
+    pub chat_id: i64,
+    pub message_id: i32,
+}
+
+#[derive(Queryable, Selectable, Debug, Clone)]
+#[diesel(table_name = payees)]
+pub struct Payee {
+    pub id: Uuid,
+    pub account_id: Uuid,
+    pub nickname: String,
+    pub payee_account_id: Uuid,
+    pub default_currency: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = payees)]
+pub struct NewPayee<'a> {
+    pub account_id: Uuid,
+    pub nickname: &'a str,
+    pub payee_account_id: Uuid,
+    pub default_currency: Option<&'a str>,
+}
+
+#[derive(Queryable, Selectable, Debug)]
+#[diesel(table_name = payment_requests)]
+pub struct PaymentRequest {
+    pub id: Uuid,
+    pub requester_account_id: Uuid,
+    pub payer_account_id: Uuid,
+    pub amount: f64,
+    pub currency: String,
+    pub memo: Option<String>,
+    pub status: String,
+    pub expires_at: DateTime<Utc>,
+    pub transaction_id: Option<Uuid>,
+    pub created_at: DateTime<Utc>,
+    pub decided_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = payment_requests)]
+pub struct NewPaymentRequest<'a> {
+    pub requester_account_id: Uuid,
+    pub payer_account_id: Uuid,
+    pub amount: f64,
+    pub currency: &'a str,
+    pub memo: Option<&'a str>,
+    pub expires_at: DateTime<Utc>,
+}
+
+#[derive(Queryable, Selectable, Debug)]
+#[diesel(table_name = transaction_categories)]
+pub struct TransactionCategory {
+    pub transaction_id: Uuid,
+    pub account_id: Uuid,
+    pub category: String,
+    pub categorized_at: DateTime<Utc>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = transaction_categories)]
+pub struct NewTransactionCategory<'a> {
+    pub transaction_id: Uuid,
+    pub account_id: Uuid,
+    pub category: &'a str,
+}
@@ -6,20 +6,302 @@ use reqwest::blocking::Client;
 use serde::Deserialize;
 use crate::models::PendingTransaction;
 use crate::models::NewPendingTransaction;
-use crate::models::ADMIN_ACCOUNT_ID;
+use crate::models::{ADMIN_ACCOUNT_ID, FX_FEE_ACCOUNT_ID, PENDING_STATUS_PENDING, PENDING_STATUS_APPROVED, PENDING_STATUS_REJECTED, PENDING_STATUS_CANCELLED};
+use chrono::Utc;
+use crate::database::create_sub_account;
+use crate::approvals::{is_operator, is_initiator, record_approval, get_approvals, required_approvals};
+use crate::transactiondetails::PaymentDetails;
+use crate::outbox::{enqueue_event, enqueue_payment_events, deposit_payload};
+use crate::models::{EVENT_DEPOSIT_APPROVED, EVENT_DEPOSIT_REJECTED};
+use serde_json::json;
 use crate::schema::pending_transactions::dsl::*;
 
+// Tolerance used when comparing refunded totals against the original amount
+const AMOUNT_EPSILON: f64 = 1e-9;
+
 #[derive(Deserialize)]
 struct ExchangeRateResponse {
     conversion_result: f64,
 }
 
+// What to do when money arrives for an account that has no sub-account in the incoming currency
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MissingSubAccountPolicy {
+    // Open a zero-balance sub-account in the incoming currency
+    AutoOpen,
+    // Convert into the recipient's primary (oldest) sub-account
+    ConvertToPrimary,
+    // Refuse the deposit or transfer
+    Reject,
+}
+
+impl MissingSubAccountPolicy {
+    // Read from MISSING_SUB_ACCOUNT_POLICY (auto_open, convert or reject), defaulting to reject
+    pub fn from_env() -> Self {
+        match std::env::var("MISSING_SUB_ACCOUNT_POLICY").as_deref() {
+            Ok("auto_open") => MissingSubAccountPolicy::AutoOpen,
+            Ok("convert") => MissingSubAccountPolicy::ConvertToPrimary,
+            _ => MissingSubAccountPolicy::Reject,
+        }
+    }
+}
+
+// Fetch a live conversion of amount_to_convert from one currency into another
+pub fn convert_amount(
+    from_currency: &str,
+    to_currency: &str,
+    amount_to_convert: f64
+) -> Result<f64, diesel::result::Error> {
+    let client = Client::new();
+    let url = format!("https://v6.exchangerate-api.com/v6/dd4a4841dba843af350365ac/pair/{}/{}/{}", from_currency, to_currency, amount_to_convert);
+    let response = client.get(&url).send().map_err(|_| diesel::result::Error::RollbackTransaction)?;
+
+    // Check if the response is successful
+    if !response.status().is_success() {
+        println!("Error: {:?}", response.status());
+        return Err(diesel::result::Error::RollbackTransaction);
+    }
+
+    // Deserialize the JSON response
+    let exchange_rate_response: ExchangeRateResponse = response.json().map_err(|_| diesel::result::Error::RollbackTransaction)?;
+
+    Ok(exchange_rate_response.conversion_result)
+}
+
+// Who pays the spread on a payment sent in one currency and received in another
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FxSpreadBearer {
+    Sender,
+    Recipient,
+}
+
+impl FxSpreadBearer {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FxSpreadBearer::Sender => "sender",
+            FxSpreadBearer::Recipient => "recipient",
+        }
+    }
+}
+
+// Spread charged on cross-currency payments, as a fraction, read from FX_SPREAD_PERCENT (default 0.5%)
+pub fn fx_spread() -> f64 {
+    std::env::var("FX_SPREAD_PERCENT")
+        .ok()
+        .and_then(|value| value.parse::<f64>().ok())
+        .filter(|percent| *percent >= 0.0 && *percent < 100.0)
+        .unwrap_or(0.5)
+        / 100.0
+}
+
+// Locked-in terms of a cross-currency payment, shown to the sender before they confirm
+#[derive(Debug, Clone)]
+pub struct CrossCurrencyQuote {
+    pub send_currency: String,
+    pub receive_currency: String,
+    pub amount_debited: f64,
+    pub amount_received: f64,
+    pub mid_rate: f64,
+    pub fx_fee: f64,
+    pub bearer: FxSpreadBearer,
+}
+
+pub fn quote_cross_currency_payment(
+    send_currency: &str,
+    receive_currency: &str,
+    amount_to_send: f64,
+    bearer: FxSpreadBearer
+) -> Result<CrossCurrencyQuote, diesel::result::Error> {
+    let converted_amount = convert_amount(send_currency, receive_currency, amount_to_send)?;
+    let fee = amount_to_send * fx_spread();
+
+    // The sender either pays the spread on top, or the recipient gets the converted amount less the spread
+    let (amount_debited, amount_received) = match bearer {
+        FxSpreadBearer::Sender => (amount_to_send + fee, converted_amount),
+        FxSpreadBearer::Recipient => (amount_to_send, converted_amount * (1.0 - fx_spread())),
+    };
+
+    Ok(CrossCurrencyQuote {
+        send_currency: send_currency.to_string(),
+        receive_currency: receive_currency.to_string(),
+        amount_debited,
+        amount_received,
+        mid_rate: converted_amount / amount_to_send,
+        fx_fee: fee,
+        bearer,
+    })
+}
+
+// Executes a payment at the rate and amounts locked in by the quote
+pub fn transfer_money_cross_currency(
+    conn: &mut PgConnection,
+    from_account: Uuid,
+    to_account: Uuid,
+    quote: &CrossCurrencyQuote,
+    details: &PaymentDetails,
+    policy: MissingSubAccountPolicy
+) -> Result<Transaction, diesel::result::Error> {
+    use crate::schema::sub_accounts::dsl::*;
+    use crate::schema::transactions::dsl::*;
+
+    conn.transaction(|conn| {
+        let from_sub = match sub_accounts
+            .filter(account_id.eq(from_account))
+            .filter(currency.eq(&quote.send_currency))
+            .first::<SubAccount>(conn)
+        {
+            Ok(account) => account,
+            Err(diesel::result::Error::NotFound) => {
+                return Err(diesel::result::Error::RollbackTransaction);
+            }
+            Err(e) => return Err(e),
+        };
+
+        // Verify sufficient balance, including the spread if the sender is paying it
+        if from_sub.balance < quote.amount_debited {
+            return Err(diesel::result::Error::RollbackTransaction);
+        }
+
+        // Falling back to the recipient's primary currency would apply a second, unquoted conversion
+        let policy = match policy {
+            MissingSubAccountPolicy::ConvertToPrimary => MissingSubAccountPolicy::Reject,
+            policy => policy,
+        };
+        let (to_sub, amount_received) = resolve_destination_sub_account(conn, to_account, &quote.receive_currency, quote.amount_received, policy)?;
+
+        update_balance(conn, from_sub.id, -quote.amount_debited)?;
+        update_balance(conn, to_sub.id, amount_received)?;
+        // The spread is revenue, kept in the currency it was charged in
+        let fee_sub = get_fx_fee_sub_account(conn, &quote.send_currency)?;
+        update_balance(conn, fee_sub.id, quote.fx_fee)?;
+
+        // Record both legs; the effective rate is what reversals use to undo the payment
+        let new_transaction = NewTransaction {
+            sub_account_id_from: Some(from_sub.id),
+            sub_account_id_to: Some(to_sub.id),
+            amount: quote.amount_debited,
+            transfer_currency: &quote.send_currency,
+            exchange_rate: Some(amount_received / quote.amount_debited),
+            reverses: None,
+            received_amount: Some(amount_received),
+            received_currency: Some(&quote.receive_currency),
+            fx_fee: Some(quote.fx_fee),
+            fx_fee_bearer: Some(quote.bearer.as_str()),
+            memo: details.memo(),
+            reference: details.reference(),
+            fx_fee_sub_account_id: Some(fee_sub.id),
+        };
+
+        diesel::insert_into(transactions)
+            .values(&new_transaction)
+            .returning(Transaction::as_returning())
+            .get_result(conn)
+    })
+}
+
+// The fee account's sub-account in the currency, opened the first time a spread is charged in it
+fn get_fx_fee_sub_account(conn: &mut PgConnection, fee_currency: &str) -> Result<SubAccount, diesel::result::Error> {
+    use crate::schema::sub_accounts::dsl::*;
+    let existing = sub_accounts
+        .filter(account_id.eq(FX_FEE_ACCOUNT_ID))
+        .filter(currency.eq(fee_currency))
+        .first::<SubAccount>(conn)
+        .optional()?;
+    match existing {
+        Some(fee_sub) => Ok(fee_sub),
+        None => create_sub_account(conn, FX_FEE_ACCOUNT_ID, fee_currency, 0.0),
+    }
+}
+
+// The sub-account an account has held the longest, used as its primary currency
+pub fn get_primary_sub_account(
+    conn: &mut PgConnection,
+    account_id_to_check: Uuid
+) -> Result<Option<SubAccount>, diesel::result::Error> {
+    use crate::schema::sub_accounts::dsl::*;
+    sub_accounts
+        .filter(account_id.eq(account_id_to_check))
+        .order(created_at.asc())
+        .first::<SubAccount>(conn)
+        .optional()
+}
+
+// Where incoming money in a currency lands under a MissingSubAccountPolicy
+#[derive(Debug)]
+pub enum Destination {
+    // The recipient already holds the currency
+    Existing(SubAccount),
+    // A sub-account in the currency is opened for it
+    Opened,
+    // The money is converted into the recipient's primary sub-account
+    Primary(SubAccount),
+}
+
+// Where money in currency_to_receive would be credited to the account under the given policy, without opening or
+// converting anything; None if the policy rejects it
+pub fn find_destination(
+    conn: &mut PgConnection,
+    to_account: Uuid,
+    currency_to_receive: &str,
+    policy: MissingSubAccountPolicy
+) -> Result<Option<Destination>, diesel::result::Error> {
+    use crate::schema::sub_accounts::dsl::*;
+    let existing = sub_accounts
+        .filter(account_id.eq(to_account))
+        .filter(currency.eq(currency_to_receive))
+        .first::<SubAccount>(conn)
+        .optional()?;
+    if let Some(to_sub) = existing {
+        return Ok(Some(Destination::Existing(to_sub)));
+    }
+
+    match policy {
+        MissingSubAccountPolicy::AutoOpen => Ok(Some(Destination::Opened)),
+        MissingSubAccountPolicy::ConvertToPrimary => Ok(get_primary_sub_account(conn, to_account)?.map(Destination::Primary)),
+        MissingSubAccountPolicy::Reject => Ok(None),
+    }
+}
+
+// Whether money in currency_to_check could be credited to the account under the given policy
+pub fn can_receive_currency(
+    conn: &mut PgConnection,
+    account_id_to_check: Uuid,
+    currency_to_check: &str,
+    policy: MissingSubAccountPolicy
+) -> Result<bool, diesel::result::Error> {
+    Ok(find_destination(conn, account_id_to_check, currency_to_check, policy)?.is_some())
+}
+
+// Finds the sub-account that receives incoming money and the amount credited to it
+fn resolve_destination_sub_account(
+    conn: &mut PgConnection,
+    to_account: Uuid,
+    currency_to_receive: &str,
+    amount_to_receive: f64,
+    policy: MissingSubAccountPolicy
+) -> Result<(SubAccount, f64), diesel::result::Error> {
+    match find_destination(conn, to_account, currency_to_receive, policy)? {
+        Some(Destination::Existing(to_sub)) => Ok((to_sub, amount_to_receive)),
+        Some(Destination::Opened) => {
+            let to_sub = create_sub_account(conn, to_account, currency_to_receive, 0.0)?;
+            Ok((to_sub, amount_to_receive))
+        }
+        Some(Destination::Primary(to_sub)) => {
+            let converted_amount = convert_amount(currency_to_receive, &to_sub.currency, amount_to_receive)?;
+            Ok((to_sub, converted_amount))
+        }
+        None => Err(diesel::result::Error::RollbackTransaction),
+    }
+}
+
 pub fn transfer_money(
     conn: &mut PgConnection,
     from_account: Uuid,
     to_account: Uuid,
     amount_to_transfer: f64,
-    currency_to_transfer: &str
+    currency_to_transfer: &str,
+    details: &PaymentDetails,
+    policy: MissingSubAccountPolicy
 ) -> Result<Transaction, diesel::result::Error> {
     use crate::schema::sub_accounts::dsl::*;
     use crate::schema::transactions::dsl::*;
@@ -37,33 +319,34 @@ pub fn transfer_money(
         Err(e) => return Err(e),
     };
 
-    let to_sub = match sub_accounts
-        .filter(account_id.eq(to_account))
-        .filter(currency.eq(currency_to_transfer))
-        .first::<SubAccount>(conn)
-    {
-        Ok(account) => account,
-        Err(diesel::result::Error::NotFound) => {
-            return Err(diesel::result::Error::RollbackTransaction);
-        }
-        Err(e) => return Err(e),
-    };
-        
     // Verify sufficient balance
     if from_sub.balance < amount_to_transfer {
         return Err(diesel::result::Error::RollbackTransaction);
     }
 
+    // The recipient may not hold this currency; the configured policy decides where the money lands
+    let (to_sub, amount_received) = resolve_destination_sub_account(conn, to_account, currency_to_transfer, amount_to_transfer, policy)?;
+
     // Update balances
     update_balance(conn, from_sub.id, -amount_to_transfer)?;
-    update_balance(conn, to_sub.id, amount_to_transfer)?;
+    update_balance(conn, to_sub.id, amount_received)?;
 
-    // Create the transaction record
+    // Create the transaction record, including the receiving leg if the money was converted
+    let converted = to_sub.currency != currency_to_transfer;
     let new_transaction = NewTransaction {
         sub_account_id_from: Some(from_sub.id),
         sub_account_id_to: Some(to_sub.id),
         amount: amount_to_transfer,
         transfer_currency: currency_to_transfer,
+        exchange_rate: if converted { Some(amount_received / amount_to_transfer) } else { None },
+        reverses: None,
+        received_amount: if converted { Some(amount_received) } else { None },
+        received_currency: if converted { Some(&to_sub.currency) } else { None },
+        fx_fee: None,
+        fx_fee_bearer: None,
+        memo: details.memo(),
+        reference: details.reference(),
+        fx_fee_sub_account_id: None,
     };
 
     diesel::insert_into(transactions)
@@ -106,7 +389,6 @@ pub fn transfer_between_sub_accounts(
     amount_to_transfer: f64
 ) -> Result<Transaction, diesel::result::Error> {
     use crate::schema::sub_accounts::dsl::*;
-    use crate::schema::transactions::dsl::*;
 
     // Get the source and destination sub-accounts
     let from_sub = sub_accounts
@@ -125,21 +407,20 @@ pub fn transfer_between_sub_accounts(
     }
 
     // Fetch live conversion rate
-    let client = Client::new();
-    let url = format!("https://v6.exchangerate-api.com/v6/dd4a4841dba843af350365ac/pair/{}/{}/{}", from_currency, to_currency, amount_to_transfer);
-    let response = client.get(&url).send().map_err(|_| diesel::result::Error::RollbackTransaction)?;
+    let converted_amount = convert_amount(from_currency, to_currency, amount_to_transfer)?;
 
-    // Check if the response is successful
-    if !response.status().is_success() {
-        println!("Error: {:?}", response.status());
-        return Err(diesel::result::Error::RollbackTransaction);
-    }
-
-    // Deserialize the JSON response
-    let exchange_rate: ExchangeRateResponse = response.json().map_err(|_| diesel::result::Error::RollbackTransaction)?;
+    record_sub_account_transfer(conn, &from_sub, &to_sub, amount_to_transfer, converted_amount)
+}
 
-    // Use the conversion_result
-    let converted_amount = exchange_rate.conversion_result;
+// Moves `amount_to_transfer` out of `from_sub` and `converted_amount` into `to_sub`, both owned by the same account
+fn record_sub_account_transfer(
+    conn: &mut PgConnection,
+    from_sub: &SubAccount,
+    to_sub: &SubAccount,
+    amount_to_transfer: f64,
+    converted_amount: f64
+) -> Result<Transaction, diesel::result::Error> {
+    use crate::schema::transactions::dsl::*;
 
     // Update balances
     update_balance(conn, from_sub.id, -amount_to_transfer)?;
@@ -150,7 +431,16 @@ pub fn transfer_between_sub_accounts(
         sub_account_id_from: Some(from_sub.id),
         sub_account_id_to: Some(to_sub.id),
         amount: amount_to_transfer,
-        transfer_currency: from_currency,
+        transfer_currency: &from_sub.currency,
+        exchange_rate: Some(converted_amount / amount_to_transfer),
+        reverses: None,
+        received_amount: Some(converted_amount),
+        received_currency: Some(&to_sub.currency),
+        fx_fee: None,
+        fx_fee_bearer: None,
+        memo: None,
+        reference: None,
+        fx_fee_sub_account_id: None,
     };
 
     diesel::insert_into(transactions)
@@ -159,6 +449,57 @@ pub fn transfer_between_sub_accounts(
         .get_result(conn)
 }
 
+// A top-up priced before it is made: `source_amount` leaves `source` and `credited` reaches the target
+#[derive(Debug)]
+pub struct TopUpQuote {
+    pub source: SubAccount,
+    pub source_amount: f64,
+    pub credited: f64,
+}
+
+// Prices converting enough from another of the owner's sub-accounts to add `amount_needed` to the target. This
+// fetches the exchange rate, so call it before opening the transaction that makes the top-up.
+pub fn quote_top_up(
+    conn: &mut PgConnection,
+    target: &SubAccount,
+    source_id: Uuid,
+    amount_needed: f64
+) -> Result<TopUpQuote, diesel::result::Error> {
+    use crate::schema::sub_accounts::dsl::*;
+    let owner = target.account_id.ok_or(diesel::result::Error::RollbackTransaction)?;
+    let source = sub_accounts
+        .find(source_id)
+        .filter(account_id.eq(owner))
+        .first::<SubAccount>(conn)?;
+
+    // Credit the target at the quoted rate; round up to the cent so the target is not left just short
+    let quoted = if source.currency == target.currency {
+        amount_needed
+    } else {
+        convert_amount(&target.currency, &source.currency, amount_needed)?
+    };
+    if quoted <= 0.0 {
+        return Err(diesel::result::Error::RollbackTransaction);
+    }
+    let source_amount = (quoted * 100.0).ceil() / 100.0;
+    Ok(TopUpQuote { credited: source_amount * amount_needed / quoted, source, source_amount })
+}
+
+// Makes a quoted top-up if the source still holds enough
+pub fn top_up_sub_account(
+    conn: &mut PgConnection,
+    target: &SubAccount,
+    quote: &TopUpQuote
+) -> Result<Transaction, diesel::result::Error> {
+    use crate::schema::sub_accounts::dsl::*;
+    conn.transaction(|conn| {
+        let source = sub_accounts.find(quote.source.id).for_update().first::<SubAccount>(conn)?;
+        if source.balance < quote.source_amount {
+            return Err(diesel::result::Error::RollbackTransaction);
+        }
+        record_sub_account_transfer(conn, &source, target, quote.source_amount, quote.credited)
+    })
+}
 
 pub fn get_transactions(
     conn: &mut PgConnection,
@@ -183,16 +524,36 @@ pub fn get_transactions(
     Ok(transactions_temp)
 }
 
+// How a transaction looks from the account owning `own_sub_accounts`: "sent", "received", or "moved" between them
+pub fn transaction_direction(transaction: &Transaction, own_sub_accounts: &[Uuid]) -> &'static str {
+    let sent = transaction.sub_account_id_from.is_some_and(|from| own_sub_accounts.contains(&from));
+    let received = transaction.sub_account_id_to.is_some_and(|to| own_sub_accounts.contains(&to));
+    match (sent, received) {
+        (true, true) => "moved",
+        (true, false) => "sent",
+        _ => "received",
+    }
+}
+
 pub fn add_money_to_sub_account(
     conn: &mut PgConnection,
     account_id_to_add_here: Uuid,
     amount_to_add: f64,
-    transfer_currency_to_add: &str
+    transfer_currency_to_add: &str,
+    details: &PaymentDetails,
+    policy: MissingSubAccountPolicy
 ) -> Result<PendingTransaction, diesel::result::Error> {
+    // Refuse up front rather than letting the request fail once an admin approves it
+    if !can_receive_currency(conn, account_id_to_add_here, transfer_currency_to_add, policy)? {
+        return Err(diesel::result::Error::RollbackTransaction);
+    }
     let new_pending_transaction = NewPendingTransaction {
         account_id_to_add: Some(account_id_to_add_here),
         amount: amount_to_add,
         transfer_currency: transfer_currency_to_add,
+        initiated_by: None,
+        memo: details.memo(),
+        reference: details.reference(),
     };
     use crate::schema::pending_transactions::dsl::*;
 
@@ -202,26 +563,347 @@ pub fn add_money_to_sub_account(
         .get_result(conn)
 }
 
+// An operator-raised credit to a customer account, subject to the same approval rules as deposits
+pub fn request_admin_adjustment(
+    conn: &mut PgConnection,
+    operator: &str,
+    account_id_to_adjust: Uuid,
+    amount_to_add: f64,
+    transfer_currency_to_add: &str,
+    policy: MissingSubAccountPolicy
+) -> Result<PendingTransaction, diesel::result::Error> {
+    if !is_operator(conn, operator)? {
+        return Err(diesel::result::Error::RollbackTransaction);
+    }
+    if !can_receive_currency(conn, account_id_to_adjust, transfer_currency_to_add, policy)? {
+        return Err(diesel::result::Error::RollbackTransaction);
+    }
+
+    let new_pending_transaction = NewPendingTransaction {
+        account_id_to_add: Some(account_id_to_adjust),
+        amount: amount_to_add,
+        transfer_currency: transfer_currency_to_add,
+        initiated_by: Some(operator),
+        memo: None,
+        reference: None,
+    };
+
+    diesel::insert_into(pending_transactions)
+        .values(&new_pending_transaction)
+        .returning(PendingTransaction::as_returning())
+        .get_result(conn)
+}
+
 pub fn get_pending_transactions(
     conn: &mut PgConnection
 ) -> Result<Vec<PendingTransaction>, diesel::result::Error> {
     
-    // output all pending transactions
-    let pending_transactions_temp = pending_transactions.load::<PendingTransaction>(conn)?;
+    // output all requests still waiting for a decision
+    let pending_transactions_temp = pending_transactions
+        .filter(status.eq(PENDING_STATUS_PENDING))
+        .order(transaction_date.asc())
+        .load::<PendingTransaction>(conn)?;
     Ok(pending_transactions_temp)
 }
 
+pub fn get_pending_transaction(
+    conn: &mut PgConnection,
+    pending_transaction_id: Uuid
+) -> Result<PendingTransaction, diesel::result::Error> {
+    pending_transactions.find(pending_transaction_id).first::<PendingTransaction>(conn)
+}
+
+pub fn get_pending_transaction_history(
+    conn: &mut PgConnection
+) -> Result<Vec<PendingTransaction>, diesel::result::Error> {
+    // output all requests that have been approved, rejected or cancelled, most recent first
+    pending_transactions
+        .filter(status.ne(PENDING_STATUS_PENDING))
+        .order(decided_at.desc())
+        .load::<PendingTransaction>(conn)
+}
+
+pub fn get_account_pending_transactions(
+    conn: &mut PgConnection,
+    account_id_to_view: Uuid
+) -> Result<Vec<PendingTransaction>, diesel::result::Error> {
+    pending_transactions
+        .filter(account_id_to_add.eq(account_id_to_view))
+        .order(transaction_date.desc())
+        .load::<PendingTransaction>(conn)
+}
+
+// Moves a request out of the pending state, failing if someone else already decided it
+fn decide_pending_transaction(
+    conn: &mut PgConnection,
+    pending_transaction_id: Uuid,
+    new_status: &str,
+    reviewer: Option<&str>,
+    reason: Option<&str>
+) -> Result<PendingTransaction, diesel::result::Error> {
+    let updated = diesel::update(
+        pending_transactions
+            .find(pending_transaction_id)
+            .filter(status.eq(PENDING_STATUS_PENDING))
+    )
+        .set((
+            status.eq(new_status),
+            reviewed_by.eq(reviewer),
+            decided_at.eq(Utc::now().naive_utc()),
+            decision_reason.eq(reason),
+        ))
+        .returning(PendingTransaction::as_returning())
+        .get_result(conn);
+
+    match updated {
+        Ok(pending_transaction) => Ok(pending_transaction),
+        Err(diesel::result::Error::NotFound) => Err(diesel::result::Error::RollbackTransaction),
+        Err(e) => Err(e),
+    }
+}
+
+// Records the reviewer's approval and executes the deposit once enough distinct operators have signed off,
+// notifying the account holder. The returned request is still pending if it is waiting on a second approval;
+// only the final decision is announced.
 pub fn approve_pending_transaction(
     conn: &mut PgConnection,
+    pending_transaction_id: Uuid,
+    reviewer: &str,
+    policy: MissingSubAccountPolicy
+) -> Result<PendingTransaction, diesel::result::Error> {
+    conn.transaction(|conn| {
+        if !is_operator(conn, reviewer)? {
+            return Err(diesel::result::Error::RollbackTransaction);
+        }
+        // Lock the request so two operators approving at once cannot both execute it
+        let pending_transaction = match pending_transactions
+            .find(pending_transaction_id)
+            .filter(status.eq(PENDING_STATUS_PENDING))
+            .for_update()
+            .first::<PendingTransaction>(conn)
+        {
+            Ok(pending_transaction) => pending_transaction,
+            Err(diesel::result::Error::NotFound) => return Err(diesel::result::Error::RollbackTransaction),
+            Err(e) => return Err(e),
+        };
+
+        if is_initiator(conn, &pending_transaction, reviewer)? {
+            return Err(diesel::result::Error::RollbackTransaction);
+        }
+
+        record_approval(conn, pending_transaction_id, reviewer)?;
+        let approvals = get_approvals(conn, pending_transaction_id)?.len();
+        if approvals < required_approvals(conn, &pending_transaction.transfer_currency, pending_transaction.amount)? {
+            return Ok(pending_transaction);
+        }
+
+        let pending_transaction = decide_pending_transaction(conn, pending_transaction_id, PENDING_STATUS_APPROVED, Some(reviewer), None)?;
+        //transfer money from Adminaccount to sub-account, keeping what the depositor said it was for
+        let details = PaymentDetails { memo: pending_transaction.memo.clone(), reference: pending_transaction.reference.clone() };
+        let account_to_notify = pending_transaction.account_id_to_add.unwrap();
+        transfer_money(conn, ADMIN_ACCOUNT_ID, account_to_notify, pending_transaction.amount, &pending_transaction.transfer_currency, &details, policy)?;
+
+        let mut payload = deposit_payload(pending_transaction.id, pending_transaction.amount, &pending_transaction.transfer_currency);
+        payload["new_balance"] = json!(get_balance(conn, account_to_notify, &pending_transaction.transfer_currency).ok());
+        enqueue_event(conn, EVENT_DEPOSIT_APPROVED, Some(account_to_notify), payload)?;
+        Ok(pending_transaction)
+    })
+}
+
+// Rejects the request and tells the account holder why
+pub fn reject_pending_transaction(
+    conn: &mut PgConnection,
+    pending_transaction_id: Uuid,
+    reviewer: &str,
+    reason: &str
+) -> Result<PendingTransaction, diesel::result::Error> {
+    conn.transaction(|conn| {
+        if !is_operator(conn, reviewer)? {
+            return Err(diesel::result::Error::RollbackTransaction);
+        }
+        let pending_transaction = decide_pending_transaction(conn, pending_transaction_id, PENDING_STATUS_REJECTED, Some(reviewer), Some(reason))?;
+        if let Some(account_to_notify) = pending_transaction.account_id_to_add {
+            let mut payload = deposit_payload(pending_transaction.id, pending_transaction.amount, &pending_transaction.transfer_currency);
+            payload["reason"] = json!(reason);
+            enqueue_event(conn, EVENT_DEPOSIT_REJECTED, Some(account_to_notify), payload)?;
+        }
+        Ok(pending_transaction)
+    })
+}
+
+pub fn cancel_pending_transaction(
+    conn: &mut PgConnection,
+    requesting_account_id: Uuid,
     pending_transaction_id: Uuid
-) -> Result<(), diesel::result::Error> {
-    use crate::schema::pending_transactions::dsl::*;
-    //transfer money from Adminaccount to sub-account
+) -> Result<PendingTransaction, diesel::result::Error> {
+    // Users may only withdraw their own requests
     let pending_transaction = pending_transactions.find(pending_transaction_id).first::<PendingTransaction>(conn)?;
-    transfer_money(conn, ADMIN_ACCOUNT_ID, pending_transaction.account_id_to_add.unwrap(), pending_transaction.amount, &pending_transaction.transfer_currency)?;
+    if pending_transaction.account_id_to_add != Some(requesting_account_id) {
+        return Err(diesel::result::Error::RollbackTransaction);
+    }
+    decide_pending_transaction(conn, pending_transaction_id, PENDING_STATUS_CANCELLED, None, None)
+}
 
 
-    diesel::delete(pending_transactions.find(pending_transaction_id))
-        .execute(conn)?;
-    Ok(())
-}
\ No newline at end of file
+pub fn get_transaction(
+    conn: &mut PgConnection,
+    transaction_id_to_get: Uuid
+) -> Result<Transaction, diesel::result::Error> {
+    use crate::schema::transactions::dsl::*;
+    transactions.find(transaction_id_to_get).first::<Transaction>(conn)
+}
+
+// The transaction locked until the end of the database transaction, so concurrent reversals and refunds of it
+// are checked and posted one after the other
+fn lock_transaction(
+    conn: &mut PgConnection,
+    transaction_id_to_lock: Uuid
+) -> Result<Transaction, diesel::result::Error> {
+    use crate::schema::transactions::dsl::*;
+    transactions.find(transaction_id_to_lock).for_update().first::<Transaction>(conn)
+}
+
+// Amount of the original transaction (in its own currency) already sent back by reversals and refunds
+pub fn get_refunded_amount(
+    conn: &mut PgConnection,
+    original: &Transaction
+) -> Result<f64, diesel::result::Error> {
+    use crate::schema::transactions::dsl::*;
+    let compensating = transactions
+        .filter(reverses.eq(original.transaction_id))
+        .load::<Transaction>(conn)?;
+
+    Ok(compensating
+        .iter()
+        .map(|t| t.amount * t.exchange_rate.unwrap_or(1.0))
+        .sum())
+}
+
+fn compensate_transaction(
+    conn: &mut PgConnection,
+    original: &Transaction,
+    amount_to_return: f64
+) -> Result<Transaction, diesel::result::Error> {
+    use crate::schema::sub_accounts::dsl::*;
+    use crate::schema::transactions::dsl::*;
+
+    // A compensating transaction cannot itself be reversed, and a transaction can only be reversed once
+    if original.reverses.is_some() || original.reversed_by.is_some() {
+        return Err(diesel::result::Error::RollbackTransaction);
+    }
+
+    let (original_from, original_to) = match (original.sub_account_id_from, original.sub_account_id_to) {
+        (Some(from_id), Some(to_id)) => (from_id, to_id),
+        _ => return Err(diesel::result::Error::RollbackTransaction),
+    };
+
+    let from_sub = sub_accounts.find(original_from).first::<SubAccount>(conn)?;
+    let to_sub = sub_accounts.find(original_to).first::<SubAccount>(conn)?;
+
+    // Conversions are undone at the rate they were executed at, never at today's rate
+    let rate = match original.exchange_rate {
+        Some(rate) => rate,
+        None if from_sub.currency == to_sub.currency => 1.0,
+        None => return Err(diesel::result::Error::RollbackTransaction),
+    };
+
+    let remaining = original.amount - get_refunded_amount(conn, original)?;
+    if amount_to_return <= 0.0 || amount_to_return > remaining + AMOUNT_EPSILON {
+        return Err(diesel::result::Error::RollbackTransaction);
+    }
+
+    // The recipient gives back the converted amount they originally received
+    let amount_to_debit = amount_to_return * rate;
+    if to_sub.balance < amount_to_debit {
+        return Err(diesel::result::Error::RollbackTransaction);
+    }
+    // The sender also gets back the matching share of any spread they were charged
+    let fee_returned = match (original.fx_fee, original.fx_fee_sub_account_id) {
+        (Some(fee), Some(fee_sub_id)) if fee > 0.0 => Some((fee_sub_id, fee * amount_to_return / original.amount)),
+        _ => None,
+    };
+    if let Some((fee_sub_id, fee_share)) = fee_returned {
+        let fee_sub = sub_accounts.find(fee_sub_id).first::<SubAccount>(conn)?;
+        if fee_sub.balance < fee_share {
+            return Err(diesel::result::Error::RollbackTransaction);
+        }
+        update_balance(conn, fee_sub_id, -fee_share)?;
+    }
+
+    update_balance(conn, to_sub.id, -amount_to_debit)?;
+    update_balance(conn, from_sub.id, amount_to_return)?;
+
+    let new_transaction = NewTransaction {
+        sub_account_id_from: Some(to_sub.id),
+        sub_account_id_to: Some(from_sub.id),
+        amount: amount_to_debit,
+        transfer_currency: &to_sub.currency,
+        exchange_rate: original.exchange_rate.map(|r| 1.0 / r),
+        reverses: Some(original.transaction_id),
+        received_amount: original.exchange_rate.map(|_| amount_to_return),
+        received_currency: original.exchange_rate.map(|_| from_sub.currency.as_str()),
+        fx_fee: fee_returned.map(|(_, fee_share)| fee_share),
+        fx_fee_bearer: None,
+        memo: None,
+        reference: None,
+        fx_fee_sub_account_id: fee_returned.map(|(fee_sub_id, _)| fee_sub_id),
+    };
+
+    let compensating = diesel::insert_into(transactions)
+        .values(&new_transaction)
+        .returning(Transaction::as_returning())
+        .get_result(conn)?;
+
+    // The original recipient pays the money back, so each side is notified of a payment the other way
+    if let (Some(payer), Some(payee)) = (to_sub.account_id, from_sub.account_id) {
+        enqueue_payment_events(conn, &compensating, payer, payee)?;
+    }
+
+    // Once everything has been sent back the original counts as reversed
+    if remaining - amount_to_return <= AMOUNT_EPSILON {
+        let marked = diesel::update(transactions.find(original.transaction_id))
+            .filter(reversed_by.is_null())
+            .set(reversed_by.eq(compensating.transaction_id))
+            .execute(conn)?;
+        if marked == 0 {
+            return Err(diesel::result::Error::RollbackTransaction);
+        }
+    }
+
+    Ok(compensating)
+}
+
+pub fn reverse_transaction(
+    conn: &mut PgConnection,
+    transaction_id_to_reverse: Uuid
+) -> Result<Transaction, diesel::result::Error> {
+    conn.transaction(|conn| {
+        let original = lock_transaction(conn, transaction_id_to_reverse)?;
+        let remaining = original.amount - get_refunded_amount(conn, &original)?;
+        compensate_transaction(conn, &original, remaining)
+    })
+}
+
+pub fn refund_transaction(
+    conn: &mut PgConnection,
+    refunding_account_id: Uuid,
+    transaction_id_to_refund: Uuid,
+    amount_to_refund: f64
+) -> Result<Transaction, diesel::result::Error> {
+    use crate::schema::sub_accounts::dsl::*;
+
+    conn.transaction(|conn| {
+        let original = lock_transaction(conn, transaction_id_to_refund)?;
+
+        // Only the recipient of a transaction may refund it
+        let recipient_sub = match original.sub_account_id_to {
+            Some(to_id) => sub_accounts.find(to_id).first::<SubAccount>(conn)?,
+            None => return Err(diesel::result::Error::RollbackTransaction),
+        };
+        if recipient_sub.account_id != Some(refunding_account_id) {
+            return Err(diesel::result::Error::RollbackTransaction);
+        }
+
+        compensate_transaction(conn, &original, amount_to_refund)
+    })
+}
@@ -0,0 +1,467 @@
+use anyhow::{anyhow, Result as AnyResult};
+use diesel::prelude::*;
+use diesel::pg::PgConnection;
+use lettre::message::{Mailbox, Message};
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{Address, SmtpTransport, Transport};
+use reqwest::blocking::Client;
+use reqwest::redirect::Policy;
+use reqwest::Url;
+use serde_json::json;
+use std::env;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
+use std::path::PathBuf;
+use std::time::Duration;
+use uuid::Uuid;
+use crate::models::{OutboxMessage, NotificationChannel, NewNotificationChannel, NewNotificationDelivery, CHANNEL_TELEGRAM, CHANNEL_EMAIL, CHANNEL_WEBHOOK, CHANNEL_FILE};
+use crate::recurringpayments::log_to_file;
+use crate::templates::{account_language, render_event};
+
+// One way of reaching a customer. `address` is the channel-specific destination stored for the account
+// (chat ID, email address, URL or file name).
+pub trait Notifier {
+    fn channel(&self) -> &'static str;
+    fn send(&self, address: &str, subject: &str, message: &str) -> AnyResult<()>;
+}
+
+// Sends through the Bot API of TELEGRAM_API_URL (default https://api.telegram.org) with TELEGRAM_BOT_TOKEN
+pub struct TelegramNotifier {
+    api_url: String,
+    bot_token: String,
+}
+
+impl TelegramNotifier {
+    pub fn from_env() -> Option<Self> {
+        let bot_token = env::var("TELEGRAM_BOT_TOKEN").ok()?;
+        let api_url = env::var("TELEGRAM_API_URL").unwrap_or_else(|_| "https://api.telegram.org".to_string());
+        Some(TelegramNotifier { api_url: api_url.trim_end_matches('/').to_string(), bot_token })
+    }
+}
+
+impl Notifier for TelegramNotifier {
+    fn channel(&self) -> &'static str {
+        CHANNEL_TELEGRAM
+    }
+
+    fn send(&self, address: &str, subject: &str, message: &str) -> AnyResult<()> {
+        let chat_id = parse_chat_id(address).ok_or_else(|| anyhow!("invalid Telegram chat ID {:?}", address))?;
+        let url = format!("{}/bot{}/sendMessage", self.api_url, self.bot_token);
+        let response = Client::new()
+            .post(&url)
+            .json(&json!({ "chat_id": chat_id, "text": format!("{}\n{}", subject, message) }))
+            .send()?;
+        if !response.status().is_success() {
+            return Err(anyhow!("Telegram returned {}", response.status()));
+        }
+        Ok(())
+    }
+}
+
+// Chat IDs are non-zero integers; groups and channels have negative ones
+fn parse_chat_id(address: &str) -> Option<i64> {
+    address.parse::<i64>().ok().filter(|id| *id != 0)
+}
+
+// Sends through the SMTP server in SMTP_HOST, SMTP_PORT (default 587), SMTP_USERNAME, SMTP_PASSWORD and
+// SMTP_FROM. SMTP_TLS=none talks plain SMTP, e.g. to a local test server.
+pub struct EmailNotifier {
+    mailer: SmtpTransport,
+    from: String,
+}
+
+impl EmailNotifier {
+    pub fn from_env() -> Option<Self> {
+        let host = env::var("SMTP_HOST").ok()?;
+        let port = env::var("SMTP_PORT").ok().and_then(|port| port.parse::<u16>().ok()).unwrap_or(587);
+        let from = env::var("SMTP_FROM").unwrap_or_else(|_| "Rust EZE <no-reply@localhost>".to_string());
+        let builder = if env::var("SMTP_TLS").is_ok_and(|tls| tls == "none") {
+            SmtpTransport::builder_dangerous(&host)
+        } else {
+            match SmtpTransport::starttls_relay(&host) {
+                Ok(builder) => builder,
+                Err(e) => {
+                    log_to_file(&format!("Invalid SMTP configuration for {}: {:?}", host, e));
+                    return None;
+                }
+            }
+        };
+        let builder = match (env::var("SMTP_USERNAME"), env::var("SMTP_PASSWORD")) {
+            (Ok(username), Ok(password)) => builder.credentials(Credentials::new(username, password)),
+            _ => builder,
+        };
+        Some(EmailNotifier { mailer: builder.port(port).build(), from })
+    }
+}
+
+impl Notifier for EmailNotifier {
+    fn channel(&self) -> &'static str {
+        CHANNEL_EMAIL
+    }
+
+    fn send(&self, address: &str, subject: &str, message: &str) -> AnyResult<()> {
+        let email = Message::builder()
+            .from(self.from.parse()?)
+            .to(Mailbox::new(None, address.parse::<Address>()?))
+            .subject(subject)
+            .body(message.to_string())?;
+        self.mailer.send(&email)?;
+        Ok(())
+    }
+}
+
+const WEBHOOK_CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+const WEBHOOK_REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Customers choose webhook URLs, so by default they may only point at public addresses, not at services on the
+// operator's network. WEBHOOK_ALLOW_PRIVATE_TARGETS=true lifts this, e.g. to test against a local receiver.
+fn private_targets_allowed() -> bool {
+    env::var("WEBHOOK_ALLOW_PRIVATE_TARGETS").is_ok_and(|allow| allow == "true")
+}
+
+// Whether an address is reachable on the public internet, rather than loopback, private, link-local or reserved
+pub fn is_public_ip(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, c, _] = ip.octets();
+            !(ip.is_unspecified()
+                || ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_broadcast()
+                || ip.is_documentation()
+                || ip.is_multicast()
+                || a == 0
+                || a >= 240
+                || (a == 100 && (64..128).contains(&b))
+                || (a == 192 && b == 0 && c == 0)
+                || (a == 198 && (b == 18 || b == 19)))
+        }
+        IpAddr::V6(ip) => {
+            if let Some(mapped) = ip.to_ipv4_mapped() {
+                return is_public_ip(IpAddr::V4(mapped));
+            }
+            let first = ip.segments()[0];
+            !(ip.is_unspecified()
+                || ip.is_loopback()
+                || ip.is_multicast()
+                || (first & 0xfe00) == 0xfc00
+                || (first & 0xffc0) == 0xfe80
+                || (first == 0x2001 && ip.segments()[1] == 0x0db8))
+        }
+    }
+}
+
+// Parses a webhook URL and resolves its host, refusing it unless every address it resolves to is public. The
+// address returned is the one to connect to, so the host cannot be re-resolved to a private address later.
+pub fn resolve_webhook_target(address: &str) -> AnyResult<(Url, SocketAddr)> {
+    let url = Url::parse(address)?;
+    if url.scheme() != "https" && url.scheme() != "http" {
+        return Err(anyhow!("webhook URLs must use http or https"));
+    }
+    let host = url.host_str().ok_or_else(|| anyhow!("webhook URL {:?} has no host", address))?;
+    let port = url.port_or_known_default().unwrap_or(443);
+    let addresses: Vec<SocketAddr> = (host.trim_start_matches('[').trim_end_matches(']'), port).to_socket_addrs()?.collect();
+    let target = *addresses.first().ok_or_else(|| anyhow!("{} did not resolve", host))?;
+    if !private_targets_allowed() && !addresses.iter().all(|a| is_public_ip(a.ip())) {
+        return Err(anyhow!("{} resolves to a private or loopback address", host));
+    }
+    Ok((url, target))
+}
+
+// A client for one request to a webhook URL. It connects only to the checked address and does not follow
+// redirects, which could lead to a private address.
+pub fn webhook_client(address: &str) -> AnyResult<(Url, Client)> {
+    let (url, target) = resolve_webhook_target(address)?;
+    let mut builder = Client::builder()
+        .connect_timeout(WEBHOOK_CONNECT_TIMEOUT)
+        .timeout(WEBHOOK_REQUEST_TIMEOUT)
+        .redirect(Policy::none());
+    if let Some(domain) = url.domain() {
+        builder = builder.resolve(domain, target);
+    }
+    Ok((url, builder.build()?))
+}
+
+// POSTs a JSON body with the subject and message to the address, which must be a public http(s) URL
+pub struct WebhookNotifier;
+
+impl Notifier for WebhookNotifier {
+    fn channel(&self) -> &'static str {
+        CHANNEL_WEBHOOK
+    }
+
+    fn send(&self, address: &str, subject: &str, message: &str) -> AnyResult<()> {
+        let (url, client) = webhook_client(address)?;
+        let response = client
+            .post(url)
+            .json(&json!({ "subject": subject, "message": message }))
+            .send()?;
+        if !response.status().is_success() {
+            return Err(anyhow!("webhook returned {}", response.status()));
+        }
+        Ok(())
+    }
+}
+
+// Where a file channel writes: the relay's standard output, or a file in NOTIFICATION_FILE_DIR
+#[derive(Debug, PartialEq)]
+pub enum FileTarget {
+    Stdout,
+    File(PathBuf),
+}
+
+// Prints to standard output for the address "stdout", or appends to a file the operator has created in
+// NOTIFICATION_FILE_DIR. Customers can only name one of those files, never a path, and the notifier never creates
+// files, so files are unavailable until an operator sets them up.
+pub struct FileNotifier {
+    dir: Option<PathBuf>,
+}
+
+impl FileNotifier {
+    pub fn from_env() -> Self {
+        FileNotifier { dir: env::var("NOTIFICATION_FILE_DIR").ok().map(PathBuf::from) }
+    }
+
+    // Standard output, or the operator-created regular file a channel address names, if there is one
+    pub fn resolve(&self, address: &str) -> Option<FileTarget> {
+        if address == "stdout" {
+            return Some(FileTarget::Stdout);
+        }
+        let is_plain_name = !address.is_empty()
+            && !address.starts_with('.')
+            && address.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'));
+        if !is_plain_name {
+            return None;
+        }
+        let path = self.dir.as_ref()?.join(address);
+        // symlink_metadata so a link cannot point the channel outside the directory
+        match std::fs::symlink_metadata(&path) {
+            Ok(metadata) if metadata.is_file() => Some(FileTarget::File(path)),
+            _ => None,
+        }
+    }
+}
+
+impl Notifier for FileNotifier {
+    fn channel(&self) -> &'static str {
+        CHANNEL_FILE
+    }
+
+    fn send(&self, address: &str, subject: &str, message: &str) -> AnyResult<()> {
+        let target = self.resolve(address).ok_or_else(|| anyhow!("{:?} is not stdout or a notification file set up by the operator", address))?;
+        let line = format!("[{}] {}: {}", chrono::Utc::now().format("%Y-%m-%d %H:%M:%S"), subject, message);
+        match target {
+            FileTarget::Stdout => writeln!(std::io::stdout().lock(), "{}", line)?,
+            FileTarget::File(path) => writeln!(OpenOptions::new().append(true).open(path)?, "{}", line)?,
+        }
+        Ok(())
+    }
+}
+
+// Fans a notification out to every channel an account has enabled
+pub struct NotificationDispatcher {
+    notifiers: Vec<Box<dyn Notifier>>,
+}
+
+impl NotificationDispatcher {
+    pub fn new(notifiers: Vec<Box<dyn Notifier>>) -> Self {
+        NotificationDispatcher { notifiers }
+    }
+
+    // Every channel that is configured in the environment
+    pub fn from_env() -> Self {
+        let mut notifiers: Vec<Box<dyn Notifier>> = vec![Box::new(WebhookNotifier), Box::new(FileNotifier::from_env())];
+        if let Some(telegram) = TelegramNotifier::from_env() {
+            notifiers.push(Box::new(telegram));
+        }
+        if let Some(email) = EmailNotifier::from_env() {
+            notifiers.push(Box::new(email));
+        }
+        NotificationDispatcher::new(notifiers)
+    }
+
+    // Sends an outbox event to its account, rendered for each channel in the account's language. Each channel that
+    // is reached is recorded, so a retried event only goes to the channels it has not reached yet. A failed channel
+    // does not stop delivery to the others, but fails the notification so the outbox retries it; channels with no
+    // notifier configured here are skipped. Returns how many channels were reached.
+    pub fn notify_event(&self, conn: &mut PgConnection, event: &OutboxMessage) -> AnyResult<usize> {
+        let account_id_to_notify = match event.account_id {
+            Some(account) => account,
+            None => return Ok(0),
+        };
+        let language = account_language(conn, account_id_to_notify)?;
+        let channels = get_notification_channels(conn, account_id_to_notify)?;
+        let mut delivered = 0;
+        let mut failures = Vec::new();
+        for preference in channels.iter().filter(|c| c.enabled) {
+            if has_reached(conn, preference.id, event.id)? {
+                continue;
+            }
+            let notifier = match self.notifiers.iter().find(|n| n.channel() == preference.channel) {
+                Some(notifier) => notifier,
+                None => {
+                    log_to_file(&format!("Skipped notifying account {} via {}: no notifier configured", account_id_to_notify, preference.channel));
+                    continue;
+                }
+            };
+            let (subject, message) = render_event(&event.event_type, Some(&preference.channel), &language, &event.payload)
+                .unwrap_or_else(|| (event.subject.clone(), event.message.clone()));
+            match notifier.send(&preference.address, &subject, &message) {
+                Ok(_) => {
+                    record_reached(conn, preference.id, event.id)?;
+                    delivered += 1;
+                }
+                Err(e) => {
+                    log_to_file(&format!("Failed to notify account {} via {}: {:?}", account_id_to_notify, preference.channel, e));
+                    failures.push(format!("{}: {}", preference.channel, e));
+                }
+            }
+        }
+        if !failures.is_empty() {
+            return Err(anyhow!("{}", failures.join("; ")));
+        }
+        Ok(delivered)
+    }
+}
+
+fn has_reached(conn: &mut PgConnection, channel_to_check: Uuid, event_id: Uuid) -> Result<bool, diesel::result::Error> {
+    use crate::schema::notification_deliveries::dsl::*;
+    diesel::select(diesel::dsl::exists(
+        notification_deliveries
+            .filter(channel_id.eq(channel_to_check))
+            .filter(outbox_id.eq(event_id)),
+    ))
+    .get_result(conn)
+}
+
+fn record_reached(conn: &mut PgConnection, channel_to_record: Uuid, event_id: Uuid) -> Result<usize, diesel::result::Error> {
+    use crate::schema::notification_deliveries::dsl::*;
+    diesel::insert_into(notification_deliveries)
+        .values(&NewNotificationDelivery { channel_id: channel_to_record, outbox_id: event_id })
+        .on_conflict_do_nothing()
+        .execute(conn)
+}
+
+pub fn is_valid_channel(channel_name: &str) -> bool {
+    [CHANNEL_TELEGRAM, CHANNEL_EMAIL, CHANNEL_WEBHOOK, CHANNEL_FILE].contains(&channel_name)
+}
+
+pub fn get_notification_channels(conn: &mut PgConnection, account_id_to_view: Uuid) -> Result<Vec<NotificationChannel>, diesel::result::Error> {
+    use crate::schema::notification_channels::dsl::*;
+    notification_channels
+        .filter(account_id.eq(account_id_to_view))
+        .order(created_at.asc())
+        .load::<NotificationChannel>(conn)
+}
+
+pub fn add_notification_channel(conn: &mut PgConnection, account_id_to_add: Uuid, channel_name: &str, address_to_add: &str) -> Result<NotificationChannel, diesel::result::Error> {
+    use crate::schema::notification_channels::dsl::*;
+    if !is_valid_channel(channel_name) || address_to_add.trim().is_empty() {
+        return Err(diesel::result::Error::RollbackTransaction);
+    }
+    let usable = match channel_name {
+        CHANNEL_TELEGRAM => parse_chat_id(address_to_add.trim()).is_some(),
+        CHANNEL_EMAIL => address_to_add.trim().parse::<Address>().is_ok(),
+        CHANNEL_WEBHOOK => resolve_webhook_target(address_to_add.trim()).is_ok(),
+        CHANNEL_FILE => FileNotifier::from_env().resolve(address_to_add.trim()).is_some(),
+        _ => false,
+    };
+    if !usable {
+        return Err(diesel::result::Error::RollbackTransaction);
+    }
+    let new_channel = NewNotificationChannel {
+        account_id: account_id_to_add,
+        channel: channel_name,
+        address: address_to_add.trim(),
+    };
+
+    diesel::insert_into(notification_channels)
+        .values(&new_channel)
+        .returning(NotificationChannel::as_returning())
+        .get_result(conn)
+}
+
+// Only the owning account can remove a channel
+pub fn remove_notification_channel(conn: &mut PgConnection, account_id_to_remove: Uuid, channel_id: Uuid) -> Result<usize, diesel::result::Error> {
+    use crate::schema::notification_channels::dsl::*;
+    let removed = diesel::delete(notification_channels.find(channel_id).filter(account_id.eq(account_id_to_remove)))
+        .execute(conn)?;
+    if removed == 0 {
+        return Err(diesel::result::Error::NotFound);
+    }
+    Ok(removed)
+}
+
+pub fn set_notification_channel_enabled(conn: &mut PgConnection, account_id_to_update: Uuid, channel_id: Uuid, enable: bool) -> Result<NotificationChannel, diesel::result::Error> {
+    use crate::schema::notification_channels::dsl::*;
+    diesel::update(notification_channels.find(channel_id).filter(account_id.eq(account_id_to_update)))
+        .set(enabled.eq(enable))
+        .returning(NotificationChannel::as_returning())
+        .get_result(conn)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_public_addresses_are_webhook_targets() {
+        for private in ["127.0.0.1", "10.1.2.3", "172.16.0.1", "192.168.1.1", "169.254.169.254", "100.64.0.1", "0.0.0.0", "::1", "fd00::1", "fe80::1", "::ffff:127.0.0.1"] {
+            assert!(!is_public_ip(private.parse().unwrap()), "{} should be refused", private);
+        }
+        for public in ["93.184.216.34", "8.8.8.8", "2606:4700::1111"] {
+            assert!(is_public_ip(public.parse().unwrap()), "{} should be allowed", public);
+        }
+    }
+
+    #[test]
+    fn refuses_private_and_non_http_webhook_urls() {
+        assert!(resolve_webhook_target("http://127.0.0.1:8088/hook").is_err());
+        assert!(resolve_webhook_target("http://[::1]/hook").is_err());
+        assert!(resolve_webhook_target("http://localhost/hook").is_err());
+        assert!(resolve_webhook_target("file:///etc/passwd").is_err());
+        assert!(resolve_webhook_target("https://93.184.216.34/hook").is_ok());
+    }
+
+    #[test]
+    fn file_channels_only_name_stdout_or_files_the_operator_created() {
+        let dir = std::env::temp_dir().join(format!("rust_eze_notifications_{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("accounting.log"), "").unwrap();
+        let notifier = FileNotifier { dir: Some(dir.clone()) };
+
+        assert_eq!(notifier.resolve("accounting.log"), Some(FileTarget::File(dir.join("accounting.log"))));
+        assert_eq!(notifier.resolve("stdout"), Some(FileTarget::Stdout));
+        assert_eq!(notifier.resolve("missing.log"), None);
+        assert_eq!(notifier.resolve("../accounting.log"), None);
+        assert_eq!(notifier.resolve("/etc/passwd"), None);
+        assert_eq!(notifier.resolve("/dev/stdout"), None);
+
+        notifier.send("accounting.log", "Payment received", "10.00 USD").unwrap();
+        assert!(std::fs::read_to_string(dir.join("accounting.log")).unwrap().contains("Payment received: 10.00 USD"));
+        assert!(notifier.send("new.log", "Payment received", "10.00 USD").is_err());
+        assert!(!dir.join("new.log").exists());
+        notifier.send("stdout", "Payment received", "10.00 USD").unwrap();
+        std::fs::remove_dir_all(dir).unwrap();
+
+        // Without NOTIFICATION_FILE_DIR only stdout is available
+        let stdout_only = FileNotifier { dir: None };
+        assert_eq!(stdout_only.resolve("stdout"), Some(FileTarget::Stdout));
+        assert_eq!(stdout_only.resolve("accounting.log"), None);
+    }
+
+    #[test]
+    fn telegram_and_email_addresses_must_be_well_formed() {
+        assert_eq!(parse_chat_id("123456789"), Some(123456789));
+        assert_eq!(parse_chat_id("-1001234567890"), Some(-1001234567890));
+        for invalid in ["0", "@channel", "12 34", "1e9", ""] {
+            assert_eq!(parse_chat_id(invalid), None, "{:?} should be refused", invalid);
+        }
+        assert!("customer@example.com".parse::<Address>().is_ok());
+        for invalid in ["customer", "customer@", "@example.com", "Customer <customer@example.com>"] {
+            assert!(invalid.parse::<Address>().is_err(), "{:?} should be refused", invalid);
+        }
+    }
+}
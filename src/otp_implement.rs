@@ -57,6 +57,7 @@ pub fn verify_totp(secret: &str, totp_code: &str) -> Result<bool> {
     Ok(result == "true")
 }
 
+#[allow(clippy::get_first, clippy::needless_borrow)]
 pub fn verify_totp_flow(conn: &mut PgConnection, username_to_verify: &str) -> Result<bool> {
     use crate::schema::username_password::dsl::*;
     use crate::models::UsernamePassword;
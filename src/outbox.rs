@@ -0,0 +1,231 @@
+use chrono::{Duration, Utc};
+use diesel::prelude::*;
+use diesel::pg::PgConnection;
+use serde_json::{json, Value};
+use std::env;
+use uuid::Uuid;
+use crate::models::{OutboxMessage, NewOutboxMessage, Transaction, OUTBOX_STATUS_PENDING, OUTBOX_STATUS_IN_FLIGHT, OUTBOX_STATUS_DELIVERED, OUTBOX_STATUS_DEAD};
+use crate::models::{EVENT_PAYMENT_SENT, EVENT_PAYMENT_RECEIVED};
+use crate::database::get_account_holder_name;
+use crate::moneytransfer::get_balance;
+use crate::notifications::NotificationDispatcher;
+use crate::webhooks::WebhookSender;
+use crate::templates::{render_event, DEFAULT_LANGUAGE};
+
+// Longest the relay waits between two attempts at the same message
+const MAX_BACKOFF_SECONDS: i64 = 6 * 60 * 60;
+
+// Attempts before a message is dead-lettered, from OUTBOX_MAX_ATTEMPTS (default 8)
+pub fn outbox_max_attempts() -> i32 {
+    env::var("OUTBOX_MAX_ATTEMPTS")
+        .ok()
+        .and_then(|value| value.parse::<i32>().ok())
+        .filter(|attempts| *attempts >= 1)
+        .unwrap_or(8)
+}
+
+// Delay before the first retry, doubled after every further failure, from OUTBOX_RETRY_BASE_SECONDS (default 30)
+pub fn outbox_retry_base_seconds() -> i64 {
+    env::var("OUTBOX_RETRY_BASE_SECONDS")
+        .ok()
+        .and_then(|value| value.parse::<i64>().ok())
+        .filter(|seconds| *seconds >= 1)
+        .unwrap_or(30)
+}
+
+// How long a relay may spend sending the messages it claimed, from OUTBOX_LEASE_SECONDS (default 300). Messages
+// still in flight after that are taken over by another relay.
+pub fn outbox_lease_seconds() -> i64 {
+    env::var("OUTBOX_LEASE_SECONDS")
+        .ok()
+        .and_then(|value| value.parse::<i64>().ok())
+        .filter(|seconds| *seconds >= 1)
+        .unwrap_or(300)
+}
+
+pub fn retry_backoff(attempts: i32) -> Duration {
+    let exponent = (attempts - 1).clamp(0, 20) as u32;
+    Duration::seconds((outbox_retry_base_seconds() * 2_i64.pow(exponent)).min(MAX_BACKOFF_SECONDS))
+}
+
+// Records an event for delivery. Call it inside the transaction that makes the change the event describes,
+// so the event is stored if and only if the change is. The payload holds the template variables; the stored
+// subject and message are the English rendering, used where no channel-specific template applies.
+pub fn enqueue_event(
+    conn: &mut PgConnection,
+    event: &str,
+    account_id_to_notify: Option<Uuid>,
+    event_payload: Value
+) -> Result<OutboxMessage, diesel::result::Error> {
+    use crate::schema::outbox::dsl::*;
+    let (subject_text, message_text) = render_event(event, None, DEFAULT_LANGUAGE, &event_payload)
+        .unwrap_or_else(|| (event.to_string(), event_payload.to_string()));
+    let new_message = NewOutboxMessage {
+        event_type: event,
+        account_id: account_id_to_notify,
+        subject: &subject_text,
+        message: &message_text,
+        payload: event_payload,
+    };
+
+    diesel::insert_into(outbox)
+        .values(&new_message)
+        .returning(OutboxMessage::as_returning())
+        .get_result(conn)
+}
+
+// Queues the sender's and recipient's notifications; call inside the transaction that moved the money
+pub fn enqueue_payment_events(conn: &mut PgConnection, transaction: &Transaction, from_account_id: Uuid, to_account_id: Uuid) -> Result<(), diesel::result::Error> {
+    let received_currency = transaction.received_currency.clone().unwrap_or_else(|| transaction.transfer_currency.clone());
+    let mut payload = json!({
+        "transaction_id": transaction.transaction_id.to_string(),
+        "from_account_id": from_account_id.to_string(),
+        "to_account_id": to_account_id.to_string(),
+        "payer_name": get_account_holder_name(conn, from_account_id)?,
+        "payee_name": get_account_holder_name(conn, to_account_id)?,
+        "amount": transaction.amount,
+        "currency": transaction.transfer_currency,
+        "received_amount": transaction.received_amount.unwrap_or(transaction.amount),
+        "received_currency": received_currency,
+        "memo": transaction.memo,
+        "reference": transaction.reference,
+    });
+    // Each side sees its own balance after the payment
+    let mut sent = payload.clone();
+    sent["new_balance"] = json!(get_balance(conn, from_account_id, &transaction.transfer_currency)?);
+    enqueue_event(conn, EVENT_PAYMENT_SENT, Some(from_account_id), sent)?;
+    // A payment converted into another of the recipient's sub-accounts has no balance in the received currency
+    payload["new_balance"] = json!(get_balance(conn, to_account_id, &received_currency).ok());
+    enqueue_event(conn, EVENT_PAYMENT_RECEIVED, Some(to_account_id), payload)?;
+    Ok(())
+}
+
+pub fn deposit_payload(pending_transaction_id: Uuid, amount: f64, currency: &str) -> Value {
+    json!({ "pending_transaction_id": pending_transaction_id.to_string(), "amount": amount, "currency": currency })
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RelayStats {
+    pub delivered: usize,
+    pub retrying: usize,
+    pub dead: usize,
+}
+
+// Claims up to `batch_size` due messages in a short transaction. Claimed messages are in flight until their lease
+// runs out, so other relays skip them while they are sent and take them over if this relay stops before recording
+// a result. Claiming counts as an attempt, so a message that keeps stopping relays is still dead-lettered.
+fn claim_outbox_messages(conn: &mut PgConnection, batch_size: i64) -> Result<Vec<OutboxMessage>, diesel::result::Error> {
+    use crate::schema::outbox::dsl::*;
+    conn.transaction(|conn| {
+        let now = Utc::now();
+        let due: Vec<Uuid> = outbox
+            .filter(status.eq(OUTBOX_STATUS_PENDING).and(next_attempt_at.le(now)))
+            .or_filter(status.eq(OUTBOX_STATUS_IN_FLIGHT).and(lease_expires_at.le(now)))
+            .order(next_attempt_at.asc())
+            .limit(batch_size)
+            .select(id)
+            .for_update()
+            .skip_locked()
+            .load(conn)?;
+        let mut claimed = diesel::update(outbox.filter(id.eq_any(&due)))
+            .set((
+                status.eq(OUTBOX_STATUS_IN_FLIGHT),
+                attempt_count.eq(attempt_count + 1),
+                lease_expires_at.eq(now + Duration::seconds(outbox_lease_seconds())),
+            ))
+            .returning(OutboxMessage::as_returning())
+            .get_results(conn)?;
+        claimed.sort_by_key(|claimed_message: &OutboxMessage| claimed_message.next_attempt_at);
+        Ok(claimed)
+    })
+}
+
+// Records the outcome of sending a claimed message, unless its lease ran out and another relay took it over
+fn record_outbox_result(conn: &mut PgConnection, claimed: &OutboxMessage, result: &anyhow::Result<()>) -> Result<Option<&'static str>, diesel::result::Error> {
+    use crate::schema::outbox::dsl::*;
+    let ours = outbox
+        .find(claimed.id)
+        .filter(status.eq(OUTBOX_STATUS_IN_FLIGHT))
+        .filter(lease_expires_at.eq(claimed.lease_expires_at));
+    // Each update is a single statement, so it commits on its own without holding locks across other sends
+    let (new_status, updated) = match result {
+        Ok(_) => {
+            let updated = diesel::update(ours)
+                .set((status.eq(OUTBOX_STATUS_DELIVERED), delivered_at.eq(Utc::now()), lease_expires_at.eq(None::<chrono::DateTime<Utc>>), last_error.eq(None::<String>)))
+                .execute(conn)?;
+            (OUTBOX_STATUS_DELIVERED, updated)
+        }
+        Err(e) => {
+            let new_status = if claimed.attempt_count >= outbox_max_attempts() { OUTBOX_STATUS_DEAD } else { OUTBOX_STATUS_PENDING };
+            let updated = diesel::update(ours)
+                .set((
+                    status.eq(new_status),
+                    next_attempt_at.eq(Utc::now() + retry_backoff(claimed.attempt_count)),
+                    lease_expires_at.eq(None::<chrono::DateTime<Utc>>),
+                    last_error.eq(Some(format!("{:#}", e))),
+                ))
+                .execute(conn)?;
+            (new_status, updated)
+        }
+    };
+    Ok(if updated == 1 { Some(new_status) } else { None })
+}
+
+// Delivers up to `batch_size` due messages. Messages are claimed in one short transaction and sent outside it,
+// and each result is recorded on its own, so no row locks are held while channels and endpoints are contacted and
+// webhook delivery logs are kept whatever happens to the rest of the batch. Several relays can run side by side.
+// Delivery is at least once: a retried message goes to the account's channels and webhook endpoints that have not
+// accepted it yet.
+pub fn relay_outbox(conn: &mut PgConnection, dispatcher: &NotificationDispatcher, webhooks: &WebhookSender, batch_size: i64) -> Result<RelayStats, diesel::result::Error> {
+    let claimed = claim_outbox_messages(conn, batch_size)?;
+
+    let mut stats = RelayStats::default();
+    for pending in claimed {
+        // Leave the rest to whichever relay takes over once the lease has run out
+        if pending.lease_expires_at.is_some_and(|lease| Utc::now() >= lease) {
+            break;
+        }
+        let result = if pending.attempt_count > outbox_max_attempts() {
+            // Claimed again after relays stopped while sending it
+            Err(anyhow::anyhow!("no result was recorded after {} attempts", pending.attempt_count - 1))
+        } else {
+            // Events without an account have nobody to notify and are complete once recorded
+            let notified = match pending.account_id {
+                Some(_) => dispatcher.notify_event(conn, &pending).map(|_| ()),
+                None => Ok(()),
+            };
+            match (notified, webhooks.deliver_event(conn, &pending)) {
+                (Ok(_), Ok(_)) => Ok(()),
+                (Err(e), Ok(_)) | (Ok(_), Err(e)) => Err(e),
+                (Err(channels), Err(hooks)) => Err(anyhow::anyhow!("{}; {}", channels, hooks)),
+            }
+        };
+        match record_outbox_result(conn, &pending, &result)? {
+            Some(OUTBOX_STATUS_DELIVERED) => stats.delivered += 1,
+            Some(OUTBOX_STATUS_DEAD) => stats.dead += 1,
+            Some(_) => stats.retrying += 1,
+            None => {}
+        }
+    }
+    Ok(stats)
+}
+
+// Dead-lettered messages, messages that have failed at least once and are waiting to be retried, and messages
+// whose relay stopped before finishing them, so their lease ran out
+pub fn get_stuck_outbox_messages(conn: &mut PgConnection) -> Result<Vec<OutboxMessage>, diesel::result::Error> {
+    use crate::schema::outbox::dsl::*;
+    outbox
+        .filter(status.eq(OUTBOX_STATUS_DEAD).or(status.eq(OUTBOX_STATUS_PENDING).and(attempt_count.gt(0))))
+        .or_filter(status.eq(OUTBOX_STATUS_IN_FLIGHT).and(lease_expires_at.lt(Utc::now())))
+        .order(created_at.asc())
+        .load::<OutboxMessage>(conn)
+}
+
+// Puts a dead-lettered message back in the queue with a fresh set of attempts
+pub fn requeue_outbox_message(conn: &mut PgConnection, message_id: Uuid) -> Result<OutboxMessage, diesel::result::Error> {
+    use crate::schema::outbox::dsl::*;
+    diesel::update(outbox.find(message_id).filter(status.eq(OUTBOX_STATUS_DEAD)))
+        .set((status.eq(OUTBOX_STATUS_PENDING), attempt_count.eq(0), next_attempt_at.eq(Utc::now())))
+        .returning(OutboxMessage::as_returning())
+        .get_result(conn)
+}
@@ -0,0 +1,127 @@
+use diesel::prelude::*;
+use diesel::pg::PgConnection;
+use uuid::Uuid;
+use crate::accountnumbers::{looks_like_account_number, resolve_account_id};
+use crate::database::get_account_holder_name;
+use crate::models::{Payee, NewPayee};
+
+// Who a payment goes to, however the payer named them
+#[derive(Debug, Clone)]
+pub struct Recipient {
+    pub account_id: Uuid,
+    pub holder_name: String,
+    // Set when the recipient was found in the payer's address book
+    pub nickname: Option<String>,
+    pub default_currency: Option<String>,
+}
+
+pub fn get_payees(conn: &mut PgConnection, account_id_to_view: Uuid) -> Result<Vec<Payee>, diesel::result::Error> {
+    use crate::schema::payees::dsl::*;
+    payees
+        .filter(account_id.eq(account_id_to_view))
+        .order(nickname.asc())
+        .load::<Payee>(conn)
+}
+
+// Nicknames are matched without regard to case
+pub fn find_payee(conn: &mut PgConnection, account_id_to_search: Uuid, nickname_to_find: &str) -> Result<Option<Payee>, diesel::result::Error> {
+    let nickname_to_find = nickname_to_find.trim();
+    Ok(get_payees(conn, account_id_to_search)?
+        .into_iter()
+        .find(|payee| payee.nickname.eq_ignore_ascii_case(nickname_to_find)))
+}
+
+// Adds someone to the account's address book. Nicknames must be unique within it and cannot look like an account
+// ID or account number; an unknown payee account is NotFound.
+pub fn add_payee(
+    conn: &mut PgConnection,
+    account_id_to_add: Uuid,
+    nickname_to_add: &str,
+    payee_account: Uuid,
+    currency: Option<&str>
+) -> Result<Payee, diesel::result::Error> {
+    use crate::schema::payees::dsl::*;
+    let nickname_to_add = nickname_to_add.trim();
+    if nickname_to_add.is_empty() || nickname_to_add.parse::<Uuid>().is_ok() || looks_like_account_number(nickname_to_add) || payee_account == account_id_to_add {
+        return Err(diesel::result::Error::RollbackTransaction);
+    }
+    get_account_holder_name(conn, payee_account)?;
+    if find_payee(conn, account_id_to_add, nickname_to_add)?.is_some() {
+        return Err(diesel::result::Error::RollbackTransaction);
+    }
+
+    let currency = currency.map(|c| c.trim().to_uppercase()).filter(|c| !c.is_empty());
+    diesel::insert_into(payees)
+        .values(&NewPayee {
+            account_id: account_id_to_add,
+            nickname: nickname_to_add,
+            payee_account_id: payee_account,
+            default_currency: currency.as_deref(),
+        })
+        .returning(Payee::as_returning())
+        .get_result(conn)
+}
+
+pub fn remove_payee(conn: &mut PgConnection, account_id_to_remove: Uuid, nickname_to_remove: &str) -> Result<usize, diesel::result::Error> {
+    use crate::schema::payees::dsl::*;
+    match find_payee(conn, account_id_to_remove, nickname_to_remove)? {
+        Some(payee) => diesel::delete(payees.find(payee.id)).execute(conn),
+        None => Ok(0),
+    }
+}
+
+// The account behind an account ID, account number or IBAN, one of the payer's nicknames, or another customer's
+// username, in that order. An account number with wrong check digits is RollbackTransaction; anything else that
+// matches nothing is NotFound.
+pub fn resolve_recipient(conn: &mut PgConnection, payer_account_id: Uuid, input: &str) -> Result<Recipient, diesel::result::Error> {
+    let input = input.trim();
+    if input.parse::<Uuid>().is_ok() || looks_like_account_number(input) {
+        let recipient_account_id = resolve_account_id(conn, input)?;
+        return Ok(Recipient {
+            account_id: recipient_account_id,
+            holder_name: get_account_holder_name(conn, recipient_account_id)?,
+            nickname: None,
+            default_currency: None,
+        });
+    }
+
+    if let Some(payee) = find_payee(conn, payer_account_id, input)? {
+        return Ok(Recipient {
+            account_id: payee.payee_account_id,
+            holder_name: get_account_holder_name(conn, payee.payee_account_id)?,
+            nickname: Some(payee.nickname),
+            default_currency: payee.default_currency,
+        });
+    }
+
+    use crate::schema::username_password::dsl::*;
+    let recipient_account_id = username_password
+        .find(input)
+        .select(account_id)
+        .first::<Option<Uuid>>(conn)?
+        .ok_or(diesel::result::Error::NotFound)?;
+    Ok(Recipient {
+        account_id: recipient_account_id,
+        holder_name: get_account_holder_name(conn, recipient_account_id)?,
+        nickname: None,
+        default_currency: None,
+    })
+}
+
+// Whether any of the payer's sub-accounts has sent money to any of the recipient's before
+pub fn has_paid_before(conn: &mut PgConnection, payer_account_id: Uuid, recipient_account_id: Uuid) -> Result<bool, diesel::result::Error> {
+    use crate::schema::sub_accounts;
+    use crate::schema::transactions::dsl::*;
+    let payer_subs = sub_accounts::table
+        .filter(sub_accounts::account_id.eq(payer_account_id))
+        .select(sub_accounts::id);
+    let recipient_subs = sub_accounts::table
+        .filter(sub_accounts::account_id.eq(recipient_account_id))
+        .select(sub_accounts::id);
+    let count: i64 = transactions
+        .filter(sub_account_id_from.eq_any(payer_subs.nullable()))
+        .filter(sub_account_id_to.eq_any(recipient_subs.nullable()))
+        .count()
+        .get_result(conn)?;
+    Ok(count > 0)
+}
@@ -0,0 +1,183 @@
+use chrono::{Duration, Utc};
+use diesel::prelude::*;
+use diesel::pg::PgConnection;
+use serde_json::{json, Value};
+use std::env;
+use uuid::Uuid;
+use crate::database::get_account_holder_name;
+use crate::models::{PaymentRequest, NewPaymentRequest, Transaction, PAYMENT_REQUEST_OPEN, PAYMENT_REQUEST_PAID, PAYMENT_REQUEST_DECLINED, PAYMENT_REQUEST_CANCELLED};
+use crate::models::{EVENT_PAYMENT_REQUEST_RECEIVED, EVENT_PAYMENT_REQUEST_PAID, EVENT_PAYMENT_REQUEST_DECLINED};
+use crate::moneytransfer::{transfer_money, can_receive_currency, MissingSubAccountPolicy};
+use crate::outbox::{enqueue_event, enqueue_payment_events};
+use crate::transactiondetails::{PaymentDetails, MAX_MEMO_LENGTH};
+
+// Days a request stays payable when the requester does not choose, from PAYMENT_REQUEST_EXPIRY_DAYS (default 7)
+pub fn payment_request_expiry_days() -> i64 {
+    env::var("PAYMENT_REQUEST_EXPIRY_DAYS")
+        .ok()
+        .and_then(|value| value.parse::<i64>().ok())
+        .filter(|days| *days >= 1)
+        .unwrap_or(7)
+}
+
+fn payment_request_payload(conn: &mut PgConnection, request: &PaymentRequest) -> Result<Value, diesel::result::Error> {
+    Ok(json!({
+        "payment_request_id": request.id.to_string(),
+        "requester_account_id": request.requester_account_id.to_string(),
+        "payer_account_id": request.payer_account_id.to_string(),
+        "requester_name": get_account_holder_name(conn, request.requester_account_id)?,
+        "payer_name": get_account_holder_name(conn, request.payer_account_id)?,
+        "amount": request.amount,
+        "currency": request.currency,
+        "memo": request.memo.clone().unwrap_or_else(|| "-".to_string()),
+        "expires_at": request.expires_at.format("%Y-%m-%d %H:%M UTC").to_string(),
+    }))
+}
+
+// What the requester asks the payer for
+#[derive(Debug, Clone)]
+pub struct PaymentRequestTerms<'a> {
+    pub amount: f64,
+    pub currency: &'a str,
+    pub memo: Option<&'a str>,
+    pub expires_in_days: i64,
+}
+
+// Asks the payer for money and notifies them. The requester must be able to receive the currency, and a request
+// cannot be made of oneself or for a non-positive amount; an unknown payer is NotFound.
+pub fn create_payment_request(
+    conn: &mut PgConnection,
+    requester: Uuid,
+    payer: Uuid,
+    terms: &PaymentRequestTerms,
+    policy: MissingSubAccountPolicy
+) -> Result<PaymentRequest, diesel::result::Error> {
+    use crate::schema::payment_requests::dsl::*;
+    let (request_amount, expires_in_days) = (terms.amount, terms.expires_in_days);
+    if requester == payer || !request_amount.is_finite() || request_amount <= 0.0 || expires_in_days < 1 {
+        return Err(diesel::result::Error::RollbackTransaction);
+    }
+    let request_currency = terms.currency.trim().to_uppercase();
+    let request_memo = terms.memo.map(str::trim).filter(|m| !m.is_empty());
+    if request_memo.is_some_and(|m| m.chars().count() > MAX_MEMO_LENGTH) {
+        return Err(diesel::result::Error::RollbackTransaction);
+    }
+
+    conn.transaction(|conn| {
+        get_account_holder_name(conn, payer)?;
+        if !can_receive_currency(conn, requester, &request_currency, policy)? {
+            return Err(diesel::result::Error::RollbackTransaction);
+        }
+
+        let request = diesel::insert_into(payment_requests)
+            .values(&NewPaymentRequest {
+                requester_account_id: requester,
+                payer_account_id: payer,
+                amount: request_amount,
+                currency: &request_currency,
+                memo: request_memo,
+                expires_at: Utc::now() + Duration::days(expires_in_days),
+            })
+            .returning(PaymentRequest::as_returning())
+            .get_result(conn)?;
+        let payload = payment_request_payload(conn, &request)?;
+        enqueue_event(conn, EVENT_PAYMENT_REQUEST_RECEIVED, Some(payer), payload)?;
+        Ok(request)
+    })
+}
+
+// Requests the account has been asked to pay, newest first
+pub fn get_incoming_payment_requests(conn: &mut PgConnection, account_id_to_view: Uuid) -> Result<Vec<PaymentRequest>, diesel::result::Error> {
+    use crate::schema::payment_requests::dsl::*;
+    payment_requests
+        .filter(payer_account_id.eq(account_id_to_view))
+        .order(created_at.desc())
+        .load::<PaymentRequest>(conn)
+}
+
+// Requests the account has sent, newest first
+pub fn get_outgoing_payment_requests(conn: &mut PgConnection, account_id_to_view: Uuid) -> Result<Vec<PaymentRequest>, diesel::result::Error> {
+    use crate::schema::payment_requests::dsl::*;
+    payment_requests
+        .filter(requester_account_id.eq(account_id_to_view))
+        .order(created_at.desc())
+        .load::<PaymentRequest>(conn)
+}
+
+// Whether an open request has passed its expiry and can no longer be paid
+pub fn is_expired(request: &PaymentRequest) -> bool {
+    request.status == PAYMENT_REQUEST_OPEN && request.expires_at <= Utc::now()
+}
+
+// An open, unexpired request locked until the end of the transaction
+fn get_open_request(conn: &mut PgConnection, request_id: Uuid) -> Result<PaymentRequest, diesel::result::Error> {
+    use crate::schema::payment_requests::dsl::*;
+    payment_requests
+        .find(request_id)
+        .filter(status.eq(PAYMENT_REQUEST_OPEN))
+        .filter(expires_at.gt(Utc::now()))
+        .for_update()
+        .first::<PaymentRequest>(conn)
+}
+
+// Pays a request addressed to the payer with transfer_money, notifying both sides. A request that is not the
+// payer's, was already decided or has expired is NotFound.
+pub fn accept_payment_request(
+    conn: &mut PgConnection,
+    payer: Uuid,
+    request_id: Uuid,
+    policy: MissingSubAccountPolicy
+) -> Result<Transaction, diesel::result::Error> {
+    conn.transaction(|conn| {
+        let request = get_open_request(conn, request_id)?;
+        if request.payer_account_id != payer {
+            return Err(diesel::result::Error::NotFound);
+        }
+        let details = PaymentDetails { memo: request.memo.clone(), reference: None };
+        let executed = transfer_money(conn, payer, request.requester_account_id, request.amount, &request.currency, &details, policy)?;
+        enqueue_payment_events(conn, &executed, payer, request.requester_account_id)?;
+
+        use crate::schema::payment_requests::dsl::*;
+        diesel::update(payment_requests.find(request.id))
+            .set((status.eq(PAYMENT_REQUEST_PAID), transaction_id.eq(executed.transaction_id), decided_at.eq(Utc::now())))
+            .execute(conn)?;
+        let payload = payment_request_payload(conn, &request)?;
+        enqueue_event(conn, EVENT_PAYMENT_REQUEST_PAID, Some(request.requester_account_id), payload)?;
+        Ok(executed)
+    })
+}
+
+// Declines a request addressed to the payer and tells the requester
+pub fn decline_payment_request(conn: &mut PgConnection, payer: Uuid, request_id: Uuid) -> Result<PaymentRequest, diesel::result::Error> {
+    conn.transaction(|conn| {
+        let request = get_open_request(conn, request_id)?;
+        if request.payer_account_id != payer {
+            return Err(diesel::result::Error::NotFound);
+        }
+
+        use crate::schema::payment_requests::dsl::*;
+        let declined = diesel::update(payment_requests.find(request.id))
+            .set((status.eq(PAYMENT_REQUEST_DECLINED), decided_at.eq(Utc::now())))
+            .returning(PaymentRequest::as_returning())
+            .get_result(conn)?;
+        let payload = payment_request_payload(conn, &declined)?;
+        enqueue_event(conn, EVENT_PAYMENT_REQUEST_DECLINED, Some(declined.requester_account_id), payload)?;
+        Ok(declined)
+    })
+}
+
+// Withdraws a request the requester sent; the payer is not notified
+pub fn cancel_payment_request(conn: &mut PgConnection, requester: Uuid, request_id: Uuid) -> Result<PaymentRequest, diesel::result::Error> {
+    conn.transaction(|conn| {
+        let request = get_open_request(conn, request_id)?;
+        if request.requester_account_id != requester {
+            return Err(diesel::result::Error::NotFound);
+        }
+
+        use crate::schema::payment_requests::dsl::*;
+        diesel::update(payment_requests.find(request.id))
+            .set((status.eq(PAYMENT_REQUEST_CANCELLED), decided_at.eq(Utc::now())))
+            .returning(PaymentRequest::as_returning())
+            .get_result(conn)
+    })
+}
@@ -1,15 +1,27 @@
-use chrono::{NaiveDateTime, Datelike, Utc, NaiveDate};
+use chrono::{DateTime, Datelike, Utc, NaiveDate, Weekday, Duration};
+use chrono_tz::Tz;
+use anyhow::{anyhow, Result as AnyResult};
 use diesel::prelude::*;
-use crate::moneytransfer::transfer_money;
-use crate::models::ScheduledTransaction;
+use crate::moneytransfer::{transfer_money, quote_top_up, top_up_sub_account, get_balance, find_destination, Destination, MissingSubAccountPolicy};
+use crate::transactiondetails::PaymentDetails;
+use crate::database::get_account_holder_name;
+use crate::models::{ScheduledTransaction, ScheduledRun, NewScheduledRun, SCHEDULE_KIND_ONE_OFF, SCHEDULE_KIND_RECURRING, SCHEDULE_STATUS_ACTIVE, SCHEDULE_STATUS_PAUSED};
+use crate::models::{RUN_STATUS_PENDING, RUN_STATUS_SUCCEEDED, RUN_STATUS_RETRYING, RUN_STATUS_FAILED, RUN_STATUS_SKIPPED};
+use uuid::Uuid;
 use std::fs::OpenOptions;
 use std::io::Write;
 use chrono::Local;
 use dotenvy::dotenv;
-use crate::sendalert::send_telegram_alert;
+use crate::outbox::enqueue_event;
+use crate::models::{EVENT_SCHEDULE_EXECUTED, EVENT_SCHEDULE_FAILED, EVENT_SCHEDULE_PAUSED, EVENT_SCHEDULE_SKIPPED, EVENT_BALANCE_LOW, EVENT_TOP_UP_SUCCEEDED, EVENT_TOP_UP_FAILED};
+use serde_json::{json, Value};
+use crate::timezones::{account_time_zone, local_to_utc, local_date};
+use crate::calendars::{BusinessDayConvention, HolidayCalendar, MAX_BUSINESS_DAY_ROLL, calendar_dir};
+use std::collections::HashMap;
 use std::env;
 
    
+#[allow(clippy::expect_fun_call)]
 pub fn establish_connection() -> PgConnection {
     dotenv().ok();
     let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
@@ -18,66 +30,997 @@ pub fn establish_connection() -> PgConnection {
 }
 
 
-pub async fn process_scheduled_transactions(conn: &mut PgConnection) -> Result<(), diesel::result::Error> {
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Frequency {
+    Daily,
+    Weekly,
+    Monthly,
+    Yearly,
+}
+
+// A subset of iCalendar RRULE: FREQ, INTERVAL, BYDAY (weekly only), BYMONTH (yearly only) and BYMONTHDAY
+// (monthly or yearly, -1 is the last day). Days a short month lacks clamp to its last day rather than being skipped.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Recurrence {
+    pub frequency: Frequency,
+    pub interval: u32,
+    pub by_day: Vec<Weekday>,
+    pub by_month: Vec<u32>,
+    pub by_month_day: Vec<i32>,
+}
+
+impl Recurrence {
+    // Accepts a preset (daily, weekly, biweekly, monthly, quarterly, yearly) or an RRULE such as
+    // "FREQ=WEEKLY;INTERVAL=2;BYDAY=MO,FR" or "FREQ=MONTHLY;BYMONTHDAY=-1"
+    pub fn parse(rule: &str) -> AnyResult<Recurrence> {
+        let rule = rule.trim();
+        let preset = |frequency, interval| Recurrence { frequency, interval, by_day: Vec::new(), by_month: Vec::new(), by_month_day: Vec::new() };
+        match rule.to_ascii_lowercase().as_str() {
+            "daily" => return Ok(preset(Frequency::Daily, 1)),
+            "weekly" => return Ok(preset(Frequency::Weekly, 1)),
+            "biweekly" | "fortnightly" => return Ok(preset(Frequency::Weekly, 2)),
+            "monthly" => return Ok(preset(Frequency::Monthly, 1)),
+            "quarterly" => return Ok(preset(Frequency::Monthly, 3)),
+            "yearly" | "annually" => return Ok(preset(Frequency::Yearly, 1)),
+            _ => {}
+        }
+
+        let body = rule.strip_prefix("RRULE:").unwrap_or(rule);
+        let mut frequency = None;
+        let mut interval = 1;
+        let mut by_day = Vec::new();
+        let mut by_month = Vec::new();
+        let mut by_month_day = Vec::new();
+
+        for part in body.split(';').filter(|p| !p.is_empty()) {
+            let (key, value) = part.split_once('=')
+                .ok_or_else(|| anyhow!("Invalid recurrence rule part: {}", part))?;
+            match key.to_ascii_uppercase().as_str() {
+                "FREQ" => {
+                    frequency = Some(match value.to_ascii_uppercase().as_str() {
+                        "DAILY" => Frequency::Daily,
+                        "WEEKLY" => Frequency::Weekly,
+                        "MONTHLY" => Frequency::Monthly,
+                        "YEARLY" => Frequency::Yearly,
+                        other => return Err(anyhow!("Unsupported FREQ: {}", other)),
+                    });
+                }
+                "INTERVAL" => {
+                    interval = value.parse::<u32>()
+                        .ok()
+                        .filter(|i| *i > 0)
+                        .ok_or_else(|| anyhow!("INTERVAL must be a positive integer"))?;
+                }
+                "BYDAY" => {
+                    for day in value.split(',') {
+                        by_day.push(parse_weekday(day)?);
+                    }
+                }
+                "BYMONTH" => {
+                    for month in value.split(',') {
+                        let month = month.parse::<u32>()
+                            .ok()
+                            .filter(|m| (1..=12).contains(m))
+                            .ok_or_else(|| anyhow!("BYMONTH must be between 1 and 12"))?;
+                        by_month.push(month);
+                    }
+                }
+                "BYMONTHDAY" => {
+                    for day in value.split(',') {
+                        let day = day.parse::<i32>()
+                            .ok()
+                            .filter(|d| (1..=31).contains(d) || (-31..=-1).contains(d))
+                            .ok_or_else(|| anyhow!("BYMONTHDAY must be between 1 and 31 or -31 and -1"))?;
+                        by_month_day.push(day);
+                    }
+                }
+                other => return Err(anyhow!("Unsupported recurrence rule part: {}", other)),
+            }
+        }
+
+        let frequency = frequency.ok_or_else(|| anyhow!("Recurrence rule must include FREQ"))?;
+        if !by_day.is_empty() && frequency != Frequency::Weekly {
+            return Err(anyhow!("BYDAY is only supported with FREQ=WEEKLY"));
+        }
+        if !by_month.is_empty() && frequency != Frequency::Yearly {
+            return Err(anyhow!("BYMONTH is only supported with FREQ=YEARLY"));
+        }
+        if !by_month_day.is_empty() && frequency != Frequency::Monthly && frequency != Frequency::Yearly {
+            return Err(anyhow!("BYMONTHDAY is only supported with FREQ=MONTHLY or FREQ=YEARLY"));
+        }
+
+        by_day.sort_by_key(|d| d.num_days_from_monday());
+        by_day.dedup();
+        by_month.sort();
+        by_month.dedup();
+        Ok(Recurrence { frequency, interval, by_day, by_month, by_month_day })
+    }
+
+    // Pins monthly and yearly rules to the start date's day (and month) so that a short month or year clamping
+    // one occurrence does not make every later one drift, e.g. a yearly payment on 29 February stays on the 29th
+    pub fn anchored_to(mut self, start: NaiveDate) -> Recurrence {
+        if self.frequency == Frequency::Yearly && self.by_month.is_empty() {
+            self.by_month.push(start.month());
+        }
+        if (self.frequency == Frequency::Monthly || self.frequency == Frequency::Yearly) && self.by_month_day.is_empty() {
+            self.by_month_day.push(start.day() as i32);
+        }
+        self
+    }
+
+    // Moves a rule pinned to one day (and month) to a new start date, e.g. when a schedule's next date is edited;
+    // rules listing several days keep them
+    pub fn reanchored_to(mut self, start: NaiveDate) -> Recurrence {
+        if self.by_month_day.len() == 1 {
+            self.by_month_day.clear();
+        }
+        if self.frequency == Frequency::Yearly && self.by_month.len() == 1 {
+            self.by_month.clear();
+        }
+        self.anchored_to(start)
+    }
+
+    // Whether the rule can fall on the date, ignoring the interval
+    pub fn includes(&self, date: NaiveDate) -> bool {
+        let on_month_day = self.by_month_day.is_empty() || month_days(date.year(), date.month(), &self.by_month_day).contains(&date);
+        match self.frequency {
+            Frequency::Daily => true,
+            Frequency::Weekly => self.by_day.is_empty() || self.by_day.contains(&date.weekday()),
+            Frequency::Monthly => on_month_day,
+            Frequency::Yearly => (self.by_month.is_empty() || self.by_month.contains(&date.month())) && on_month_day,
+        }
+    }
+
+    // The first occurrence of a schedule starting on the date: the date itself if the rule includes it
+    pub fn first_on_or_after(&self, date: NaiveDate) -> NaiveDate {
+        if self.includes(date) { date } else { self.next_after(date) }
+    }
+
+    pub fn to_rrule(&self) -> String {
+        let frequency = match self.frequency {
+            Frequency::Daily => "DAILY",
+            Frequency::Weekly => "WEEKLY",
+            Frequency::Monthly => "MONTHLY",
+            Frequency::Yearly => "YEARLY",
+        };
+        let mut rule = format!("FREQ={}", frequency);
+        if self.interval != 1 {
+            rule.push_str(&format!(";INTERVAL={}", self.interval));
+        }
+        if !self.by_day.is_empty() {
+            let days: Vec<&str> = self.by_day.iter().map(|d| weekday_code(*d)).collect();
+            rule.push_str(&format!(";BYDAY={}", days.join(",")));
+        }
+        if !self.by_month.is_empty() {
+            let months: Vec<String> = self.by_month.iter().map(|m| m.to_string()).collect();
+            rule.push_str(&format!(";BYMONTH={}", months.join(",")));
+        }
+        if !self.by_month_day.is_empty() {
+            let days: Vec<String> = self.by_month_day.iter().map(|d| d.to_string()).collect();
+            rule.push_str(&format!(";BYMONTHDAY={}", days.join(",")));
+        }
+        rule
+    }
+
+    // The first occurrence strictly after the given date
+    pub fn next_after(&self, date: NaiveDate) -> NaiveDate {
+        match self.frequency {
+            Frequency::Daily => date + Duration::days(self.interval as i64),
+            Frequency::Weekly => {
+                if self.by_day.is_empty() {
+                    return date + Duration::weeks(self.interval as i64);
+                }
+                // Remaining days in this week, otherwise the first listed day `interval` weeks on
+                let week_start = date - Duration::days(date.weekday().num_days_from_monday() as i64);
+                if let Some(day) = self.by_day.iter().find(|d| d.num_days_from_monday() > date.weekday().num_days_from_monday()) {
+                    return week_start + Duration::days(day.num_days_from_monday() as i64);
+                }
+                week_start + Duration::weeks(self.interval as i64) + Duration::days(self.by_day[0].num_days_from_monday() as i64)
+            }
+            Frequency::Monthly => {
+                let by_month_day = if self.by_month_day.is_empty() { vec![date.day() as i32] } else { self.by_month_day.clone() };
+                // Later days in this month, otherwise the first listed day `interval` months on
+                if let Some(next) = month_days(date.year(), date.month(), &by_month_day).into_iter().find(|d| *d > date) {
+                    return next;
+                }
+                let (year, month) = add_months(date.year(), date.month(), self.interval);
+                month_days(year, month, &by_month_day)[0]
+            }
+            Frequency::Yearly => {
+                let by_month = if self.by_month.is_empty() { vec![date.month()] } else { self.by_month.clone() };
+                let by_month_day = if self.by_month_day.is_empty() { vec![date.day() as i32] } else { self.by_month_day.clone() };
+                let year_days = |year| by_month.iter().flat_map(|month| month_days(year, *month, &by_month_day)).collect::<Vec<_>>();
+                // Later days in this year, otherwise the first listed day `interval` years on
+                if let Some(next) = year_days(date.year()).into_iter().find(|d| *d > date) {
+                    return next;
+                }
+                year_days(date.year() + self.interval as i32)[0]
+            }
+        }
+    }
+}
+
+// What the processor does with occurrences that came due while it was not running
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CatchUpPolicy {
+    // Execute every missed occurrence inside the look-back window
+    ExecuteAll,
+    // Execute only the most recent missed occurrence
+    LatestOnly,
+    // Execute nothing that was missed, only today's occurrence, and notify about the rest
+    SkipAndNotify,
+}
+
+impl CatchUpPolicy {
+    pub fn parse(value: &str) -> Option<CatchUpPolicy> {
+        match value {
+            "execute_all" => Some(CatchUpPolicy::ExecuteAll),
+            "latest_only" => Some(CatchUpPolicy::LatestOnly),
+            "skip_and_notify" => Some(CatchUpPolicy::SkipAndNotify),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CatchUpPolicy::ExecuteAll => "execute_all",
+            CatchUpPolicy::LatestOnly => "latest_only",
+            CatchUpPolicy::SkipAndNotify => "skip_and_notify",
+        }
+    }
+}
+
+// How a schedule repeats and when it stops; no recurrence means a one-off payment
+#[derive(Debug, Clone)]
+pub struct ScheduleOptions {
+    pub recurrence: Option<Recurrence>,
+    pub end_date: Option<NaiveDate>,
+    pub occurrence_count: Option<i32>,
+    pub catch_up_policy: CatchUpPolicy,
+    pub business_day_convention: BusinessDayConvention,
+    // Memo and reference given to every payment the schedule makes
+    pub details: PaymentDetails,
+}
+
+impl ScheduleOptions {
+    pub fn new(recurrence: Recurrence) -> Self {
+        ScheduleOptions { recurrence: Some(recurrence), ..ScheduleOptions::one_off() }
+    }
+
+    pub fn one_off() -> Self {
+        ScheduleOptions {
+            recurrence: None,
+            end_date: None,
+            occurrence_count: None,
+            catch_up_policy: CatchUpPolicy::ExecuteAll,
+            business_day_convention: BusinessDayConvention::Unadjusted,
+            details: PaymentDetails::none(),
+        }
+    }
+
+    pub fn kind(&self) -> &'static str {
+        if self.recurrence.is_some() { SCHEDULE_KIND_RECURRING } else { SCHEDULE_KIND_ONE_OFF }
+    }
+}
+
+fn parse_weekday(code: &str) -> AnyResult<Weekday> {
+    match code.trim().to_ascii_uppercase().as_str() {
+        "MO" => Ok(Weekday::Mon),
+        "TU" => Ok(Weekday::Tue),
+        "WE" => Ok(Weekday::Wed),
+        "TH" => Ok(Weekday::Thu),
+        "FR" => Ok(Weekday::Fri),
+        "SA" => Ok(Weekday::Sat),
+        "SU" => Ok(Weekday::Sun),
+        other => Err(anyhow!("Invalid BYDAY value: {}", other)),
+    }
+}
+
+fn weekday_code(day: Weekday) -> &'static str {
+    match day {
+        Weekday::Mon => "MO",
+        Weekday::Tue => "TU",
+        Weekday::Wed => "WE",
+        Weekday::Thu => "TH",
+        Weekday::Fri => "FR",
+        Weekday::Sat => "SA",
+        Weekday::Sun => "SU",
+    }
+}
+
+pub fn last_day_of_month(year: i32, month: u32) -> u32 {
+    let (next_year, next_month) = add_months(year, month, 1);
+    NaiveDate::from_ymd_opt(next_year, next_month, 1).unwrap().pred_opt().unwrap().day()
+}
+
+fn add_months(year: i32, month: u32, months: u32) -> (i32, u32) {
+    let zero_based = month - 1 + months;
+    (year + (zero_based / 12) as i32, zero_based % 12 + 1)
+}
+
+// Resolves BYMONTHDAY values for one month: days past the end clamp to the last day, negatives count back from it
+fn month_days(year: i32, month: u32, by_month_day: &[i32]) -> Vec<NaiveDate> {
+    let last = last_day_of_month(year, month) as i32;
+    let mut days: Vec<NaiveDate> = by_month_day
+        .iter()
+        .map(|d| if *d > 0 { (*d).min(last) } else { (last + d + 1).max(1) })
+        .map(|d| NaiveDate::from_ymd_opt(year, month, d as u32).unwrap())
+        .collect();
+    days.sort();
+    days.dedup();
+    days
+}
+
+// Up to `limit` occurrences from `start` on, stopping at the end date or occurrence count
+pub fn upcoming_occurrences(
+    recurrence: &Recurrence,
+    start: NaiveDate,
+    end_date_limit: Option<NaiveDate>,
+    occurrence_count_limit: Option<i32>,
+    limit: usize
+) -> Vec<NaiveDate> {
+    let mut occurrences = Vec::new();
+    let mut current = recurrence.first_on_or_after(start);
+    while occurrences.len() < limit {
+        if end_date_limit.is_some_and(|end| current > end) {
+            break;
+        }
+        if occurrence_count_limit.is_some_and(|count| occurrences.len() as i32 >= count) {
+            break;
+        }
+        occurrences.push(current);
+        current = recurrence.next_after(current);
+    }
+    occurrences
+}
+
+// How many days back the processor will still execute missed occurrences, from SCHEDULE_LOOKBACK_DAYS (default 7)
+pub fn lookback_days() -> i64 {
+    env::var("SCHEDULE_LOOKBACK_DAYS")
+        .ok()
+        .and_then(|value| value.parse::<i64>().ok())
+        .filter(|days| *days >= 0)
+        .unwrap_or(7)
+}
+
+// Attempts per occurrence before it is marked failed, from SCHEDULE_MAX_ATTEMPTS (default 3)
+pub fn max_attempts() -> i32 {
+    env::var("SCHEDULE_MAX_ATTEMPTS")
+        .ok()
+        .and_then(|value| value.parse::<i32>().ok())
+        .filter(|attempts| *attempts >= 1)
+        .unwrap_or(3)
+}
+
+// Minimum gap between retries of the same occurrence, from SCHEDULE_RETRY_INTERVAL_MINUTES (default 60)
+pub fn retry_interval_minutes() -> i64 {
+    env::var("SCHEDULE_RETRY_INTERVAL_MINUTES")
+        .ok()
+        .and_then(|value| value.parse::<i64>().ok())
+        .filter(|minutes| *minutes >= 0)
+        .unwrap_or(60)
+}
+
+// Consecutive failed occurrences after which a schedule is paused, from SCHEDULE_PAUSE_AFTER_FAILURES (default 3)
+pub fn pause_after_failures() -> i32 {
+    env::var("SCHEDULE_PAUSE_AFTER_FAILURES")
+        .ok()
+        .and_then(|value| value.parse::<i32>().ok())
+        .filter(|failures| *failures >= 1)
+        .unwrap_or(3)
+}
+
+// Days ahead the scheduler checks sub-accounts can cover scheduled payments, from BALANCE_WARNING_DAYS (default 3)
+pub fn balance_warning_days() -> i64 {
+    env::var("BALANCE_WARNING_DAYS")
+        .ok()
+        .and_then(|value| value.parse::<i64>().ok())
+        .filter(|days| *days >= 0)
+        .unwrap_or(3)
+}
+
+// Runs are keyed by the instant the occurrence was due
+fn get_or_create_run(conn: &mut PgConnection, schedule_id: Uuid, occurrence_at: DateTime<Utc>) -> Result<ScheduledRun, diesel::result::Error> {
+    use crate::schema::scheduled_runs::dsl::*;
+    let new_run = NewScheduledRun {
+        scheduled_transaction_id: schedule_id,
+        occurrence_date: occurrence_at,
+        status: RUN_STATUS_PENDING,
+    };
+
+    diesel::insert_into(scheduled_runs)
+        .values(&new_run)
+        .on_conflict((scheduled_transaction_id, occurrence_date))
+        .do_nothing()
+        .execute(conn)?;
+
+    scheduled_runs
+        .filter(scheduled_transaction_id.eq(schedule_id))
+        .filter(occurrence_date.eq(occurrence_at))
+        .first::<ScheduledRun>(conn)
+}
+
+fn update_run(conn: &mut PgConnection, run_id: Uuid, new_status: &str, attempts: i32, error: Option<&str>, executed_transaction_id: Option<Uuid>) -> Result<(), diesel::result::Error> {
+    use crate::schema::scheduled_runs::dsl::*;
+    diesel::update(scheduled_runs.find(run_id))
+        .set((
+            status.eq(new_status),
+            attempt_count.eq(attempts),
+            last_error.eq(error),
+            transaction_id.eq(executed_transaction_id),
+            updated_at.eq(Utc::now()),
+        ))
+        .execute(conn)?;
+    Ok(())
+}
+
+// The fields every scheduled-payment event carries
+fn schedule_payload(conn: &mut PgConnection, transaction: &ScheduledTransaction) -> Result<Value, diesel::result::Error> {
+    Ok(json!({
+        "schedule_id": transaction.id.to_string(),
+        "from_account_id": transaction.from_account_id.to_string(),
+        "to_account_id": transaction.to_account_id.to_string(),
+        "payee_name": get_account_holder_name(conn, transaction.to_account_id)?,
+        "amount": transaction.amount,
+        "currency": transaction.currency,
+    }))
+}
+
+fn failed_payload(conn: &mut PgConnection, transaction: &ScheduledTransaction, occurrence: NaiveDate, attempt: i32, error: &diesel::result::Error) -> Result<Value, diesel::result::Error> {
+    let mut payload = schedule_payload(conn, transaction)?;
+    payload["occurrence"] = json!(occurrence.to_string());
+    payload["attempt"] = json!(attempt);
+    payload["max_attempts"] = json!(max_attempts());
+    payload["error"] = json!(format!("{:?}", error));
+    Ok(payload)
+}
+
+pub fn schedule_rule(transaction: &ScheduledTransaction, tz: Tz) -> Option<Recurrence> {
+    if transaction.kind == SCHEDULE_KIND_ONE_OFF {
+        return None;
+    }
+    // Rules are validated when saved; fall back to the historical monthly behaviour if one is not
+    let rule = Recurrence::parse(transaction.recurrence.as_deref().unwrap_or("monthly"))
+        .unwrap_or_else(|_| Recurrence::parse("monthly").unwrap())
+        .anchored_to(local_date(tz, transaction.scheduled_date));
+    Some(rule)
+}
+
+// Every local date from the schedule's current date up to and including `until`, within its end date
+fn due_occurrences(transaction: &ScheduledTransaction, rule: Option<&Recurrence>, tz: Tz, until: NaiveDate) -> Vec<NaiveDate> {
+    let end = transaction.end_date.map(|d| local_date(tz, d));
+    let mut occurrences = Vec::new();
+    let start = local_date(tz, transaction.scheduled_date);
+    let mut current = rule.map_or(start, |rule| rule.first_on_or_after(start));
+    while current <= until && end.is_none_or(|end| current <= end) {
+        occurrences.push(current);
+        match rule {
+            Some(rule) => current = rule.next_after(current),
+            None => break,
+        }
+    }
+    occurrences
+}
+
+// The currency's holiday calendar, read once per batch and kept in `calendars`. A broken holiday file falls back
+// to weekends only and is logged.
+fn schedule_calendar<'a>(calendars: &'a mut HashMap<String, HolidayCalendar>, schedule_currency: &str) -> &'a HolidayCalendar {
+    calendars.entry(schedule_currency.to_uppercase()).or_insert_with(|| {
+        HolidayCalendar::for_currency(schedule_currency).unwrap_or_else(|e| {
+            log_to_file(&format!("Failed to load holiday calendar for {}, using weekends only: {}", schedule_currency, e));
+            HolidayCalendar::weekends_only()
+        })
+    })
+}
+
+// Past the end of its holiday list a calendar only knows about weekends, so a holiday there would go unnoticed
+fn warn_past_calendar(calendar: &HolidayCalendar, schedule: &ScheduledTransaction, occurrence: NaiveDate) {
+    if let Some(until) = calendar.covered_until().filter(|until| occurrence > *until) {
+        log_to_file(&format!("Holiday calendar for {} ends {}; the payment of schedule {} on {} is only moved for weekends. Add the holidays to {}",
+            schedule.currency, until, schedule.id, occurrence, calendar_dir().join(format!("{}.txt", schedule.currency.to_uppercase())).display()));
+    }
+}
+
+// Payees missing a sub-account in the schedule's currency are handled by sub_account_policy
+pub fn process_scheduled_transactions(
+    conn: &mut PgConnection,
+    sub_account_policy: MissingSubAccountPolicy
+) -> Result<(), diesel::result::Error> {
     use crate::schema::scheduled_transactions::dsl::*;
-    let time_to_filter_by = Utc::now().naive_utc().date().and_hms_opt(0, 0, 0).unwrap();
-    //println!("Time to filter by: {:?}", time_to_filter_by);
-    // Filter for transactions scheduled for today
+    let now = Utc::now();
+    // Pick up everything that is due, including occurrences missed while the processor was not running.
+    // Look a little ahead too, since a preceding business-day convention can move a payment earlier.
     let pending_transactions: Vec<ScheduledTransaction> = scheduled_transactions
         .filter(executed.eq(false))
-        .filter(scheduled_date.eq(time_to_filter_by)) 
+        .filter(status.eq(SCHEDULE_STATUS_ACTIVE))
+        .filter(scheduled_date.le(now + Duration::days(MAX_BUSINESS_DAY_ROLL)))
         .load(conn)?;
 
+    let mut calendars = HashMap::new();
     for transaction in pending_transactions {
-        // Execute the transfer
-        match transfer_money(
-            conn,
-            transaction.from_account_id,
-            transaction.to_account_id,
-            transaction.amount,
-            &transaction.currency,
-        ) {
+        let calendar = schedule_calendar(&mut calendars, &transaction.currency);
+        process_scheduled_transaction(conn, &transaction, now, calendar, sub_account_policy)?;
+    }
+
+    Ok(())
+}
+
+fn process_scheduled_transaction(
+    conn: &mut PgConnection,
+    transaction: &ScheduledTransaction,
+    now: DateTime<Utc>,
+    calendar: &HolidayCalendar,
+    sub_account_policy: MissingSubAccountPolicy
+) -> Result<(), diesel::result::Error> {
+    use crate::schema::scheduled_transactions::dsl::*;
+    // Dates are in the paying account's zone. Occurrences keep their nominal date, which drives the recurrence
+    // and keys the run, but are paid on the business day given by the schedule's convention.
+    let tz = account_time_zone(conn, transaction.from_account_id)?;
+    let today = local_date(tz, now);
+    let convention = BusinessDayConvention::parse(&transaction.business_day_convention).unwrap_or(BusinessDayConvention::Unadjusted);
+    let payment_date = |occurrence: NaiveDate| calendar.adjust(occurrence, convention);
+    let nominal_at = |occurrence: NaiveDate| local_to_utc(tz, occurrence, transaction.execution_time);
+    let rule = schedule_rule(transaction, tz);
+    let policy = CatchUpPolicy::parse(&transaction.catch_up_policy).unwrap_or(CatchUpPolicy::ExecuteAll);
+    let occurrences: Vec<NaiveDate> = due_occurrences(transaction, rule.as_ref(), tz, today + Duration::days(MAX_BUSINESS_DAY_ROLL))
+        .into_iter()
+        .filter(|occurrence| local_to_utc(tz, payment_date(*occurrence), transaction.execution_time) <= now)
+        .collect();
+    if convention != BusinessDayConvention::Unadjusted {
+        if let Some(last) = occurrences.last() {
+            warn_past_calendar(calendar, transaction, *last);
+        }
+    }
+    let lookback_start = today - Duration::days(lookback_days());
+
+    // Occurrences outside the look-back window are never executed; the policy decides among the rest
+    let in_window: Vec<NaiveDate> = occurrences.iter().copied().filter(|d| payment_date(*d) >= lookback_start).collect();
+    let to_execute: Vec<NaiveDate> = match policy {
+        CatchUpPolicy::ExecuteAll => in_window,
+        CatchUpPolicy::LatestOnly => in_window.last().copied().into_iter().collect(),
+        CatchUpPolicy::SkipAndNotify => in_window.into_iter().filter(|d| payment_date(*d) == today).collect(),
+    };
+
+    let mut executed_so_far = transaction.occurrences_executed;
+    let mut consecutive = transaction.consecutive_failures;
+    let mut skipped = Vec::new();
+    let mut failed_on = None;
+    let mut last_handled = None;
+    let mut paused = false;
+
+    for occurrence in occurrences {
+        if transaction.occurrence_count.is_some_and(|count| executed_so_far >= count) {
+            break;
+        }
+        let run = get_or_create_run(conn, transaction.id, nominal_at(occurrence))?;
+
+        // Occurrences already settled by an earlier run are not executed again
+        if run.status == RUN_STATUS_SUCCEEDED || run.status == RUN_STATUS_FAILED || run.status == RUN_STATUS_SKIPPED {
+            last_handled = Some(occurrence);
+            continue;
+        }
+        if !to_execute.contains(&occurrence) {
+            update_run(conn, run.id, RUN_STATUS_SKIPPED, run.attempt_count, None, None)?;
+            skipped.push(occurrence);
+            last_handled = Some(occurrence);
+            continue;
+        }
+        // Spread retries across the day instead of hammering a failing transfer on every run
+        if run.status == RUN_STATUS_RETRYING && now < run.updated_at + Duration::minutes(retry_interval_minutes()) {
+            failed_on = Some(occurrence);
+            break;
+        }
+
+        // Execute the transfer, record the run and queue the notification together so a crash cannot pay twice
+        let attempt = run.attempt_count + 1;
+        let result = conn.transaction(|conn| {
+            let executed_transaction = transfer_money(
+                conn,
+                transaction.from_account_id,
+                transaction.to_account_id,
+                transaction.amount,
+                &transaction.currency,
+                &PaymentDetails { memo: transaction.memo.clone(), reference: transaction.reference.clone() },
+                sub_account_policy,
+            )?;
+            update_run(conn, run.id, RUN_STATUS_SUCCEEDED, attempt, None, Some(executed_transaction.transaction_id))?;
+            let mut payload = schedule_payload(conn, transaction)?;
+            payload["occurrence"] = json!(occurrence.to_string());
+            payload["transaction_id"] = json!(executed_transaction.transaction_id.to_string());
+            payload["new_balance"] = json!(get_balance(conn, transaction.from_account_id, &transaction.currency)?);
+            enqueue_event(conn, EVENT_SCHEDULE_EXECUTED, Some(transaction.from_account_id), payload)?;
+            Ok::<_, diesel::result::Error>(executed_transaction)
+        });
+
+        match result {
             Ok(_) => {
-                let current_date = transaction.scheduled_date;
-                let current_month = current_date.month();
-                let current_year = current_date.year();
-                let next_month = if current_month == 12 {
-                    1 // January
-                } else {
-                    current_month + 1
-                };
-                
-                // Calculate the next year if the month rolls over
-                let next_year = if next_month == 1 {
-                    current_year + 1
-                } else {
-                    current_year
-                };
-
-                let day = current_date.day();
-                let last_day_of_next_month = NaiveDate::from_ymd_opt(next_year, next_month, 1).unwrap().pred_opt().unwrap().day();
-                let next_day = if day > last_day_of_next_month {last_day_of_next_month} else {day};
-
-                let next_date = NaiveDateTime::new(NaiveDate::from_ymd_opt(next_year, next_month, next_day).unwrap(), current_date.time());
-                // Mark the scheduled transaction as executed
+                executed_so_far += 1;
+                consecutive = 0;
+                last_handled = Some(occurrence);
+            }
+            Err(e) if attempt < max_attempts() => {
+                // Stay on the failed occurrence so a later run today tries it again; the payer is only told
+                // once it has run out of retries
+                update_run(conn, run.id, RUN_STATUS_RETRYING, attempt, Some(&format!("{:?}", e)), None)?;
+                failed_on = Some(occurrence);
+                println!("Failed to execute scheduled transaction {:?}: {:?}", transaction, e);
+                break;
+            }
+            Err(e) => {
+                // Out of retries: give up on this occurrence and move on to the next one
+                conn.transaction(|conn| {
+                    update_run(conn, run.id, RUN_STATUS_FAILED, attempt, Some(&format!("{:?}", e)), None)?;
+                    let payload = failed_payload(conn, transaction, occurrence, attempt, &e)?;
+                    enqueue_event(conn, EVENT_SCHEDULE_FAILED, Some(transaction.from_account_id), payload)?;
+                    Ok::<_, diesel::result::Error>(())
+                })?;
+                consecutive += 1;
+                last_handled = Some(occurrence);
+                println!("Failed to execute scheduled transaction {:?}: {:?}", transaction, e);
+                if consecutive >= pause_after_failures() {
+                    paused = true;
+                    break;
+                }
+            }
+        }
+    }
+
+    if consecutive != transaction.consecutive_failures || paused {
+        conn.transaction(|conn| {
+            diesel::update(scheduled_transactions.find(transaction.id))
+                .set((
+                    consecutive_failures.eq(consecutive),
+                    status.eq(if paused { SCHEDULE_STATUS_PAUSED } else { SCHEDULE_STATUS_ACTIVE }),
+                ))
+                .execute(conn)?;
+            if paused {
+                let mut payload = schedule_payload(conn, transaction)?;
+                payload["consecutive_failures"] = json!(consecutive);
+                enqueue_event(conn, EVENT_SCHEDULE_PAUSED, Some(transaction.from_account_id), payload)?;
+            }
+            Ok::<_, diesel::result::Error>(())
+        })?;
+    }
+
+    let next_date = match (failed_on, last_handled, rule.as_ref()) {
+        (Some(failed), _, _) => Some(failed),
+        (None, Some(last), Some(rule)) => Some(rule.next_after(last)),
+        (None, Some(_), None) => None,
+        (None, None, _) => {
+            // Nothing left to run: close out schedules that are already past their end date
+            if transaction.end_date.is_some_and(|end| transaction.scheduled_date > end) {
                 diesel::update(scheduled_transactions.find(transaction.id))
-                    .set(scheduled_date.eq(next_date))
+                    .set(executed.eq(true))
                     .execute(conn)?;
+            }
+            return Ok(());
+        }
+    };
+
+    // The schedule is finished once a one-off has been handled, or the occurrence count or end date is reached
+    let finished = match next_date {
+        None => true,
+        Some(next) => transaction.occurrence_count.is_some_and(|count| executed_so_far >= count)
+            || transaction.end_date.is_some_and(|end| next > local_date(tz, end)),
+    };
+
+    conn.transaction(|conn| {
+        diesel::update(scheduled_transactions.find(transaction.id))
+            .set((
+                scheduled_date.eq(next_date.map(nominal_at).unwrap_or(transaction.scheduled_date)),
+                occurrences_executed.eq(executed_so_far),
+                executed.eq(finished),
+            ))
+            .execute(conn)?;
+
+        if !skipped.is_empty() {
+            let dates: Vec<String> = skipped.iter().map(|d| d.to_string()).collect();
+            let mut payload = schedule_payload(conn, transaction)?;
+            payload["occurrences"] = json!(dates);
+            enqueue_event(conn, EVENT_SCHEDULE_SKIPPED, Some(transaction.from_account_id), payload)?;
+        }
+        Ok(())
+    })
+}
+
 
-                send_telegram_alert(&format!("Executed scheduled transaction: {:?}", transaction)).await;
-                //println!("Executed scheduled transaction: {:?}", transaction);
+
+// One projected payment in a dry-run forecast
+#[derive(Debug, Clone)]
+pub struct ForecastEntry {
+    pub date: NaiveDate,
+    pub schedule_id: Uuid,
+    pub from_account_id: Uuid,
+    pub to_account_id: Uuid,
+    pub amount: f64,
+    pub currency: String,
+    pub will_succeed: bool,
+    pub balance_after: f64,
+}
+
+// Projection of one source sub-account through the forecast period
+#[derive(Debug, Clone)]
+pub struct AccountForecast {
+    pub account_id: Uuid,
+    pub currency: String,
+    pub starting_balance: f64,
+    pub ending_balance: f64,
+    pub failed_payments: usize,
+    // Extra money needed up front for every payment in the period to succeed
+    pub shortfall: f64,
+}
+
+#[derive(Debug, Clone)]
+pub struct Forecast {
+    pub entries: Vec<ForecastEntry>,
+    pub accounts: Vec<AccountForecast>,
+}
+
+// Simulates process_scheduled_transactions up to and including `until` without writing anything.
+// Occurrences are applied in date order across all schedules, so incoming scheduled payments count
+// towards later outgoing ones. Payees without the currency are handled as the processor's policy would.
+pub fn forecast_scheduled_transactions(
+    conn: &mut PgConnection,
+    until: NaiveDate,
+    sub_account_policy: MissingSubAccountPolicy
+) -> Result<Forecast, diesel::result::Error> {
+    forecast_until(conn, |_| until, sub_account_policy)
+}
+
+// The forecast for the next `days` days, counted from today in each paying account's time zone
+pub fn forecast_days_ahead(
+    conn: &mut PgConnection,
+    days: i64,
+    sub_account_policy: MissingSubAccountPolicy
+) -> Result<Forecast, diesel::result::Error> {
+    let now = Utc::now();
+    forecast_until(conn, |tz| local_date(tz, now) + Duration::days(days), sub_account_policy)
+}
+
+fn forecast_until(
+    conn: &mut PgConnection,
+    until_for: impl Fn(Tz) -> NaiveDate,
+    sub_account_policy: MissingSubAccountPolicy
+) -> Result<Forecast, diesel::result::Error> {
+    use crate::schema::scheduled_transactions::dsl::*;
+    use crate::schema::sub_accounts::dsl::sub_accounts;
+    use crate::models::SubAccount;
+
+    let now = Utc::now();
+    let schedules: Vec<ScheduledTransaction> = scheduled_transactions
+        .filter(executed.eq(false))
+        .filter(status.eq(SCHEDULE_STATUS_ACTIVE))
+        .load(conn)?;
+
+    let mut occurrences: Vec<(DateTime<Utc>, NaiveDate, &ScheduledTransaction)> = Vec::new();
+    let mut calendars = HashMap::new();
+    for schedule in &schedules {
+        // `until` and the look-back window are local dates in each paying account's zone
+        let tz = account_time_zone(conn, schedule.from_account_id)?;
+        let until = until_for(tz);
+        let lookback_start = local_date(tz, now) - Duration::days(lookback_days());
+        let calendar = schedule_calendar(&mut calendars, &schedule.currency);
+        let convention = BusinessDayConvention::parse(&schedule.business_day_convention).unwrap_or(BusinessDayConvention::Unadjusted);
+        let rule = schedule_rule(schedule, tz);
+        let remaining = schedule.occurrence_count.map(|count| (count - schedule.occurrences_executed).max(0) as usize);
+        // Forecast the dates payments are actually made on, after business-day adjustment
+        let dates: Vec<NaiveDate> = due_occurrences(schedule, rule.as_ref(), tz, until + Duration::days(MAX_BUSINESS_DAY_ROLL))
+            .into_iter()
+            .map(|d| calendar.adjust(d, convention))
+            .filter(|d| *d >= lookback_start && *d <= until)
+            .take(remaining.unwrap_or(usize::MAX))
+            .collect();
+        occurrences.extend(dates.into_iter().map(|d| (local_to_utc(tz, d, schedule.execution_time), d, schedule)));
+    }
+    occurrences.sort_by_key(|(due_at, _, _)| *due_at);
+
+    // Balances keyed by (account, currency), with and without failed payments applied
+    let mut balances: HashMap<(Uuid, String), f64> = HashMap::new();
+    for sub in sub_accounts.load::<SubAccount>(conn)? {
+        if let Some(owner) = sub.account_id {
+            balances.insert((owner, sub.currency), sub.balance);
+        }
+    }
+    let starting = balances.clone();
+    let mut unconstrained = balances.clone();
+    let mut lowest: HashMap<(Uuid, String), f64> = HashMap::new();
+    let mut failures: HashMap<(Uuid, String), usize> = HashMap::new();
+    // Where each payee is credited in each currency, resolved as transfer_money would
+    let mut destinations: HashMap<(Uuid, String), Option<Destination>> = HashMap::new();
+
+    let mut entries = Vec::new();
+    for (_, date, schedule) in occurrences {
+        let from_key = (schedule.from_account_id, schedule.currency.clone());
+        let to_key = (schedule.to_account_id, schedule.currency.clone());
+        if !destinations.contains_key(&to_key) {
+            let destination = find_destination(conn, schedule.to_account_id, &schedule.currency, sub_account_policy)?;
+            destinations.insert(to_key.clone(), destination);
+        }
+        // Money converted into the payee's primary currency is not counted, as a forecast fetches no rates
+        let credited = match &destinations[&to_key] {
+            Some(Destination::Existing(_)) | Some(Destination::Opened) => Some(true),
+            Some(Destination::Primary(_)) => Some(false),
+            None => None,
+        };
+
+        let available = balances.get(&from_key).copied();
+        let will_succeed = available.is_some_and(|b| b >= schedule.amount) && credited.is_some();
+        if will_succeed {
+            *balances.get_mut(&from_key).unwrap() -= schedule.amount;
+            if credited == Some(true) {
+                *balances.entry(to_key.clone()).or_insert(0.0) += schedule.amount;
             }
-            Err(e) => {
-                send_telegram_alert(&format!("Failed to execute scheduled transaction {:?}: {:?}", transaction, e)).await;
-                println!("Failed to execute scheduled transaction {:?}: {:?}", transaction, e);
+        } else {
+            *failures.entry(from_key.clone()).or_insert(0) += 1;
+        }
+
+        // Track how low the balance would go if every payment went through regardless
+        let projected = unconstrained.entry(from_key.clone()).or_insert(0.0);
+        *projected -= schedule.amount;
+        let projected = *projected;
+        if credited == Some(true) {
+            *unconstrained.entry(to_key.clone()).or_insert(0.0) += schedule.amount;
+        }
+        let low = lowest.entry(from_key.clone()).or_insert(projected);
+        *low = low.min(projected);
+
+        entries.push(ForecastEntry {
+            date,
+            schedule_id: schedule.id,
+            from_account_id: schedule.from_account_id,
+            to_account_id: schedule.to_account_id,
+            amount: schedule.amount,
+            currency: schedule.currency.clone(),
+            will_succeed,
+            balance_after: balances.get(&from_key).copied().unwrap_or(0.0),
+        });
+    }
+
+    let mut accounts: Vec<AccountForecast> = lowest
+        .into_iter()
+        .map(|(key, low)| AccountForecast {
+            starting_balance: starting.get(&key).copied().unwrap_or(0.0),
+            ending_balance: balances.get(&key).copied().unwrap_or(0.0),
+            failed_payments: failures.get(&key).copied().unwrap_or(0),
+            shortfall: (-low).max(0.0),
+            account_id: key.0,
+            currency: key.1,
+        })
+        .collect();
+    accounts.sort_by(|a, b| (a.account_id, &a.currency).cmp(&(b.account_id, &b.currency)));
+
+    Ok(Forecast { entries, accounts })
+}
+
+// Warns owners whose sub-accounts cannot cover the scheduled payments due in the next few days. Sub-accounts
+// with an automatic top-up are topped up from their source first; owners are warned at most once a day.
+pub fn check_upcoming_balances(conn: &mut PgConnection, sub_account_policy: MissingSubAccountPolicy) -> Result<(), diesel::result::Error> {
+    use crate::schema::sub_accounts::dsl::*;
+    use crate::models::SubAccount;
+    let now = Utc::now();
+    // The window runs to the same day for everyone in a time zone, whatever the server's clock says
+    let forecast = forecast_days_ahead(conn, balance_warning_days(), sub_account_policy)?;
+
+    for projection in forecast.accounts.iter().filter(|a| a.shortfall > 0.0) {
+        let sub = sub_accounts
+            .filter(account_id.eq(projection.account_id))
+            .filter(currency.eq(&projection.currency))
+            .first::<SubAccount>(conn)
+            .optional()?;
+        let sub = match sub {
+            Some(sub) => sub,
+            None => continue,
+        };
+        let due_by = local_date(account_time_zone(conn, projection.account_id)?, now) + Duration::days(balance_warning_days());
+
+        if let Some(source_id) = sub.auto_top_up_from {
+            // Price the top-up before opening the transaction, so no locks are held while the rate is fetched
+            let result = quote_top_up(conn, &sub, source_id, projection.shortfall).and_then(|quote| {
+                conn.transaction(|conn| {
+                    let top_up = top_up_sub_account(conn, &sub, &quote)?;
+                    let new_balance = get_balance(conn, projection.account_id, &projection.currency)?;
+                    enqueue_event(conn, EVENT_TOP_UP_SUCCEEDED, Some(projection.account_id), json!({
+                        "transaction_id": top_up.transaction_id.to_string(),
+                        "currency": projection.currency,
+                        "amount": top_up.amount,
+                        "source_currency": top_up.transfer_currency,
+                        "due_by": due_by.to_string(),
+                        "new_balance": new_balance,
+                    }))?;
+                    Ok::<_, diesel::result::Error>(())
+                })
+            });
+            match result {
+                Ok(_) => continue,
+                Err(e) => {
+                    // Each run retries the top-up, but the owner hears once about the payment it was meant to cover
+                    let first_failing = forecast.entries.iter()
+                        .find(|entry| entry.from_account_id == projection.account_id && entry.currency == projection.currency && !entry.will_succeed);
+                    let schedule = first_failing.map(|entry| entry.schedule_id);
+                    let due_date = first_failing.map_or(due_by, |entry| entry.date);
+                    if !top_up_failure_notified(conn, projection.account_id, &projection.currency, schedule, due_date)? {
+                        enqueue_event(conn, EVENT_TOP_UP_FAILED, Some(projection.account_id), json!({
+                            "currency": projection.currency,
+                            "shortfall": projection.shortfall,
+                            "schedule_id": schedule.map(|s| s.to_string()),
+                            "due_date": due_date.to_string(),
+                            "error": format!("{:?}", e),
+                        }))?;
+                    }
+                }
             }
         }
+
+        if sub.low_balance_warned_at.is_some_and(|at| now - at < Duration::hours(24)) {
+            continue;
+        }
+        conn.transaction(|conn| {
+            enqueue_event(conn, EVENT_BALANCE_LOW, Some(projection.account_id), json!({
+                "currency": projection.currency,
+                "shortfall": projection.shortfall,
+                "failed_payments": projection.failed_payments,
+                "due_by": due_by.to_string(),
+            }))?;
+            diesel::update(sub_accounts.find(sub.id))
+                .set(low_balance_warned_at.eq(now))
+                .execute(conn)?;
+            Ok::<_, diesel::result::Error>(())
+        })?;
     }
 
     Ok(())
 }
 
+// Whether the owner has already been told a top-up failed to cover the schedule's payment on `due_date`
+fn top_up_failure_notified(conn: &mut PgConnection, account_to_check: Uuid, currency_to_check: &str, schedule: Option<Uuid>, due_date: NaiveDate) -> Result<bool, diesel::result::Error> {
+    use crate::schema::outbox::dsl::*;
+    let mut query = outbox
+        .filter(event_type.eq(EVENT_TOP_UP_FAILED))
+        .filter(account_id.eq(account_to_check))
+        .filter(payload.retrieve_as_text("currency").eq(currency_to_check.to_string()))
+        .filter(payload.retrieve_as_text("due_date").eq(due_date.to_string()))
+        .into_boxed();
+    query = match schedule {
+        Some(schedule) => query.filter(payload.retrieve_as_text("schedule_id").eq(schedule.to_string())),
+        None => query.filter(payload.retrieve_as_text("schedule_id").is_null()),
+    };
+    Ok(query.select(id).first::<Uuid>(conn).optional()?.is_some())
+}
+
+// Postgres advisory lock key held by whichever recurring_payments instance is allowed to process payments
+pub const SCHEDULER_LOCK_KEY: i64 = 0x5255_5354_455A_4531;
+
+#[derive(QueryableByName)]
+struct AdvisoryLockResult {
+    #[diesel(sql_type = diesel::sql_types::Bool)]
+    acquired: bool,
+}
+
+// Tries to become the scheduler leader without blocking; the lock lasts as long as this connection
+pub fn try_acquire_scheduler_lock(conn: &mut PgConnection) -> Result<bool, diesel::result::Error> {
+    let result = diesel::sql_query("SELECT pg_try_advisory_lock($1) AS acquired")
+        .bind::<diesel::sql_types::BigInt, _>(SCHEDULER_LOCK_KEY)
+        .get_result::<AdvisoryLockResult>(conn)?;
+    Ok(result.acquired)
+}
+
+// Whether this connection still holds the scheduler lock; a bigint advisory key is split across classid and objid
+pub fn holds_scheduler_lock(conn: &mut PgConnection) -> Result<bool, diesel::result::Error> {
+    let result = diesel::sql_query(
+        "SELECT EXISTS (SELECT 1 FROM pg_locks WHERE locktype = 'advisory' AND pid = pg_backend_pid() AND granted \
+         AND classid = ($1 >> 32)::oid AND objid = ($1 & 4294967295)::oid AND objsubid = 1) AS acquired"
+    )
+        .bind::<diesel::sql_types::BigInt, _>(SCHEDULER_LOCK_KEY)
+        .get_result::<AdvisoryLockResult>(conn)?;
+    Ok(result.acquired)
+}
 
+pub fn release_scheduler_lock(conn: &mut PgConnection) -> Result<(), diesel::result::Error> {
+    diesel::sql_query("SELECT pg_advisory_unlock($1)")
+        .bind::<diesel::sql_types::BigInt, _>(SCHEDULER_LOCK_KEY)
+        .execute(conn)?;
+    Ok(())
+}
 
 pub fn log_to_file(message: &str) {
     // Get log path from environment variable or use default
@@ -97,4 +1040,164 @@ pub fn log_to_file(message: &str) {
     } else {
         eprintln!("Failed to open log file at: {}", log_path);
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap()
+    }
+
+    fn occurrences(rule: &str, start: NaiveDate, limit: usize) -> Vec<NaiveDate> {
+        let recurrence = Recurrence::parse(rule).unwrap().anchored_to(start);
+        upcoming_occurrences(&recurrence, start, None, None, limit)
+    }
+
+    #[test]
+    fn parses_presets() {
+        let biweekly = Recurrence::parse("Biweekly").unwrap();
+        assert_eq!((biweekly.frequency, biweekly.interval), (Frequency::Weekly, 2));
+        let quarterly = Recurrence::parse("quarterly").unwrap();
+        assert_eq!((quarterly.frequency, quarterly.interval), (Frequency::Monthly, 3));
+        assert_eq!(Recurrence::parse("yearly").unwrap().frequency, Frequency::Yearly);
+    }
+
+    #[test]
+    fn parses_rrules() {
+        let rule = Recurrence::parse("RRULE:FREQ=WEEKLY;INTERVAL=2;BYDAY=FR,MO,FR").unwrap();
+        assert_eq!(rule.frequency, Frequency::Weekly);
+        assert_eq!(rule.interval, 2);
+        assert_eq!(rule.by_day, vec![Weekday::Mon, Weekday::Fri]);
+
+        let rule = Recurrence::parse("freq=monthly;bymonthday=-1").unwrap();
+        assert_eq!(rule.by_month_day, vec![-1]);
+
+        let rule = Recurrence::parse("FREQ=YEARLY;BYMONTH=12,6;BYMONTHDAY=15").unwrap();
+        assert_eq!(rule.by_month, vec![6, 12]);
+    }
+
+    #[test]
+    fn rejects_invalid_rrules() {
+        for rule in [
+            "",
+            "INTERVAL=2",
+            "FREQ=HOURLY",
+            "FREQ=DAILY;INTERVAL=0",
+            "FREQ=WEEKLY;BYDAY=XX",
+            "FREQ=MONTHLY;BYDAY=MO",
+            "FREQ=WEEKLY;BYMONTHDAY=1",
+            "FREQ=MONTHLY;BYMONTHDAY=32",
+            "FREQ=MONTHLY;BYMONTH=2",
+            "FREQ=YEARLY;BYMONTH=13",
+            "FREQ=DAILY;COUNT=3",
+            "FREQ",
+        ] {
+            assert!(Recurrence::parse(rule).is_err(), "{} should not parse", rule);
+        }
+    }
+
+    #[test]
+    fn round_trips_through_to_rrule() {
+        for rule in ["FREQ=DAILY", "FREQ=WEEKLY;INTERVAL=2;BYDAY=MO,FR", "FREQ=MONTHLY;BYMONTHDAY=-1", "FREQ=YEARLY;BYMONTH=2;BYMONTHDAY=29"] {
+            assert_eq!(Recurrence::parse(rule).unwrap().to_rrule(), rule);
+        }
+    }
+
+    #[test]
+    fn anchors_monthly_and_yearly_rules_to_the_start() {
+        assert_eq!(Recurrence::parse("monthly").unwrap().anchored_to(date(2024, 1, 31)).to_rrule(), "FREQ=MONTHLY;BYMONTHDAY=31");
+        assert_eq!(Recurrence::parse("yearly").unwrap().anchored_to(date(2024, 2, 29)).to_rrule(), "FREQ=YEARLY;BYMONTH=2;BYMONTHDAY=29");
+        assert_eq!(Recurrence::parse("weekly").unwrap().anchored_to(date(2024, 2, 29)).to_rrule(), "FREQ=WEEKLY");
+    }
+
+    #[test]
+    fn reanchoring_moves_single_day_rules_only() {
+        let monthly = Recurrence::parse("monthly").unwrap().anchored_to(date(2024, 1, 31));
+        assert_eq!(monthly.reanchored_to(date(2024, 3, 15)).to_rrule(), "FREQ=MONTHLY;BYMONTHDAY=15");
+        let yearly = Recurrence::parse("yearly").unwrap().anchored_to(date(2024, 2, 29));
+        assert_eq!(yearly.reanchored_to(date(2024, 7, 4)).to_rrule(), "FREQ=YEARLY;BYMONTH=7;BYMONTHDAY=4");
+        let twice_monthly = Recurrence::parse("FREQ=MONTHLY;BYMONTHDAY=1,15").unwrap();
+        assert_eq!(twice_monthly.reanchored_to(date(2024, 3, 20)).to_rrule(), "FREQ=MONTHLY;BYMONTHDAY=1,15");
+    }
+
+    #[test]
+    fn daily_and_weekly_intervals() {
+        assert_eq!(occurrences("FREQ=DAILY;INTERVAL=3", date(2024, 12, 30), 3), vec![date(2024, 12, 30), date(2025, 1, 2), date(2025, 1, 5)]);
+        assert_eq!(occurrences("biweekly", date(2024, 1, 3), 3), vec![date(2024, 1, 3), date(2024, 1, 17), date(2024, 1, 31)]);
+    }
+
+    #[test]
+    fn weekly_by_day_within_and_across_weeks() {
+        // 2024-01-01 is a Monday
+        assert_eq!(
+            occurrences("FREQ=WEEKLY;INTERVAL=2;BYDAY=MO,TH", date(2024, 1, 1), 4),
+            vec![date(2024, 1, 1), date(2024, 1, 4), date(2024, 1, 15), date(2024, 1, 18)]
+        );
+    }
+
+    #[test]
+    fn weekly_by_day_skips_an_unlisted_start_date() {
+        // Started on Wednesday 2024-01-03 with only Mondays and Fridays listed
+        assert_eq!(
+            occurrences("FREQ=WEEKLY;BYDAY=MO,FR", date(2024, 1, 3), 3),
+            vec![date(2024, 1, 5), date(2024, 1, 8), date(2024, 1, 12)]
+        );
+        let rule = Recurrence::parse("FREQ=WEEKLY;BYDAY=MO").unwrap();
+        assert!(!rule.includes(date(2024, 1, 3)));
+        assert_eq!(rule.first_on_or_after(date(2024, 1, 3)), date(2024, 1, 8));
+        assert_eq!(rule.first_on_or_after(date(2024, 1, 8)), date(2024, 1, 8));
+    }
+
+    #[test]
+    fn monthly_on_the_31st_clamps_without_drifting() {
+        assert_eq!(
+            occurrences("monthly", date(2024, 1, 31), 4),
+            vec![date(2024, 1, 31), date(2024, 2, 29), date(2024, 3, 31), date(2024, 4, 30)]
+        );
+    }
+
+    #[test]
+    fn monthly_last_day_and_quarterly() {
+        assert_eq!(
+            occurrences("FREQ=MONTHLY;BYMONTHDAY=-1", date(2023, 1, 31), 3),
+            vec![date(2023, 1, 31), date(2023, 2, 28), date(2023, 3, 31)]
+        );
+        assert_eq!(
+            occurrences("quarterly", date(2024, 11, 15), 3),
+            vec![date(2024, 11, 15), date(2025, 2, 15), date(2025, 5, 15)]
+        );
+    }
+
+    #[test]
+    fn monthly_by_month_day_skips_an_unlisted_start_date() {
+        assert_eq!(
+            occurrences("FREQ=MONTHLY;BYMONTHDAY=1,15", date(2024, 1, 20), 3),
+            vec![date(2024, 2, 1), date(2024, 2, 15), date(2024, 3, 1)]
+        );
+    }
+
+    #[test]
+    fn yearly_on_29_february_returns_to_the_29th_in_leap_years() {
+        assert_eq!(
+            occurrences("yearly", date(2024, 2, 29), 5),
+            vec![date(2024, 2, 29), date(2025, 2, 28), date(2026, 2, 28), date(2027, 2, 28), date(2028, 2, 29)]
+        );
+    }
+
+    #[test]
+    fn yearly_by_month() {
+        assert_eq!(
+            occurrences("FREQ=YEARLY;BYMONTH=3,9;BYMONTHDAY=-1", date(2024, 3, 31), 3),
+            vec![date(2024, 3, 31), date(2024, 9, 30), date(2025, 3, 31)]
+        );
+    }
+
+    #[test]
+    fn stops_at_end_date_and_count() {
+        let recurrence = Recurrence::parse("weekly").unwrap();
+        assert_eq!(upcoming_occurrences(&recurrence, date(2024, 1, 1), Some(date(2024, 1, 15)), None, 10).len(), 3);
+        assert_eq!(upcoming_occurrences(&recurrence, date(2024, 1, 1), None, Some(2), 10).len(), 2);
+        assert_eq!(upcoming_occurrences(&recurrence, date(2024, 1, 1), None, None, 4).len(), 4);
+    }
+}
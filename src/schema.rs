@@ -4,8 +4,101 @@ diesel::table! {
     accounts (id) {
         id -> Uuid,
         account_holder_name -> Varchar,
-        created_at -> Timestamp,
+        created_at -> Timestamptz,
         status -> Varchar,
+        time_zone -> Varchar,
+        language -> Varchar,
+        account_number -> Varchar,
+    }
+}
+
+diesel::table! {
+    approval_thresholds (currency) {
+        currency -> Varchar,
+        amount -> Float8,
+    }
+}
+
+diesel::table! {
+    notification_deliveries (id) {
+        id -> Uuid,
+        channel_id -> Uuid,
+        outbox_id -> Uuid,
+        created_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    notification_channels (id) {
+        id -> Uuid,
+        account_id -> Uuid,
+        channel -> Varchar,
+        address -> Varchar,
+        enabled -> Bool,
+        created_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    operators (username) {
+        username -> Varchar,
+        passwd -> Varchar,
+        created_at -> Timestamptz,
+        account_id -> Nullable<Uuid>,
+    }
+}
+
+diesel::table! {
+    outbox (id) {
+        id -> Uuid,
+        event_type -> Varchar,
+        account_id -> Nullable<Uuid>,
+        subject -> Varchar,
+        message -> Varchar,
+        payload -> Jsonb,
+        status -> Varchar,
+        attempt_count -> Int4,
+        next_attempt_at -> Timestamptz,
+        last_error -> Nullable<Varchar>,
+        created_at -> Timestamptz,
+        delivered_at -> Nullable<Timestamptz>,
+        lease_expires_at -> Nullable<Timestamptz>,
+    }
+}
+
+diesel::table! {
+    payees (id) {
+        id -> Uuid,
+        account_id -> Uuid,
+        nickname -> Varchar,
+        payee_account_id -> Uuid,
+        default_currency -> Nullable<Varchar>,
+        created_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    payment_requests (id) {
+        id -> Uuid,
+        requester_account_id -> Uuid,
+        payer_account_id -> Uuid,
+        amount -> Float8,
+        currency -> Varchar,
+        memo -> Nullable<Varchar>,
+        status -> Varchar,
+        expires_at -> Timestamptz,
+        transaction_id -> Nullable<Uuid>,
+        created_at -> Timestamptz,
+        decided_at -> Nullable<Timestamptz>,
+    }
+}
+
+diesel::table! {
+    pending_transaction_approvals (id) {
+        id -> Uuid,
+        pending_transaction_id -> Uuid,
+        approved_by -> Varchar,
+        approved_at -> Timestamptz,
     }
 }
 
@@ -15,7 +108,14 @@ diesel::table! {
         account_id_to_add -> Nullable<Uuid>,
         amount -> Float8,
         transfer_currency -> Varchar,
-        transaction_date -> Timestamp,
+        transaction_date -> Timestamptz,
+        status -> Varchar,
+        reviewed_by -> Nullable<Varchar>,
+        decided_at -> Nullable<Timestamptz>,
+        decision_reason -> Nullable<Varchar>,
+        initiated_by -> Nullable<Varchar>,
+        memo -> Nullable<Varchar>,
+        reference -> Nullable<Varchar>,
     }
 }
 
@@ -28,7 +128,21 @@ diesel::table! {
         account_holder_to -> Varchar,
         amount -> Float8,
         currency -> Varchar,
-        created_at -> Timestamp,
+        created_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    scheduled_runs (id) {
+        id -> Uuid,
+        scheduled_transaction_id -> Uuid,
+        occurrence_date -> Timestamptz,
+        status -> Varchar,
+        attempt_count -> Int4,
+        last_error -> Nullable<Varchar>,
+        transaction_id -> Nullable<Uuid>,
+        created_at -> Timestamptz,
+        updated_at -> Timestamptz,
     }
 }
 
@@ -39,8 +153,20 @@ diesel::table! {
         to_account_id -> Uuid,
         amount -> Float8,
         currency -> Varchar,
-        scheduled_date -> Timestamp,
+        scheduled_date -> Timestamptz,
         executed -> Bool,
+        recurrence -> Nullable<Varchar>,
+        end_date -> Nullable<Timestamptz>,
+        occurrence_count -> Nullable<Int4>,
+        occurrences_executed -> Int4,
+        kind -> Varchar,
+        catch_up_policy -> Varchar,
+        status -> Varchar,
+        consecutive_failures -> Int4,
+        execution_time -> Time,
+        business_day_convention -> Varchar,
+        memo -> Nullable<Varchar>,
+        reference -> Nullable<Varchar>,
     }
 }
 
@@ -50,7 +176,69 @@ diesel::table! {
         account_id -> Nullable<Uuid>,
         currency -> Varchar,
         balance -> Float8,
-        created_at -> Timestamp,
+        created_at -> Timestamptz,
+        auto_top_up_from -> Nullable<Uuid>,
+        low_balance_warned_at -> Nullable<Timestamptz>,
+        account_number -> Varchar,
+    }
+}
+
+diesel::table! {
+    telegram_approval_messages (pending_transaction_id) {
+        pending_transaction_id -> Uuid,
+        chat_id -> Int8,
+        message_id -> Int4,
+        sent_at -> Timestamptz,
+        closed_at -> Nullable<Timestamptz>,
+    }
+}
+
+diesel::table! {
+    telegram_link_codes (code) {
+        code -> Varchar,
+        account_id -> Uuid,
+        expires_at -> Timestamptz,
+        created_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    telegram_links (account_id) {
+        account_id -> Uuid,
+        telegram_user_id -> Int8,
+        chat_id -> Int8,
+        linked_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    telegram_operators (telegram_user_id) {
+        telegram_user_id -> Int8,
+        operator -> Varchar,
+        created_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    telegram_transfers (id) {
+        id -> Uuid,
+        from_account_id -> Uuid,
+        to_account_id -> Uuid,
+        amount -> Float8,
+        currency -> Varchar,
+        status -> Varchar,
+        transaction_id -> Nullable<Uuid>,
+        created_at -> Timestamptz,
+        decided_at -> Nullable<Timestamptz>,
+    }
+}
+
+diesel::table! {
+    transaction_categories (transaction_id, account_id) {
+        transaction_id -> Uuid,
+        account_id -> Uuid,
+        category -> Varchar,
+        categorized_at -> Timestamptz,
     }
 }
 
@@ -61,7 +249,17 @@ diesel::table! {
         sub_account_id_to -> Nullable<Uuid>,
         amount -> Float8,
         transfer_currency -> Varchar,
-        transaction_date -> Timestamp,
+        transaction_date -> Timestamptz,
+        exchange_rate -> Nullable<Float8>,
+        reverses -> Nullable<Uuid>,
+        reversed_by -> Nullable<Uuid>,
+        received_amount -> Nullable<Float8>,
+        received_currency -> Nullable<Varchar>,
+        fx_fee -> Nullable<Float8>,
+        fx_fee_bearer -> Nullable<Varchar>,
+        memo -> Nullable<Varchar>,
+        reference -> Nullable<Varchar>,
+        fx_fee_sub_account_id -> Nullable<Uuid>,
     }
 }
 
@@ -74,16 +272,78 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    webhook_deliveries (id) {
+        id -> Uuid,
+        endpoint_id -> Uuid,
+        outbox_id -> Uuid,
+        replay -> Bool,
+        succeeded -> Bool,
+        status_code -> Nullable<Int4>,
+        error -> Nullable<Varchar>,
+        duration_ms -> Int4,
+        created_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    webhook_endpoints (id) {
+        id -> Uuid,
+        account_id -> Uuid,
+        url -> Varchar,
+        secret -> Varchar,
+        event_types -> Array<Text>,
+        enabled -> Bool,
+        created_at -> Timestamptz,
+    }
+}
+
+diesel::joinable!(notification_channels -> accounts (account_id));
+diesel::joinable!(notification_deliveries -> notification_channels (channel_id));
+diesel::joinable!(notification_deliveries -> outbox (outbox_id));
+diesel::joinable!(operators -> accounts (account_id));
+diesel::joinable!(outbox -> accounts (account_id));
+diesel::joinable!(payment_requests -> transactions (transaction_id));
+diesel::joinable!(pending_transaction_approvals -> pending_transactions (pending_transaction_id));
 diesel::joinable!(pending_transactions -> accounts (account_id_to_add));
+diesel::joinable!(scheduled_runs -> scheduled_transactions (scheduled_transaction_id));
+diesel::joinable!(scheduled_runs -> transactions (transaction_id));
 diesel::joinable!(sub_accounts -> accounts (account_id));
+diesel::joinable!(telegram_approval_messages -> pending_transactions (pending_transaction_id));
+diesel::joinable!(telegram_link_codes -> accounts (account_id));
+diesel::joinable!(telegram_links -> accounts (account_id));
+diesel::joinable!(telegram_operators -> operators (operator));
+diesel::joinable!(telegram_transfers -> transactions (transaction_id));
+diesel::joinable!(transaction_categories -> accounts (account_id));
+diesel::joinable!(transaction_categories -> transactions (transaction_id));
 diesel::joinable!(username_password -> accounts (account_id));
+diesel::joinable!(webhook_deliveries -> outbox (outbox_id));
+diesel::joinable!(webhook_deliveries -> webhook_endpoints (endpoint_id));
+diesel::joinable!(webhook_endpoints -> accounts (account_id));
 
 diesel::allow_tables_to_appear_in_same_query!(
     accounts,
+    approval_thresholds,
+    notification_channels,
+    notification_deliveries,
+    operators,
+    outbox,
+    payees,
+    payment_requests,
+    pending_transaction_approvals,
     pending_transactions,
     records,
+    scheduled_runs,
     scheduled_transactions,
     sub_accounts,
+    telegram_approval_messages,
+    telegram_link_codes,
+    telegram_links,
+    telegram_operators,
+    telegram_transfers,
+    transaction_categories,
     transactions,
     username_password,
+    webhook_deliveries,
+    webhook_endpoints,
 );
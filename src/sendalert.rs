@@ -1,5 +1,6 @@
 use teloxide::prelude::*;
 use crate::recurringpayments::log_to_file;
+use crate::telegram::telegram_bot;
 
 pub async fn send_telegram_alert(message: &str) {
     log_to_file("Starting telegram alert function");
@@ -11,7 +12,7 @@ pub async fn send_telegram_alert(message: &str) {
         .parse::<i64>()
         .expect("TELEGRAM_CHAT_ID must be a valid integer");
     
-    let bot = Bot::new(bot_token);
+    let bot = telegram_bot(&bot_token);
     
     match bot.send_message(ChatId(chat_id), message).await {
         Ok(_) => log_to_file("Telegram alert sent successfully"),
@@ -0,0 +1,250 @@
+use chrono::{Duration, Utc};
+use diesel::prelude::*;
+use diesel::pg::PgConnection;
+use rand::Rng;
+use std::env;
+use teloxide::Bot;
+use uuid::Uuid;
+use crate::database::get_account_holder_name;
+use crate::models::{Transaction, TelegramLink, NewTelegramLink, TelegramLinkCode, NewTelegramLinkCode, TelegramTransfer, NewTelegramTransfer};
+use crate::models::{TELEGRAM_TRANSFER_AWAITING, TELEGRAM_TRANSFER_CONFIRMED, TELEGRAM_TRANSFER_DECLINED, PENDING_STATUS_PENDING};
+use crate::models::{PendingTransaction, TelegramOperator, NewTelegramOperator, TelegramApprovalMessage, NewTelegramApprovalMessage};
+use crate::moneytransfer::{transfer_money, MissingSubAccountPolicy};
+use crate::transactiondetails::PaymentDetails;
+use crate::outbox::enqueue_payment_events;
+
+// How long a link code from the CLI can be used
+pub const LINK_CODE_TTL_MINUTES: i64 = 10;
+// How long a transfer started in the bot waits for the user to confirm it
+pub const TRANSFER_CONFIRM_MINUTES: i64 = 10;
+
+// Codes avoid characters that are easy to mix up when typed from another screen
+const LINK_CODE_ALPHABET: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+const LINK_CODE_LENGTH: usize = 8;
+
+// The chat new deposit requests are posted to for approval, from TELEGRAM_OPERATOR_CHAT_ID
+pub fn operator_chat_id() -> Option<i64> {
+    env::var("TELEGRAM_OPERATOR_CHAT_ID").ok().and_then(|chat| chat.trim().parse::<i64>().ok())
+}
+
+// A bot for TELEGRAM_BOT_TOKEN talking to TELEGRAM_API_URL (default https://api.telegram.org), so tests can
+// point it at a local mock server
+pub fn telegram_bot(token: &str) -> Bot {
+    let bot = Bot::new(token);
+    match env::var("TELEGRAM_API_URL").ok().and_then(|url| reqwest::Url::parse(&url).ok()) {
+        Some(url) => bot.set_api_url(url),
+        None => bot,
+    }
+}
+
+// Issues a one-time code the account holder sends to the bot with /link. Earlier unused codes stop working.
+pub fn create_telegram_link_code(conn: &mut PgConnection, account_id_to_link: Uuid) -> Result<String, diesel::result::Error> {
+    use crate::schema::telegram_link_codes::dsl::*;
+    let mut rng = rand::thread_rng();
+    let new_code: String = (0..LINK_CODE_LENGTH)
+        .map(|_| LINK_CODE_ALPHABET[rng.gen_range(0..LINK_CODE_ALPHABET.len())] as char)
+        .collect();
+
+    conn.transaction(|conn| {
+        diesel::delete(telegram_link_codes.filter(account_id.eq(account_id_to_link))).execute(conn)?;
+        diesel::insert_into(telegram_link_codes)
+            .values(&NewTelegramLinkCode {
+                code: &new_code,
+                account_id: account_id_to_link,
+                expires_at: Utc::now() + Duration::minutes(LINK_CODE_TTL_MINUTES),
+            })
+            .execute(conn)?;
+        Ok(new_code)
+    })
+}
+
+// Links the Telegram user to the code's account, replacing any earlier link of either. Unknown and expired
+// codes are NotFound.
+pub fn redeem_telegram_link_code(conn: &mut PgConnection, code_to_redeem: &str, user_id: i64, chat: i64) -> Result<Uuid, diesel::result::Error> {
+    conn.transaction(|conn| {
+        let link_code = {
+            use crate::schema::telegram_link_codes::dsl::*;
+            let found = telegram_link_codes
+                .find(code_to_redeem.trim().to_uppercase())
+                .filter(expires_at.gt(Utc::now()))
+                .first::<TelegramLinkCode>(conn)?;
+            diesel::delete(telegram_link_codes.find(&found.code)).execute(conn)?;
+            found
+        };
+
+        use crate::schema::telegram_links::dsl::*;
+        diesel::delete(telegram_links.filter(telegram_user_id.eq(user_id).or(account_id.eq(link_code.account_id))))
+            .execute(conn)?;
+        diesel::insert_into(telegram_links)
+            .values(&NewTelegramLink { account_id: link_code.account_id, telegram_user_id: user_id, chat_id: chat })
+            .execute(conn)?;
+        Ok(link_code.account_id)
+    })
+}
+
+pub fn get_linked_account(conn: &mut PgConnection, user_id: i64) -> Result<Option<Uuid>, diesel::result::Error> {
+    use crate::schema::telegram_links::dsl::*;
+    telegram_links
+        .filter(telegram_user_id.eq(user_id))
+        .select(account_id)
+        .first(conn)
+        .optional()
+}
+
+pub fn get_telegram_link(conn: &mut PgConnection, account_id_to_view: Uuid) -> Result<Option<TelegramLink>, diesel::result::Error> {
+    use crate::schema::telegram_links::dsl::*;
+    telegram_links
+        .find(account_id_to_view)
+        .first::<TelegramLink>(conn)
+        .optional()
+}
+
+pub fn unlink_telegram(conn: &mut PgConnection, account_id_to_unlink: Uuid) -> Result<usize, diesel::result::Error> {
+    use crate::schema::telegram_links::dsl::*;
+    diesel::delete(telegram_links.find(account_id_to_unlink)).execute(conn)
+}
+
+// Records a transfer for the user to confirm; nothing moves until they do
+pub fn create_telegram_transfer(conn: &mut PgConnection, from: Uuid, to: Uuid, transfer_amount: f64, transfer_currency: &str) -> Result<TelegramTransfer, diesel::result::Error> {
+    use crate::schema::telegram_transfers::dsl::*;
+    if from == to || !transfer_amount.is_finite() || transfer_amount <= 0.0 {
+        return Err(diesel::result::Error::RollbackTransaction);
+    }
+    // The recipient must exist
+    get_account_holder_name(conn, to)?;
+
+    diesel::insert_into(telegram_transfers)
+        .values(&NewTelegramTransfer {
+            from_account_id: from,
+            to_account_id: to,
+            amount: transfer_amount,
+            currency: &transfer_currency.to_uppercase(),
+        })
+        .returning(TelegramTransfer::as_returning())
+        .get_result(conn)
+}
+
+// An awaiting transfer of the account that has not expired, locked until the end of the transaction
+fn get_awaiting_transfer(conn: &mut PgConnection, from: Uuid, transfer_id: Uuid) -> Result<TelegramTransfer, diesel::result::Error> {
+    use crate::schema::telegram_transfers::dsl::*;
+    telegram_transfers
+        .find(transfer_id)
+        .filter(from_account_id.eq(from))
+        .filter(status.eq(TELEGRAM_TRANSFER_AWAITING))
+        .filter(created_at.gt(Utc::now() - Duration::minutes(TRANSFER_CONFIRM_MINUTES)))
+        .for_update()
+        .first::<TelegramTransfer>(conn)
+}
+
+// Executes a confirmed transfer. A transfer that was already decided or has expired is NotFound.
+pub fn confirm_telegram_transfer(
+    conn: &mut PgConnection,
+    from: Uuid,
+    transfer_id: Uuid,
+    policy: MissingSubAccountPolicy
+) -> Result<Transaction, diesel::result::Error> {
+    conn.transaction(|conn| {
+        let transfer = get_awaiting_transfer(conn, from, transfer_id)?;
+        let executed = transfer_money(conn, transfer.from_account_id, transfer.to_account_id, transfer.amount, &transfer.currency, &PaymentDetails::none(), policy)?;
+        enqueue_payment_events(conn, &executed, transfer.from_account_id, transfer.to_account_id)?;
+
+        use crate::schema::telegram_transfers::dsl::*;
+        diesel::update(telegram_transfers.find(transfer.id))
+            .set((status.eq(TELEGRAM_TRANSFER_CONFIRMED), transaction_id.eq(executed.transaction_id), decided_at.eq(Utc::now())))
+            .execute(conn)?;
+        Ok(executed)
+    })
+}
+
+pub fn decline_telegram_transfer(conn: &mut PgConnection, from: Uuid, transfer_id: Uuid) -> Result<TelegramTransfer, diesel::result::Error> {
+    conn.transaction(|conn| {
+        let transfer = get_awaiting_transfer(conn, from, transfer_id)?;
+
+        use crate::schema::telegram_transfers::dsl::*;
+        diesel::update(telegram_transfers.find(transfer.id))
+            .set((status.eq(TELEGRAM_TRANSFER_DECLINED), decided_at.eq(Utc::now())))
+            .returning(TelegramTransfer::as_returning())
+            .get_result(conn)
+    })
+}
+
+// The operator a Telegram user approves and rejects deposit requests as, if any
+pub fn get_telegram_operator(conn: &mut PgConnection, user_id: i64) -> Result<Option<String>, diesel::result::Error> {
+    use crate::schema::telegram_operators::dsl::*;
+    telegram_operators
+        .find(user_id)
+        .select(operator)
+        .first(conn)
+        .optional()
+}
+
+pub fn get_telegram_operators(conn: &mut PgConnection) -> Result<Vec<TelegramOperator>, diesel::result::Error> {
+    use crate::schema::telegram_operators::dsl::*;
+    telegram_operators
+        .order(operator.asc())
+        .load::<TelegramOperator>(conn)
+}
+
+// Maps an operator to a Telegram user, replacing any earlier mapping of either. Unknown operators are NotFound.
+pub fn map_telegram_operator(conn: &mut PgConnection, operator_to_map: &str, user_id: i64) -> Result<TelegramOperator, diesel::result::Error> {
+    conn.transaction(|conn| {
+        {
+            use crate::schema::operators::dsl::*;
+            operators.find(operator_to_map).select(username).first::<String>(conn)?;
+        }
+
+        use crate::schema::telegram_operators::dsl::*;
+        diesel::delete(telegram_operators.filter(telegram_user_id.eq(user_id).or(operator.eq(operator_to_map))))
+            .execute(conn)?;
+        diesel::insert_into(telegram_operators)
+            .values(&NewTelegramOperator { telegram_user_id: user_id, operator: operator_to_map })
+            .returning(TelegramOperator::as_returning())
+            .get_result(conn)
+    })
+}
+
+pub fn unmap_telegram_operator(conn: &mut PgConnection, operator_to_unmap: &str) -> Result<usize, diesel::result::Error> {
+    use crate::schema::telegram_operators::dsl::*;
+    diesel::delete(telegram_operators.filter(operator.eq(operator_to_unmap))).execute(conn)
+}
+
+// Pending transactions that have not been posted to the operator chat yet, oldest first
+pub fn get_unannounced_pending_transactions(conn: &mut PgConnection) -> Result<Vec<PendingTransaction>, diesel::result::Error> {
+    use crate::schema::pending_transactions::dsl::*;
+    use crate::schema::telegram_approval_messages;
+    pending_transactions
+        .left_join(telegram_approval_messages::table)
+        .filter(telegram_approval_messages::pending_transaction_id.is_null())
+        .filter(status.eq(PENDING_STATUS_PENDING))
+        .order(transaction_date.asc())
+        .select(PendingTransaction::as_select())
+        .load(conn)
+}
+
+pub fn record_approval_message(conn: &mut PgConnection, pending_transaction_id_to_record: Uuid, chat: i64, message: i32) -> Result<TelegramApprovalMessage, diesel::result::Error> {
+    use crate::schema::telegram_approval_messages::dsl::*;
+    diesel::insert_into(telegram_approval_messages)
+        .values(&NewTelegramApprovalMessage { pending_transaction_id: pending_transaction_id_to_record, chat_id: chat, message_id: message })
+        .returning(TelegramApprovalMessage::as_returning())
+        .get_result(conn)
+}
+
+// Messages still showing buttons for transactions that were decided elsewhere, e.g. in the CLI
+pub fn get_stale_approval_messages(conn: &mut PgConnection) -> Result<Vec<(TelegramApprovalMessage, PendingTransaction)>, diesel::result::Error> {
+    use crate::schema::telegram_approval_messages::dsl::*;
+    use crate::schema::pending_transactions;
+    telegram_approval_messages
+        .inner_join(pending_transactions::table)
+        .filter(closed_at.is_null())
+        .filter(pending_transactions::status.ne(PENDING_STATUS_PENDING))
+        .select((TelegramApprovalMessage::as_select(), PendingTransaction::as_select()))
+        .load(conn)
+}
+
+// Marks the message as showing the final decision
+pub fn close_approval_message(conn: &mut PgConnection, pending_transaction_id_to_close: Uuid) -> Result<usize, diesel::result::Error> {
+    use crate::schema::telegram_approval_messages::dsl::*;
+    diesel::update(telegram_approval_messages.find(pending_transaction_id_to_close))
+        .set(closed_at.eq(Utc::now()))
+        .execute(conn)
+}
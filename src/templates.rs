@@ -0,0 +1,344 @@
+use anyhow::{anyhow, Result as AnyResult};
+use diesel::prelude::*;
+use diesel::pg::PgConnection;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use uuid::Uuid;
+use crate::models::{Account, EVENT_PAYMENT_SENT, EVENT_PAYMENT_RECEIVED, EVENT_DEPOSIT_REQUESTED, EVENT_DEPOSIT_APPROVED, EVENT_DEPOSIT_REJECTED};
+use crate::models::{EVENT_SCHEDULE_EXECUTED, EVENT_SCHEDULE_FAILED, EVENT_SCHEDULE_PAUSED, EVENT_SCHEDULE_SKIPPED, EVENT_BALANCE_LOW, EVENT_TOP_UP_SUCCEEDED, EVENT_TOP_UP_FAILED};
+use crate::models::{EVENT_PAYMENT_REQUEST_RECEIVED, EVENT_PAYMENT_REQUEST_PAID, EVENT_PAYMENT_REQUEST_DECLINED};
+use crate::recurringpayments::log_to_file;
+
+pub const DEFAULT_LANGUAGE: &str = "en";
+
+// Directory of operator templates, from NOTIFICATION_TEMPLATE_DIR (default "templates"). Templates live in one
+// subdirectory per language, as <event>.<channel>.txt for one channel or <event>.txt for all of them.
+pub fn template_dir() -> PathBuf {
+    PathBuf::from(env::var("NOTIFICATION_TEMPLATE_DIR").unwrap_or_else(|_| "templates".to_string()))
+}
+
+// A notification before its {variables} are filled in
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Template {
+    pub subject: String,
+    pub body: String,
+}
+
+impl Template {
+    fn new(subject: &str, body: &str) -> Self {
+        Template { subject: subject.to_string(), body: body.to_string() }
+    }
+
+    // Template files start with a "Subject: ..." line; everything after it is the body
+    pub fn parse(contents: &str) -> AnyResult<Template> {
+        let (first, rest) = contents.split_once('\n').unwrap_or((contents, ""));
+        let subject = first
+            .trim()
+            .strip_prefix("Subject:")
+            .ok_or_else(|| anyhow!("the first line must be \"Subject: ...\""))?;
+        Ok(Template::new(subject.trim(), rest.trim()))
+    }
+
+    pub fn render(&self, context: &HashMap<String, String>) -> (String, String) {
+        (fill(&self.subject, context), fill(&self.body, context))
+    }
+}
+
+// The templates used when no file overrides them
+fn builtin_template(event: &str) -> Option<Template> {
+    let template = match event {
+        EVENT_PAYMENT_SENT => Template::new("Payment sent",
+            "You sent {amount} {currency} to {payee_name}, who received {received_amount} {received_currency}. Your {currency} balance is now {new_balance} {currency}."),
+        EVENT_PAYMENT_RECEIVED => Template::new("Payment received",
+            "You received {received_amount} {received_currency} from {payer_name}. Your {received_currency} balance is now {new_balance} {received_currency}."),
+        EVENT_DEPOSIT_REQUESTED => Template::new("Deposit requested",
+            "Your deposit of {amount} {currency} is waiting for approval."),
+        EVENT_DEPOSIT_APPROVED => Template::new("Deposit approved",
+            "Your deposit of {amount} {currency} was approved. Your {currency} balance is now {new_balance} {currency}."),
+        EVENT_DEPOSIT_REJECTED => Template::new("Deposit rejected",
+            "Your deposit of {amount} {currency} was rejected: {reason}"),
+        EVENT_SCHEDULE_EXECUTED => Template::new("Scheduled payment made",
+            "Your scheduled payment of {amount} {currency} to {payee_name} for {occurrence} was made. Your {currency} balance is now {new_balance} {currency}."),
+        EVENT_SCHEDULE_FAILED => Template::new("Scheduled payment failed",
+            "Your scheduled payment of {amount} {currency} to {payee_name} for {occurrence} failed on attempt {attempt} of {max_attempts}: {error}"),
+        EVENT_SCHEDULE_PAUSED => Template::new("Scheduled payment paused",
+            "Your scheduled payment of {amount} {currency} to {payee_name} was paused after {consecutive_failures} failed payments in a row. Resume it once the problem is fixed."),
+        EVENT_SCHEDULE_SKIPPED => Template::new("Scheduled payments skipped",
+            "Missed payments of {amount} {currency} to {payee_name} were skipped for: {occurrences}"),
+        EVENT_BALANCE_LOW => Template::new("Low balance for scheduled payments",
+            "Your {currency} sub-account is {shortfall} {currency} short of the scheduled payments due by {due_by}. {failed_payments} payment(s) would fail."),
+        EVENT_TOP_UP_SUCCEEDED => Template::new("Sub-account topped up",
+            "Your {currency} sub-account was topped up with {amount} {source_currency} to cover scheduled payments due by {due_by}. Its balance is now {new_balance} {currency}."),
+        EVENT_TOP_UP_FAILED => Template::new("Automatic top-up failed",
+            "The automatic top-up of your {currency} sub-account failed: {error}"),
+        EVENT_PAYMENT_REQUEST_RECEIVED => Template::new("Payment request",
+            "{requester_name} asked you to pay {amount} {currency} ({memo}). Pay or decline the request before {expires_at}."),
+        EVENT_PAYMENT_REQUEST_PAID => Template::new("Payment request paid",
+            "{payer_name} paid your request for {amount} {currency} ({memo})."),
+        EVENT_PAYMENT_REQUEST_DECLINED => Template::new("Payment request declined",
+            "{payer_name} declined your request for {amount} {currency} ({memo})."),
+        _ => return None,
+    };
+    Some(template)
+}
+
+fn read_template_file(path: &PathBuf) -> Option<Template> {
+    let contents = fs::read_to_string(path).ok()?;
+    match Template::parse(&contents) {
+        Ok(template) => Some(template),
+        Err(e) => {
+            log_to_file(&format!("Ignoring notification template {}: {}", path.display(), e));
+            None
+        }
+    }
+}
+
+// Templates already looked up, by directory, event, channel and language. Files are read once per process, so
+// edited templates are used after a restart.
+type TemplateKey = (PathBuf, String, Option<String>, String);
+static TEMPLATE_CACHE: OnceLock<Mutex<HashMap<TemplateKey, Option<Template>>>> = OnceLock::new();
+
+// The most specific template for an event: the channel's file in the language, the language's file for all
+// channels, then the same in English, then the built-in English template
+pub fn find_template(event: &str, channel: Option<&str>, language: &str) -> Option<Template> {
+    find_template_in(&template_dir(), event, channel, language)
+}
+
+fn find_template_in(dir: &Path, event: &str, channel: Option<&str>, language: &str) -> Option<Template> {
+    let key = (dir.to_path_buf(), event.to_string(), channel.map(str::to_string), language.to_string());
+    let cache = TEMPLATE_CACHE.get_or_init(|| Mutex::new(HashMap::new()));
+    if let Some(template) = cache.lock().unwrap_or_else(|e| e.into_inner()).get(&key) {
+        return template.clone();
+    }
+    let template = load_template(dir, event, channel, language);
+    cache.lock().unwrap_or_else(|e| e.into_inner()).insert(key, template.clone());
+    template
+}
+
+fn load_template(dir: &Path, event: &str, channel: Option<&str>, language: &str) -> Option<Template> {
+    for lang in [language, DEFAULT_LANGUAGE] {
+        let mut names = Vec::new();
+        if let Some(channel) = channel {
+            names.push(format!("{}.{}.txt", event, channel));
+        }
+        names.push(format!("{}.txt", event));
+        for name in names {
+            if let Some(template) = read_template_file(&dir.join(lang).join(name)) {
+                return Some(template);
+            }
+        }
+    }
+    builtin_template(event)
+}
+
+// Digits after the decimal point in a currency's minor unit (ISO 4217); most currencies have cents
+pub fn currency_decimals(currency: &str) -> usize {
+    match currency.to_ascii_uppercase().as_str() {
+        "BIF" | "CLP" | "DJF" | "GNF" | "ISK" | "JPY" | "KMF" | "KRW" | "PYG" | "RWF" | "UGX" | "VND" | "VUV" | "XAF" | "XOF" | "XPF" => 0,
+        "BHD" | "IQD" | "JOD" | "KWD" | "LYD" | "OMR" | "TND" => 3,
+        _ => 2,
+    }
+}
+
+// 1234.5 USD -> "1,234.50", 1000 JPY -> "1,000"
+pub fn format_money(value: f64, currency: &str) -> String {
+    let decimals = currency_decimals(currency);
+    let fixed = format!("{:.*}", decimals, value.abs());
+    let (whole, fraction) = fixed.split_once('.').unwrap_or((&fixed, ""));
+    let mut grouped = String::new();
+    for (i, digit) in whole.chars().enumerate() {
+        if i > 0 && (whole.len() - i) % 3 == 0 {
+            grouped.push(',');
+        }
+        grouped.push(digit);
+    }
+    // Rounding can turn a tiny negative amount into zero, which is shown without a sign
+    let sign = if value < 0.0 && fixed.chars().any(|c| c.is_ascii_digit() && c != '0') { "-" } else { "" };
+    if fraction.is_empty() {
+        format!("{}{}", sign, grouped)
+    } else {
+        format!("{}{}.{}", sign, grouped, fraction)
+    }
+}
+
+// The payload field holding the currency of a money field
+fn currency_field(event: &str, field: &str) -> &'static str {
+    match (event, field) {
+        (_, "received_amount") => "received_currency",
+        (EVENT_PAYMENT_RECEIVED, "new_balance") => "received_currency",
+        (EVENT_TOP_UP_SUCCEEDED, "amount") => "source_currency",
+        _ => "currency",
+    }
+}
+
+fn context_value(value: &Value, currency: &str) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(text) => text.clone(),
+        // Fractional numbers in event payloads are money
+        Value::Number(number) if number.is_f64() => format_money(number.as_f64().unwrap_or_default(), currency),
+        Value::Array(items) => items.iter().map(|item| context_value(item, currency)).collect::<Vec<_>>().join(", "),
+        other => other.to_string(),
+    }
+}
+
+// The variables a template can use: the top-level fields of the event payload, with amounts formatted for
+// their currency
+pub fn template_context(event: &str, payload: &Value) -> HashMap<String, String> {
+    match payload {
+        Value::Object(fields) => fields
+            .iter()
+            .map(|(key, value)| {
+                let currency = fields.get(currency_field(event, key)).and_then(Value::as_str).unwrap_or_default();
+                (key.clone(), context_value(value, currency))
+            })
+            .collect(),
+        _ => HashMap::new(),
+    }
+}
+
+// Replaces each {variable} with its value; unknown variables are left as they are
+fn fill(text: &str, context: &HashMap<String, String>) -> String {
+    let mut output = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('{') {
+        output.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        match after.find('}') {
+            Some(end) if context.contains_key(&after[..end]) => {
+                output.push_str(&context[&after[..end]]);
+                rest = &after[end + 1..];
+            }
+            _ => {
+                output.push('{');
+                rest = after;
+            }
+        }
+    }
+    output.push_str(rest);
+    output
+}
+
+// Subject and body of an event for one channel in the language, or None for an event without a template
+pub fn render_event(event: &str, channel: Option<&str>, language: &str, payload: &Value) -> Option<(String, String)> {
+    find_template(event, channel, language).map(|template| template.render(&template_context(event, payload)))
+}
+
+// English, or any language with a directory of templates
+pub fn is_supported_language(language: &str) -> bool {
+    language == DEFAULT_LANGUAGE
+        || (language.len() == 2 && language.chars().all(|c| c.is_ascii_lowercase()) && template_dir().join(language).is_dir())
+}
+
+pub fn account_language(conn: &mut PgConnection, account_id_to_check: Uuid) -> Result<String, diesel::result::Error> {
+    use crate::schema::accounts::dsl::*;
+    accounts
+        .find(account_id_to_check)
+        .select(language)
+        .first(conn)
+}
+
+pub fn set_account_language(conn: &mut PgConnection, account_id_to_update: Uuid, new_language: &str) -> Result<Account, diesel::result::Error> {
+    use crate::schema::accounts::dsl::*;
+    let new_language = new_language.trim().to_lowercase();
+    if !is_supported_language(&new_language) {
+        return Err(diesel::result::Error::RollbackTransaction);
+    }
+    diesel::update(accounts.find(account_id_to_update))
+        .set(language.eq(new_language))
+        .returning(Account::as_returning())
+        .get_result(conn)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn context(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs.iter().map(|(key, value)| (key.to_string(), value.to_string())).collect()
+    }
+
+    // A template directory of its own, so tests do not share cached lookups
+    fn template_dir_with(files: &[(&str, &str)]) -> PathBuf {
+        let dir = env::temp_dir().join(format!("rust_eze_templates_{}", Uuid::new_v4()));
+        for (name, contents) in files {
+            let path = dir.join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn fill_replaces_known_variables_only() {
+        let filled = fill("Paid {amount} {currency} to {payee_name} {unknown} {", &context(&[("amount", "10.00"), ("currency", "USD"), ("payee_name", "Ana")]));
+        assert_eq!(filled, "Paid 10.00 USD to Ana {unknown} {");
+        assert_eq!(fill("{{amount}}", &context(&[("amount", "5")])), "{5}");
+    }
+
+    #[test]
+    fn format_money_uses_the_currency_minor_unit() {
+        assert_eq!(format_money(1234.5, "USD"), "1,234.50");
+        assert_eq!(format_money(1000.0, "JPY"), "1,000");
+        assert_eq!(format_money(1234567.891, "KWD"), "1,234,567.891");
+        assert_eq!(format_money(-0.5, "EUR"), "-0.50");
+        assert_eq!(format_money(-0.001, "EUR"), "0.00");
+        assert_eq!(format_money(999.995, "usd"), "1,000.00");
+        assert_eq!(format_money(12.0, ""), "12.00");
+    }
+
+    #[test]
+    fn context_formats_each_amount_in_its_own_currency() {
+        let payload = json!({ "amount": 10.0, "currency": "USD", "received_amount": 1500.0, "received_currency": "JPY", "new_balance": 25000.0 });
+        let received = template_context(EVENT_PAYMENT_RECEIVED, &payload);
+        assert_eq!(received["amount"], "10.00");
+        assert_eq!(received["received_amount"], "1,500");
+        assert_eq!(received["new_balance"], "25,000");
+        let sent = template_context(EVENT_PAYMENT_SENT, &payload);
+        assert_eq!(sent["new_balance"], "25,000.00");
+
+        let top_up = template_context(EVENT_TOP_UP_SUCCEEDED, &json!({ "amount": 5000.0, "source_currency": "JPY", "currency": "USD", "new_balance": 40.0 }));
+        assert_eq!(top_up["amount"], "5,000");
+        assert_eq!(top_up["new_balance"], "40.00");
+    }
+
+    #[test]
+    fn find_template_prefers_the_most_specific_file() {
+        let dir = template_dir_with(&[
+            ("es/payment.sent.telegram.txt", "Subject: Pago (telegram)\nEnviaste {amount}"),
+            ("es/payment.sent.txt", "Subject: Pago enviado\nEnviaste {amount} {currency}"),
+            ("en/payment.received.txt", "Subject: Money in\nGot {amount}"),
+            ("es/deposit.approved.txt", "no subject line"),
+        ]);
+        assert_eq!(find_template_in(&dir, EVENT_PAYMENT_SENT, Some("telegram"), "es").unwrap().subject, "Pago (telegram)");
+        assert_eq!(find_template_in(&dir, EVENT_PAYMENT_SENT, Some("email"), "es").unwrap().subject, "Pago enviado");
+        assert_eq!(find_template_in(&dir, EVENT_PAYMENT_RECEIVED, Some("email"), "es").unwrap().subject, "Money in");
+        assert_eq!(find_template_in(&dir, EVENT_PAYMENT_SENT, None, "fr").unwrap().subject, "Payment sent");
+        // A malformed file is ignored in favour of the built-in template
+        assert_eq!(find_template_in(&dir, EVENT_DEPOSIT_APPROVED, None, "es").unwrap().subject, "Deposit approved");
+        assert_eq!(find_template_in(&dir, "unknown.event", None, "es"), None);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn find_template_reads_files_once() {
+        let dir = template_dir_with(&[("en/payment.sent.txt", "Subject: First\nbody")]);
+        assert_eq!(find_template_in(&dir, EVENT_PAYMENT_SENT, None, "en").unwrap().subject, "First");
+        fs::write(dir.join("en/payment.sent.txt"), "Subject: Second\nbody").unwrap();
+        assert_eq!(find_template_in(&dir, EVENT_PAYMENT_SENT, None, "en").unwrap().subject, "First");
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn shipped_spanish_templates_parse() {
+        for entry in fs::read_dir(Path::new(env!("CARGO_MANIFEST_DIR")).join("templates/es")).unwrap() {
+            let path = entry.unwrap().path();
+            assert!(Template::parse(&fs::read_to_string(&path).unwrap()).is_ok(), "{} does not parse", path.display());
+        }
+    }
+}
@@ -0,0 +1,69 @@
+use chrono::{DateTime, Duration, LocalResult, NaiveDate, NaiveTime, Offset, TimeZone, Utc};
+use chrono_tz::Tz;
+use diesel::prelude::*;
+use diesel::pg::PgConnection;
+use uuid::Uuid;
+use crate::models::{Account, ScheduledTransaction};
+
+pub fn parse_time_zone(name: &str) -> Option<Tz> {
+    name.trim().parse::<Tz>().ok()
+}
+
+// Accounts with an unrecognised zone fall back to UTC rather than stopping their schedules
+pub fn account_time_zone(conn: &mut PgConnection, account_id_to_check: Uuid) -> Result<Tz, diesel::result::Error> {
+    use crate::schema::accounts::dsl::*;
+    let zone: String = accounts
+        .find(account_id_to_check)
+        .select(time_zone)
+        .first(conn)?;
+    Ok(parse_time_zone(&zone).unwrap_or(Tz::UTC))
+}
+
+// The instant a local date and time happens in the zone. A time skipped by a DST gap runs that many
+// minutes later (02:30 becomes 03:30), and a time repeated when clocks go back runs the first time round.
+pub fn local_to_utc(tz: Tz, date: NaiveDate, time: NaiveTime) -> DateTime<Utc> {
+    let local = date.and_time(time);
+    match tz.from_local_datetime(&local) {
+        LocalResult::Single(instant) => instant.with_timezone(&Utc),
+        LocalResult::Ambiguous(earliest, _) => earliest.with_timezone(&Utc),
+        LocalResult::None => {
+            // Interpret the time with the offset in force before the gap
+            let offset_before = tz.offset_from_utc_datetime(&(local - Duration::days(1))).fix();
+            Utc.from_utc_datetime(&(local - Duration::seconds(offset_before.local_minus_utc() as i64)))
+        }
+    }
+}
+
+// The calendar date of an instant in the zone
+pub fn local_date(tz: Tz, instant: DateTime<Utc>) -> NaiveDate {
+    instant.with_timezone(&tz).date_naive()
+}
+
+// Changes the account's zone and moves its open schedules so they keep the same local date and time
+pub fn set_account_time_zone(conn: &mut PgConnection, account_id_to_update: Uuid, zone_name: &str) -> Result<Account, diesel::result::Error> {
+    let new_zone = parse_time_zone(zone_name).ok_or(diesel::result::Error::RollbackTransaction)?;
+    conn.transaction(|conn| {
+        let old_zone = account_time_zone(conn, account_id_to_update)?;
+
+        {
+            use crate::schema::scheduled_transactions::dsl::*;
+            let open_schedules: Vec<ScheduledTransaction> = scheduled_transactions
+                .filter(from_account_id.eq(account_id_to_update))
+                .filter(executed.eq(false))
+                .load(conn)?;
+            for schedule in open_schedules {
+                let next = local_to_utc(new_zone, local_date(old_zone, schedule.scheduled_date), schedule.execution_time);
+                let end = schedule.end_date.map(|end| local_to_utc(new_zone, local_date(old_zone, end), schedule.execution_time));
+                diesel::update(scheduled_transactions.find(schedule.id))
+                    .set((scheduled_date.eq(next), end_date.eq(end)))
+                    .execute(conn)?;
+            }
+        }
+
+        use crate::schema::accounts::dsl::*;
+        diesel::update(accounts.find(account_id_to_update))
+            .set(time_zone.eq(new_zone.name()))
+            .returning(Account::as_returning())
+            .get_result(conn)
+    })
+}
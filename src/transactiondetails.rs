@@ -0,0 +1,231 @@
+use chrono::{DateTime, Utc};
+use diesel::prelude::*;
+use diesel::pg::PgConnection;
+use std::collections::HashMap;
+use uuid::Uuid;
+use crate::accountnumbers::{check_digits, compact};
+use crate::models::{Transaction, TransactionCategory, NewTransactionCategory};
+
+// Longest memo kept with a payment, the length of a SEPA remittance line
+pub const MAX_MEMO_LENGTH: usize = 140;
+// Longest part of an ISO 11649 creditor reference after "RF" and its two check digits
+const MAX_REFERENCE_BODY_LENGTH: usize = 21;
+const MAX_CATEGORY_LENGTH: usize = 32;
+
+// Why money moved, as given by whoever set up the payment, deposit or schedule
+#[derive(Debug, Clone, Default)]
+pub struct PaymentDetails {
+    pub memo: Option<String>,
+    pub reference: Option<String>,
+}
+
+impl PaymentDetails {
+    pub fn none() -> Self {
+        PaymentDetails::default()
+    }
+
+    // Blank values count as not given. A memo longer than MAX_MEMO_LENGTH or a reference that is neither a valid
+    // creditor reference nor something one can be made from is RollbackTransaction.
+    pub fn new(memo: Option<&str>, reference: Option<&str>) -> Result<Self, diesel::result::Error> {
+        let memo = memo.map(str::trim).filter(|m| !m.is_empty());
+        if memo.is_some_and(|m| m.chars().count() > MAX_MEMO_LENGTH) {
+            return Err(diesel::result::Error::RollbackTransaction);
+        }
+        let reference = match reference.map(str::trim).filter(|r| !r.is_empty()) {
+            Some(reference) => Some(parse_reference(reference).ok_or(diesel::result::Error::RollbackTransaction)?),
+            None => None,
+        };
+        Ok(PaymentDetails { memo: memo.map(str::to_string), reference })
+    }
+
+    pub fn memo(&self) -> Option<&str> {
+        self.memo.as_deref()
+    }
+
+    pub fn reference(&self) -> Option<&str> {
+        self.reference.as_deref()
+    }
+}
+
+pub fn is_valid_creditor_reference(input: &str) -> bool {
+    let reference = compact(input);
+    let body_length = reference.len().saturating_sub(4);
+    reference.starts_with("RF")
+        && (1..=MAX_REFERENCE_BODY_LENGTH).contains(&body_length)
+        && reference.chars().all(|c| c.is_ascii_alphanumeric())
+        && reference[2..4] == check_digits(&format!("{}RF", &reference[4..]))
+}
+
+// The ISO 11649 creditor reference for an invoice number or similar, e.g. 539007547034 becomes RF18539007547034
+pub fn creditor_reference(base: &str) -> Option<String> {
+    let base = compact(base);
+    if base.is_empty() || base.len() > MAX_REFERENCE_BODY_LENGTH || !base.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return None;
+    }
+    Some(format!("RF{}{}", check_digits(&format!("{}RF", base)), base))
+}
+
+// A creditor reference as given, or one made from anything else that fits in one. A reference starting with RF
+// whose check digits are wrong is None rather than being wrapped again.
+pub fn parse_reference(input: &str) -> Option<String> {
+    let compacted = compact(input);
+    if is_valid_creditor_reference(&compacted) {
+        return Some(compacted);
+    }
+    let looks_structured = compacted.starts_with("RF") && compacted.chars().skip(2).take(2).filter(|c| c.is_ascii_digit()).count() == 2;
+    if looks_structured {
+        return None;
+    }
+    creditor_reference(&compacted)
+}
+
+// Categories are free text, matched without regard to case, e.g. "rent" or "groceries"
+pub fn normalize_category(input: &str) -> Option<String> {
+    let category = input.split_whitespace().collect::<Vec<_>>().join(" ").to_lowercase();
+    let allowed = category.chars().all(|c| c.is_alphanumeric() || c == ' ' || c == '-' || c == '&');
+    if category.is_empty() || category.chars().count() > MAX_CATEGORY_LENGTH || !allowed {
+        return None;
+    }
+    Some(category)
+}
+
+// The account's sub-accounts' IDs, used to find the transactions it took part in
+fn sub_account_ids(conn: &mut PgConnection, account_id_to_check: Uuid) -> Result<Vec<Uuid>, diesel::result::Error> {
+    use crate::schema::sub_accounts::dsl::*;
+    sub_accounts
+        .filter(account_id.eq(account_id_to_check))
+        .select(id)
+        .load::<Uuid>(conn)
+}
+
+// Files a transaction the account sent or received under a category, replacing any it had; no category removes
+// it. A transaction the account took no part in is NotFound, and an unusable category RollbackTransaction.
+pub fn set_transaction_category(
+    conn: &mut PgConnection,
+    account_id_to_set: Uuid,
+    transaction_id_to_set: Uuid,
+    category_to_set: Option<&str>
+) -> Result<Option<String>, diesel::result::Error> {
+    let subs = sub_account_ids(conn, account_id_to_set)?;
+    {
+        use crate::schema::transactions::dsl::*;
+        transactions
+            .find(transaction_id_to_set)
+            .filter(sub_account_id_from.eq_any(&subs).or(sub_account_id_to.eq_any(&subs)))
+            .select(transaction_id)
+            .first::<Uuid>(conn)?;
+    }
+
+    use crate::schema::transaction_categories::dsl::*;
+    let existing = transaction_categories
+        .filter(transaction_id.eq(transaction_id_to_set))
+        .filter(account_id.eq(account_id_to_set));
+    let Some(input) = category_to_set.filter(|c| !c.trim().is_empty()) else {
+        diesel::delete(existing).execute(conn)?;
+        return Ok(None);
+    };
+    let new_category = normalize_category(input).ok_or(diesel::result::Error::RollbackTransaction)?;
+
+    diesel::insert_into(transaction_categories)
+        .values(&NewTransactionCategory {
+            transaction_id: transaction_id_to_set,
+            account_id: account_id_to_set,
+            category: &new_category,
+        })
+        .on_conflict((transaction_id, account_id))
+        .do_update()
+        .set((category.eq(&new_category), categorized_at.eq(Utc::now())))
+        .execute(conn)?;
+    Ok(Some(new_category))
+}
+
+// The categories the account has used, for suggesting one
+pub fn get_used_categories(conn: &mut PgConnection, account_id_to_view: Uuid) -> Result<Vec<String>, diesel::result::Error> {
+    use crate::schema::transaction_categories::dsl::*;
+    transaction_categories
+        .filter(account_id.eq(account_id_to_view))
+        .select(category)
+        .distinct()
+        .order(category.asc())
+        .load::<String>(conn)
+}
+
+// Searched text is matched literally, not as a LIKE pattern
+fn escape_like(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_")
+}
+
+// What to narrow an account's history down to; fields left empty match everything
+#[derive(Debug, Clone, Default)]
+pub struct TransactionFilter {
+    // Found anywhere in the memo or reference, without regard to case
+    pub text: Option<String>,
+    pub category: Option<String>,
+    // Matches the currency sent or received
+    pub currency: Option<String>,
+    pub from_date: Option<DateTime<Utc>>,
+    pub to_date: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug)]
+pub struct HistoryEntry {
+    pub transaction: Transaction,
+    // The category the viewing account filed the transaction under
+    pub category: Option<String>,
+}
+
+// The account's transactions that match the filter, newest first
+pub fn search_transactions(
+    conn: &mut PgConnection,
+    account_id_to_search: Uuid,
+    filter: &TransactionFilter
+) -> Result<Vec<HistoryEntry>, diesel::result::Error> {
+    use crate::schema::transaction_categories;
+    use crate::schema::transactions::dsl::*;
+    let subs = sub_account_ids(conn, account_id_to_search)?;
+
+    let mut query = transactions
+        .filter(sub_account_id_from.eq_any(&subs).or(sub_account_id_to.eq_any(&subs)))
+        .into_boxed();
+    if let Some(text) = filter.text.as_deref().map(str::trim).filter(|t| !t.is_empty()) {
+        let in_memo = format!("%{}%", escape_like(text));
+        let in_reference = format!("%{}%", escape_like(&compact(text)));
+        query = query.filter(memo.ilike(in_memo).or(reference.like(in_reference)));
+    }
+    if let Some(currency) = filter.currency.as_deref().map(|c| c.trim().to_uppercase()).filter(|c| !c.is_empty()) {
+        query = query.filter(transfer_currency.eq(currency.clone()).or(received_currency.eq(currency)));
+    }
+    if let Some(from_date) = filter.from_date {
+        query = query.filter(transaction_date.ge(from_date));
+    }
+    if let Some(to_date) = filter.to_date {
+        query = query.filter(transaction_date.lt(to_date));
+    }
+    if let Some(wanted) = filter.category.as_deref() {
+        let wanted = normalize_category(wanted).unwrap_or_default();
+        let categorized = transaction_categories::table
+            .filter(transaction_categories::account_id.eq(account_id_to_search))
+            .filter(transaction_categories::category.eq(wanted))
+            .select(transaction_categories::transaction_id);
+        query = query.filter(transaction_id.eq_any(categorized));
+    }
+    let found = query
+        .order(transaction_date.desc())
+        .load::<Transaction>(conn)?;
+
+    let ids: Vec<Uuid> = found.iter().map(|t| t.transaction_id).collect();
+    let mut categories: HashMap<Uuid, String> = transaction_categories::table
+        .filter(transaction_categories::account_id.eq(account_id_to_search))
+        .filter(transaction_categories::transaction_id.eq_any(&ids))
+        .load::<TransactionCategory>(conn)?
+        .into_iter()
+        .map(|c| (c.transaction_id, c.category))
+        .collect();
+    Ok(found
+        .into_iter()
+        .map(|transaction| HistoryEntry {
+            category: categories.remove(&transaction.transaction_id),
+            transaction,
+        })
+        .collect())
+}
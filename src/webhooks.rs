@@ -0,0 +1,307 @@
+use anyhow::{anyhow, Result as AnyResult};
+use chrono::Utc;
+use diesel::prelude::*;
+use diesel::pg::PgConnection;
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use serde_json::json;
+use sha2::Sha256;
+use std::time::Instant;
+use uuid::Uuid;
+use crate::models::{OutboxMessage, WebhookEndpoint, NewWebhookEndpoint, WebhookDelivery, NewWebhookDelivery, EVENT_TYPES};
+use crate::notifications::{webhook_client, resolve_webhook_target};
+
+// Headers sent with every delivery. The signature is "sha256=" followed by the hex HMAC-SHA256, keyed with the
+// endpoint secret, of "<timestamp>.<body>".
+pub const SIGNATURE_HEADER: &str = "X-Rust-EZE-Signature";
+pub const TIMESTAMP_HEADER: &str = "X-Rust-EZE-Timestamp";
+pub const EVENT_HEADER: &str = "X-Rust-EZE-Event";
+pub const DELIVERY_HEADER: &str = "X-Rust-EZE-Delivery";
+
+// Receivers should reject deliveries whose timestamp is further than this from their clock, to stop replays
+pub const SIGNATURE_TOLERANCE_SECONDS: i64 = 5 * 60;
+
+pub fn generate_webhook_secret() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    format!("whsec_{}", hex::encode(bytes))
+}
+
+pub fn sign_payload(secret: &str, timestamp: i64, body: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(format!("{}.{}", timestamp, body).as_bytes());
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+// Checks a delivery's signature in constant time and that its timestamp is recent
+pub fn verify_signature(secret: &str, timestamp: i64, body: &str, signature: &str) -> bool {
+    if (Utc::now().timestamp() - timestamp).abs() > SIGNATURE_TOLERANCE_SECONDS {
+        return false;
+    }
+    let expected = match signature.strip_prefix("sha256=").and_then(|digest| hex::decode(digest).ok()) {
+        Some(expected) => expected,
+        None => return false,
+    };
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(format!("{}.{}", timestamp, body).as_bytes());
+    mac.verify_slice(&expected).is_ok()
+}
+
+pub fn is_valid_event_type(event: &str) -> bool {
+    EVENT_TYPES.contains(&event)
+}
+
+// An endpoint with no event types receives every event
+pub fn endpoint_wants(endpoint: &WebhookEndpoint, event: &str) -> bool {
+    endpoint.enabled && (endpoint.event_types.is_empty() || endpoint.event_types.iter().any(|e| e == event))
+}
+
+// The JSON body of a delivery; the outbox message ID doubles as the event ID so receivers can deduplicate
+pub fn event_body(event: &OutboxMessage) -> String {
+    json!({
+        "id": event.id.to_string(),
+        "type": event.event_type,
+        "created_at": event.created_at.to_rfc3339(),
+        "account_id": event.account_id.map(|id| id.to_string()),
+        "data": event.payload,
+    })
+    .to_string()
+}
+
+// The outcome of one delivery attempt
+#[derive(Debug)]
+pub struct DeliveryAttempt {
+    pub succeeded: bool,
+    pub status_code: Option<i32>,
+    pub error: Option<String>,
+    pub duration_ms: i32,
+}
+
+// Each delivery uses its own client, so the sender can be created and dropped on an async runtime. Endpoints
+// must be public http(s) URLs, like webhook notification channels.
+pub struct WebhookSender;
+
+impl Default for WebhookSender {
+    fn default() -> Self {
+        WebhookSender::new()
+    }
+}
+
+impl WebhookSender {
+    pub fn new() -> Self {
+        WebhookSender
+    }
+
+    // Makes one signed delivery of an event to a URL
+    pub fn send(&self, url: &str, secret: &str, event: &OutboxMessage) -> DeliveryAttempt {
+        let body = event_body(event);
+        let timestamp = Utc::now().timestamp();
+        let started = Instant::now();
+        let response = webhook_client(url).and_then(|(url, client)| {
+            Ok(client
+                .post(url)
+                .header("Content-Type", "application/json")
+                .header(SIGNATURE_HEADER, sign_payload(secret, timestamp, &body))
+                .header(TIMESTAMP_HEADER, timestamp.to_string())
+                .header(EVENT_HEADER, &event.event_type)
+                .header(DELIVERY_HEADER, event.id.to_string())
+                .body(body)
+                .send()?)
+        });
+        let duration_ms = started.elapsed().as_millis().min(i32::MAX as u128) as i32;
+
+        match response {
+            Ok(response) if response.status().is_success() => DeliveryAttempt { succeeded: true, status_code: Some(response.status().as_u16() as i32), error: None, duration_ms },
+            Ok(response) => DeliveryAttempt { succeeded: false, status_code: Some(response.status().as_u16() as i32), error: Some(format!("endpoint returned {}", response.status())), duration_ms },
+            Err(e) => DeliveryAttempt { succeeded: false, status_code: None, error: Some(e.to_string()), duration_ms },
+        }
+    }
+
+    // Makes one signed delivery to an endpoint and records the attempt
+    pub fn deliver(&self, conn: &mut PgConnection, endpoint: &WebhookEndpoint, event: &OutboxMessage, is_replay: bool) -> Result<WebhookDelivery, diesel::result::Error> {
+        let attempt = self.send(&endpoint.url, &endpoint.secret, event);
+        record_delivery(conn, &NewWebhookDelivery {
+            endpoint_id: endpoint.id,
+            outbox_id: event.id,
+            replay: is_replay,
+            succeeded: attempt.succeeded,
+            status_code: attempt.status_code,
+            error: attempt.error.as_deref(),
+            duration_ms: attempt.duration_ms,
+        })
+    }
+
+    // Sends an outbox event to the account's endpoints that want it and have not received it yet, so a retried
+    // event does not reach the endpoints that already accepted it
+    pub fn deliver_event(&self, conn: &mut PgConnection, event: &OutboxMessage) -> AnyResult<usize> {
+        let account = match event.account_id {
+            Some(account) => account,
+            None => return Ok(0),
+        };
+        let endpoints = get_webhook_endpoints(conn, account)?;
+        let mut delivered = 0;
+        let mut failures = Vec::new();
+        for endpoint in endpoints.iter().filter(|e| endpoint_wants(e, &event.event_type)) {
+            if has_succeeded(conn, endpoint.id, event.id)? {
+                continue;
+            }
+            let delivery = self.deliver(conn, endpoint, event, false)?;
+            if delivery.succeeded {
+                delivered += 1;
+            } else {
+                failures.push(format!("webhook {}: {}", endpoint.url, delivery.error.unwrap_or_default()));
+            }
+        }
+        if !failures.is_empty() {
+            return Err(anyhow!("{}", failures.join("; ")));
+        }
+        Ok(delivered)
+    }
+}
+
+fn record_delivery(conn: &mut PgConnection, new_delivery: &NewWebhookDelivery) -> Result<WebhookDelivery, diesel::result::Error> {
+    use crate::schema::webhook_deliveries::dsl::*;
+    diesel::insert_into(webhook_deliveries)
+        .values(new_delivery)
+        .returning(WebhookDelivery::as_returning())
+        .get_result(conn)
+}
+
+fn has_succeeded(conn: &mut PgConnection, endpoint_to_check: Uuid, event_id: Uuid) -> Result<bool, diesel::result::Error> {
+    use crate::schema::webhook_deliveries::dsl::*;
+    diesel::select(diesel::dsl::exists(
+        webhook_deliveries
+            .filter(endpoint_id.eq(endpoint_to_check))
+            .filter(outbox_id.eq(event_id))
+            .filter(succeeded.eq(true)),
+    ))
+    .get_result(conn)
+}
+
+pub fn get_webhook_endpoints(conn: &mut PgConnection, account_id_to_view: Uuid) -> Result<Vec<WebhookEndpoint>, diesel::result::Error> {
+    use crate::schema::webhook_endpoints::dsl::*;
+    webhook_endpoints
+        .filter(account_id.eq(account_id_to_view))
+        .order(created_at.asc())
+        .load::<WebhookEndpoint>(conn)
+}
+
+// Registers an endpoint with a new secret, which is returned once here for the integrator to store
+pub fn add_webhook_endpoint(conn: &mut PgConnection, account_id_to_add: Uuid, endpoint_url: &str, events: &[String]) -> Result<WebhookEndpoint, diesel::result::Error> {
+    use crate::schema::webhook_endpoints::dsl::*;
+    let endpoint_url = endpoint_url.trim();
+    if resolve_webhook_target(endpoint_url).is_err() || !events.iter().all(|e| is_valid_event_type(e)) {
+        return Err(diesel::result::Error::RollbackTransaction);
+    }
+    let new_secret = generate_webhook_secret();
+    let new_endpoint = NewWebhookEndpoint {
+        account_id: account_id_to_add,
+        url: endpoint_url,
+        secret: &new_secret,
+        event_types: events.to_vec(),
+    };
+
+    diesel::insert_into(webhook_endpoints)
+        .values(&new_endpoint)
+        .returning(WebhookEndpoint::as_returning())
+        .get_result(conn)
+}
+
+// Only the owning account can remove an endpoint
+pub fn remove_webhook_endpoint(conn: &mut PgConnection, account_id_to_remove: Uuid, endpoint_to_remove: Uuid) -> Result<usize, diesel::result::Error> {
+    use crate::schema::webhook_endpoints::dsl::*;
+    let removed = diesel::delete(webhook_endpoints.find(endpoint_to_remove).filter(account_id.eq(account_id_to_remove)))
+        .execute(conn)?;
+    if removed == 0 {
+        return Err(diesel::result::Error::NotFound);
+    }
+    Ok(removed)
+}
+
+fn get_owned_endpoint(conn: &mut PgConnection, owner: Uuid, endpoint_to_find: Uuid) -> Result<WebhookEndpoint, diesel::result::Error> {
+    use crate::schema::webhook_endpoints::dsl::*;
+    webhook_endpoints
+        .find(endpoint_to_find)
+        .filter(account_id.eq(owner))
+        .first::<WebhookEndpoint>(conn)
+}
+
+// The most recent delivery attempts to one of the account's endpoints
+pub fn get_webhook_deliveries(conn: &mut PgConnection, account_id_to_view: Uuid, endpoint_to_view: Uuid, limit: i64) -> Result<Vec<WebhookDelivery>, diesel::result::Error> {
+    let endpoint = get_owned_endpoint(conn, account_id_to_view, endpoint_to_view)?;
+    use crate::schema::webhook_deliveries::dsl::*;
+    webhook_deliveries
+        .filter(endpoint_id.eq(endpoint.id))
+        .order(created_at.desc())
+        .limit(limit)
+        .load::<WebhookDelivery>(conn)
+}
+
+// The account's most recent events, which can be replayed
+pub fn get_account_events(conn: &mut PgConnection, account_id_to_view: Uuid, limit: i64) -> Result<Vec<OutboxMessage>, diesel::result::Error> {
+    use crate::schema::outbox::dsl::*;
+    outbox
+        .filter(account_id.eq(account_id_to_view))
+        .order(created_at.desc())
+        .limit(limit)
+        .load::<OutboxMessage>(conn)
+}
+
+// Sends a past event of the account to one of its endpoints again, whatever happened to earlier deliveries.
+// The event ID is unchanged so receivers can tell a replay from a new event.
+pub fn replay_webhook_event(conn: &mut PgConnection, sender: &WebhookSender, account_id_to_replay: Uuid, endpoint_to_replay: Uuid, event_id: Uuid) -> Result<WebhookDelivery, diesel::result::Error> {
+    let endpoint = get_owned_endpoint(conn, account_id_to_replay, endpoint_to_replay)?;
+    let event = {
+        use crate::schema::outbox::dsl::*;
+        outbox
+            .find(event_id)
+            .filter(account_id.eq(account_id_to_replay))
+            .first::<OutboxMessage>(conn)?
+    };
+    sender.deliver(conn, &endpoint, &event, true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: &str = "whsec_test";
+    const BODY: &str = r#"{"id":"1","type":"payment.received"}"#;
+
+    #[test]
+    fn signature_round_trips() {
+        let now = Utc::now().timestamp();
+        let signature = sign_payload(SECRET, now, BODY);
+        assert!(signature.starts_with("sha256="));
+        assert!(verify_signature(SECRET, now, BODY, &signature));
+    }
+
+    #[test]
+    fn tampering_breaks_the_signature() {
+        let now = Utc::now().timestamp();
+        let signature = sign_payload(SECRET, now, BODY);
+        assert!(!verify_signature(SECRET, now, &BODY.replace("received", "sent"), &signature));
+        assert!(!verify_signature("whsec_other", now, BODY, &signature));
+        assert!(!verify_signature(SECRET, now - 1, BODY, &signature));
+        assert!(!verify_signature(SECRET, now, BODY, &signature.replace("sha256=", "")));
+        assert!(!verify_signature(SECRET, now, BODY, "sha256=not-hex"));
+    }
+
+    #[test]
+    fn stale_and_future_timestamps_are_rejected() {
+        let now = Utc::now().timestamp();
+        let stale = now - SIGNATURE_TOLERANCE_SECONDS - 1;
+        assert!(!verify_signature(SECRET, stale, BODY, &sign_payload(SECRET, stale, BODY)));
+        let future = now + SIGNATURE_TOLERANCE_SECONDS + 1;
+        assert!(!verify_signature(SECRET, future, BODY, &sign_payload(SECRET, future, BODY)));
+        let recent = now - SIGNATURE_TOLERANCE_SECONDS + 5;
+        assert!(verify_signature(SECRET, recent, BODY, &sign_payload(SECRET, recent, BODY)));
+    }
+
+    #[test]
+    fn generated_secrets_are_unique() {
+        let secret = generate_webhook_secret();
+        assert!(secret.starts_with("whsec_") && secret.len() == "whsec_".len() + 64);
+        assert_ne!(secret, generate_webhook_secret());
+    }
+}
@@ -0,0 +1,65 @@
+// Fanning outbox events out to notification channels, against the migrated database in TEST_DATABASE_URL. Each
+// test runs in a transaction that is rolled back; run them with `cargo test -- --ignored`.
+use anyhow::{anyhow, Result as AnyResult};
+use diesel::prelude::*;
+use rust_eze::database::create_account;
+use rust_eze::models::{CHANNEL_EMAIL, CHANNEL_TELEGRAM, EVENT_BALANCE_LOW};
+use rust_eze::notifications::{add_notification_channel, NotificationDispatcher, Notifier};
+use rust_eze::outbox::enqueue_event;
+use serde_json::json;
+use std::cell::RefCell;
+use std::rc::Rc;
+use uuid::Uuid;
+
+fn test_connection() -> PgConnection {
+    let database_url = std::env::var("TEST_DATABASE_URL").expect("TEST_DATABASE_URL is set to a migrated database");
+    let mut conn = PgConnection::establish(&database_url).expect("TEST_DATABASE_URL is reachable");
+    conn.begin_test_transaction().unwrap();
+    conn
+}
+
+// A Telegram stand-in that records the chats it reached and fails for the ones listed as down
+struct RecordingNotifier {
+    sent: Rc<RefCell<Vec<String>>>,
+    down: Rc<RefCell<Vec<String>>>,
+}
+
+impl Notifier for RecordingNotifier {
+    fn channel(&self) -> &'static str {
+        CHANNEL_TELEGRAM
+    }
+
+    fn send(&self, address: &str, _subject: &str, _message: &str) -> AnyResult<()> {
+        if self.down.borrow().iter().any(|down| down == address) {
+            return Err(anyhow!("chat {} is down", address));
+        }
+        self.sent.borrow_mut().push(address.to_string());
+        Ok(())
+    }
+}
+
+#[test]
+#[ignore = "needs a migrated database in TEST_DATABASE_URL"]
+fn retries_only_reach_the_channels_that_failed() {
+    let mut conn = test_connection();
+    let account = create_account(&mut conn, &format!("Notification test {}", Uuid::new_v4())).unwrap().id;
+    add_notification_channel(&mut conn, account, CHANNEL_TELEGRAM, "1001").unwrap();
+    add_notification_channel(&mut conn, account, CHANNEL_TELEGRAM, "1002").unwrap();
+    // No email notifier is configured below, so this channel is skipped rather than failed
+    add_notification_channel(&mut conn, account, CHANNEL_EMAIL, "customer@example.com").unwrap();
+    let event = enqueue_event(&mut conn, EVENT_BALANCE_LOW, Some(account), json!({})).unwrap();
+
+    let (sent, down) = (Rc::new(RefCell::new(Vec::new())), Rc::new(RefCell::new(vec!["1002".to_string()])));
+    let dispatcher = NotificationDispatcher::new(vec![Box::new(RecordingNotifier { sent: sent.clone(), down: down.clone() })]);
+
+    assert!(dispatcher.notify_event(&mut conn, &event).is_err());
+    assert_eq!(*sent.borrow(), vec!["1001"]);
+
+    down.borrow_mut().clear();
+    assert_eq!(dispatcher.notify_event(&mut conn, &event).unwrap(), 1);
+    assert_eq!(*sent.borrow(), vec!["1001", "1002"]);
+
+    // Every configured channel has been reached, so there is nothing left to send
+    assert_eq!(dispatcher.notify_event(&mut conn, &event).unwrap(), 0);
+    assert_eq!(sent.borrow().len(), 2);
+}
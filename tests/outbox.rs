@@ -0,0 +1,38 @@
+// Watching the outbox for messages that are not getting through, against the migrated database in TEST_DATABASE_URL.
+// Each test runs in a transaction that is rolled back; run them with `cargo test -- --ignored`.
+use chrono::{Duration, Utc};
+use diesel::prelude::*;
+use rust_eze::models::{EVENT_BALANCE_LOW, OUTBOX_STATUS_IN_FLIGHT};
+use rust_eze::outbox::{enqueue_event, get_stuck_outbox_messages};
+use serde_json::json;
+
+fn test_connection() -> PgConnection {
+    let database_url = std::env::var("TEST_DATABASE_URL").expect("TEST_DATABASE_URL is set to a migrated database");
+    let mut conn = PgConnection::establish(&database_url).expect("TEST_DATABASE_URL is reachable");
+    conn.begin_test_transaction().unwrap();
+    conn
+}
+
+#[test]
+#[ignore = "needs a migrated database in TEST_DATABASE_URL"]
+fn a_message_whose_lease_ran_out_is_stuck() {
+    let mut conn = test_connection();
+    let expired = enqueue_event(&mut conn, EVENT_BALANCE_LOW, None, json!({})).unwrap();
+    let leased = enqueue_event(&mut conn, EVENT_BALANCE_LOW, None, json!({})).unwrap();
+    {
+        use rust_eze::schema::outbox::dsl::*;
+        diesel::update(outbox.find(expired.id))
+            .set((status.eq(OUTBOX_STATUS_IN_FLIGHT), lease_expires_at.eq(Utc::now() - Duration::minutes(5))))
+            .execute(&mut conn)
+            .unwrap();
+        diesel::update(outbox.find(leased.id))
+            .set((status.eq(OUTBOX_STATUS_IN_FLIGHT), lease_expires_at.eq(Utc::now() + Duration::minutes(5))))
+            .execute(&mut conn)
+            .unwrap();
+    }
+
+    let stuck: Vec<_> = get_stuck_outbox_messages(&mut conn).unwrap().into_iter().map(|message| message.id).collect();
+    assert!(stuck.contains(&expired.id));
+    // A relay is still working on this one
+    assert!(!stuck.contains(&leased.id));
+}
@@ -0,0 +1,77 @@
+// Reversing and refunding payments, against the migrated database in TEST_DATABASE_URL. Each test runs in a
+// transaction that is rolled back; run them with `cargo test -- --ignored`.
+use diesel::prelude::*;
+use rust_eze::database::{create_account, create_sub_account};
+use rust_eze::models::{OutboxMessage, Transaction, EVENT_PAYMENT_RECEIVED, EVENT_PAYMENT_SENT};
+use rust_eze::moneytransfer::{transfer_money, reverse_transaction, refund_transaction, MissingSubAccountPolicy};
+use rust_eze::transactiondetails::PaymentDetails;
+use uuid::Uuid;
+
+fn test_connection() -> PgConnection {
+    let database_url = std::env::var("TEST_DATABASE_URL").expect("TEST_DATABASE_URL is set to a migrated database");
+    let mut conn = PgConnection::establish(&database_url).expect("TEST_DATABASE_URL is reachable");
+    conn.begin_test_transaction().unwrap();
+    conn
+}
+
+fn new_account(conn: &mut PgConnection, balance: f64) -> Uuid {
+    let account = create_account(conn, &format!("Reversal test {}", Uuid::new_v4())).unwrap().id;
+    create_sub_account(conn, account, "USD", balance).unwrap();
+    account
+}
+
+fn pay(conn: &mut PgConnection, from: Uuid, to: Uuid, amount: f64) -> Transaction {
+    transfer_money(conn, from, to, amount, "USD", &PaymentDetails::none(), MissingSubAccountPolicy::Reject).unwrap()
+}
+
+// The (event type, account) pairs queued for a transaction
+fn queued_events(conn: &mut PgConnection, transaction: &Transaction) -> Vec<(String, Option<Uuid>)> {
+    use rust_eze::schema::outbox::dsl::*;
+    let mut events: Vec<(String, Option<Uuid>)> = outbox
+        .filter(payload.retrieve_as_text("transaction_id").eq(transaction.transaction_id.to_string()))
+        .load::<OutboxMessage>(conn)
+        .unwrap()
+        .into_iter()
+        .map(|event| (event.event_type, event.account_id))
+        .collect();
+    events.sort();
+    events
+}
+
+#[test]
+#[ignore = "needs a migrated database in TEST_DATABASE_URL"]
+fn reversals_notify_both_sides() {
+    let mut conn = test_connection();
+    let (sender, recipient) = (new_account(&mut conn, 100.0), new_account(&mut conn, 0.0));
+    let payment = pay(&mut conn, sender, recipient, 40.0);
+
+    let reversal = reverse_transaction(&mut conn, payment.transaction_id).unwrap();
+    assert_eq!(queued_events(&mut conn, &reversal), vec![
+        (EVENT_PAYMENT_RECEIVED.to_string(), Some(sender)),
+        (EVENT_PAYMENT_SENT.to_string(), Some(recipient)),
+    ]);
+}
+
+#[test]
+#[ignore = "needs a migrated database in TEST_DATABASE_URL"]
+fn refunds_notify_both_sides_and_refused_refunds_queue_nothing() {
+    let mut conn = test_connection();
+    let (sender, recipient) = (new_account(&mut conn, 100.0), new_account(&mut conn, 0.0));
+    let payment = pay(&mut conn, sender, recipient, 40.0);
+
+    let refund = refund_transaction(&mut conn, recipient, payment.transaction_id, 15.0).unwrap();
+    assert_eq!(queued_events(&mut conn, &refund), vec![
+        (EVENT_PAYMENT_RECEIVED.to_string(), Some(sender)),
+        (EVENT_PAYMENT_SENT.to_string(), Some(recipient)),
+    ]);
+
+    // Only the recipient can refund, and never more than is left; nothing is queued for a refused refund
+    let queued = |conn: &mut PgConnection| {
+        use rust_eze::schema::outbox::dsl::*;
+        outbox.count().get_result::<i64>(conn).unwrap()
+    };
+    let before = queued(&mut conn);
+    assert!(refund_transaction(&mut conn, sender, payment.transaction_id, 5.0).is_err());
+    assert!(refund_transaction(&mut conn, recipient, payment.transaction_id, 30.0).is_err());
+    assert_eq!(queued(&mut conn), before);
+}
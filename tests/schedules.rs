@@ -0,0 +1,100 @@
+// Editing and checking scheduled payments, against the migrated database in TEST_DATABASE_URL. Each test runs in
+// a transaction that is rolled back; run them with `cargo test -- --ignored`.
+use chrono::{Datelike, Duration, NaiveDate, NaiveTime, Utc};
+use chrono_tz::UTC;
+use diesel::prelude::*;
+use rust_eze::database::{create_account, create_sub_account, set_auto_top_up, add_scheduled_transaction, update_scheduled_transaction, ScheduleUpdate};
+use rust_eze::models::{OutboxMessage, EVENT_TOP_UP_FAILED};
+use rust_eze::moneytransfer::MissingSubAccountPolicy;
+use rust_eze::recurringpayments::{check_upcoming_balances, forecast_scheduled_transactions, schedule_rule, Recurrence, ScheduleOptions};
+use rust_eze::recurringpayments::{try_acquire_scheduler_lock, holds_scheduler_lock, release_scheduler_lock};
+use uuid::Uuid;
+
+fn test_connection() -> PgConnection {
+    let database_url = std::env::var("TEST_DATABASE_URL").expect("TEST_DATABASE_URL is set to a migrated database");
+    let mut conn = PgConnection::establish(&database_url).expect("TEST_DATABASE_URL is reachable");
+    conn.begin_test_transaction().unwrap();
+    conn
+}
+
+fn new_account(conn: &mut PgConnection) -> Uuid {
+    create_account(conn, &format!("Schedule test {}", Uuid::new_v4())).unwrap().id
+}
+
+fn date(year: i32, month: u32, day: u32) -> NaiveDate {
+    NaiveDate::from_ymd_opt(year, month, day).unwrap()
+}
+
+#[test]
+#[ignore = "needs a migrated database in TEST_DATABASE_URL"]
+fn moving_a_monthly_schedule_moves_the_day_it_repeats_on() {
+    let mut conn = test_connection();
+    let (payer, payee) = (new_account(&mut conn), new_account(&mut conn));
+    let year = Utc::now().year() + 1;
+    let nine = NaiveTime::from_hms_opt(9, 0, 0).unwrap();
+
+    let options = ScheduleOptions::new(Recurrence::parse("monthly").unwrap());
+    let schedule = add_scheduled_transaction(&mut conn, payer, payee, 10.0, "USD", date(year, 1, 31).and_time(nine), &options).unwrap();
+    assert_eq!(schedule.recurrence.as_deref(), Some("FREQ=MONTHLY;BYMONTHDAY=31"));
+
+    let changes = ScheduleUpdate { amount: None, next_date: Some(date(year, 3, 15)), execution_time: None, recurrence: None };
+    let moved = update_scheduled_transaction(&mut conn, payer, schedule.id, &changes).unwrap();
+    assert_eq!(moved.scheduled_date.date_naive(), date(year, 3, 15));
+    assert_eq!(moved.recurrence.as_deref(), Some("FREQ=MONTHLY;BYMONTHDAY=15"));
+    let rule = schedule_rule(&moved, UTC).unwrap();
+    assert_eq!(rule.next_after(date(year, 3, 15)), date(year, 4, 15));
+}
+
+#[test]
+#[ignore = "needs a migrated database in TEST_DATABASE_URL"]
+fn a_failed_top_up_is_notified_once_per_payment() {
+    let mut conn = test_connection();
+    let (payer, payee) = (new_account(&mut conn), new_account(&mut conn));
+    create_sub_account(&mut conn, payer, "USD", 0.0).unwrap();
+    // The source is empty, so the top-up fails whether or not exchange rates can be fetched
+    create_sub_account(&mut conn, payer, "EUR", 0.0).unwrap();
+    create_sub_account(&mut conn, payee, "USD", 0.0).unwrap();
+    set_auto_top_up(&mut conn, payer, "USD", Some("EUR")).unwrap();
+    let tomorrow = Utc::now().date_naive() + Duration::days(1);
+    let schedule = add_scheduled_transaction(&mut conn, payer, payee, 50.0, "USD", tomorrow.and_hms_opt(9, 0, 0).unwrap(), &ScheduleOptions::one_off()).unwrap();
+
+    check_upcoming_balances(&mut conn, MissingSubAccountPolicy::Reject).unwrap();
+    check_upcoming_balances(&mut conn, MissingSubAccountPolicy::Reject).unwrap();
+
+    let failures: Vec<OutboxMessage> = {
+        use rust_eze::schema::outbox::dsl::*;
+        outbox.filter(account_id.eq(payer)).filter(event_type.eq(EVENT_TOP_UP_FAILED)).load(&mut conn).unwrap()
+    };
+    assert_eq!(failures.len(), 1);
+    assert_eq!(failures[0].payload["schedule_id"], schedule.id.to_string());
+    assert_eq!(failures[0].payload["due_date"], tomorrow.to_string());
+}
+
+#[test]
+#[ignore = "needs a migrated database in TEST_DATABASE_URL"]
+fn the_forecast_resolves_payees_as_the_policy_does() {
+    let mut conn = test_connection();
+    let (payer, payee) = (new_account(&mut conn), new_account(&mut conn));
+    create_sub_account(&mut conn, payer, "USD", 100.0).unwrap();
+    // The payee holds no USD, so the payment only goes through if a sub-account is opened for it
+    create_sub_account(&mut conn, payee, "EUR", 0.0).unwrap();
+    let tomorrow = Utc::now().date_naive() + Duration::days(1);
+    let schedule = add_scheduled_transaction(&mut conn, payer, payee, 40.0, "USD", tomorrow.and_hms_opt(9, 0, 0).unwrap(), &ScheduleOptions::one_off()).unwrap();
+
+    let succeeds = |conn: &mut PgConnection, policy| {
+        let forecast = forecast_scheduled_transactions(conn, tomorrow + Duration::days(1), policy).unwrap();
+        forecast.entries.iter().find(|entry| entry.schedule_id == schedule.id).unwrap().will_succeed
+    };
+    assert!(!succeeds(&mut conn, MissingSubAccountPolicy::Reject));
+    assert!(succeeds(&mut conn, MissingSubAccountPolicy::AutoOpen));
+}
+
+#[test]
+#[ignore = "needs a migrated database in TEST_DATABASE_URL"]
+fn the_leader_notices_when_it_no_longer_holds_the_scheduler_lock() {
+    let mut conn = test_connection();
+    assert!(try_acquire_scheduler_lock(&mut conn).unwrap(), "no scheduler may be running against the test database");
+    assert!(holds_scheduler_lock(&mut conn).unwrap());
+    release_scheduler_lock(&mut conn).unwrap();
+    assert!(!holds_scheduler_lock(&mut conn).unwrap());
+}
@@ -0,0 +1,114 @@
+// Linking Telegram users and deciding Telegram transfers, against the migrated database in TEST_DATABASE_URL. Each
+// test runs in a transaction that is rolled back; run them with `cargo test -- --ignored`.
+use chrono::{Duration, Utc};
+use diesel::prelude::*;
+use rust_eze::database::create_account;
+use rust_eze::models::TELEGRAM_TRANSFER_DECLINED;
+use rust_eze::moneytransfer::MissingSubAccountPolicy;
+use rust_eze::telegram::{create_telegram_link_code, redeem_telegram_link_code, get_linked_account, create_telegram_transfer, confirm_telegram_transfer, decline_telegram_transfer};
+use rust_eze::telegram::{LINK_CODE_TTL_MINUTES, TRANSFER_CONFIRM_MINUTES};
+use uuid::Uuid;
+
+fn test_connection() -> PgConnection {
+    let database_url = std::env::var("TEST_DATABASE_URL").expect("TEST_DATABASE_URL is set to a migrated database");
+    let mut conn = PgConnection::establish(&database_url).expect("TEST_DATABASE_URL is reachable");
+    conn.begin_test_transaction().unwrap();
+    conn
+}
+
+fn new_account(conn: &mut PgConnection) -> Uuid {
+    create_account(conn, &format!("Telegram test {}", Uuid::new_v4())).unwrap().id
+}
+
+// A Telegram user ID no real user has
+fn new_user_id() -> i64 {
+    -(Uuid::new_v4().as_u128() as i64).abs() - 1
+}
+
+#[test]
+#[ignore = "needs a migrated database in TEST_DATABASE_URL"]
+fn link_code_links_once() {
+    let mut conn = test_connection();
+    let account = new_account(&mut conn);
+    let user_id = new_user_id();
+
+    let code = create_telegram_link_code(&mut conn, account).unwrap();
+    // Codes are typed by hand, so case and surrounding spaces do not matter
+    assert_eq!(redeem_telegram_link_code(&mut conn, &format!(" {} ", code.to_lowercase()), user_id, 42).unwrap(), account);
+    assert_eq!(get_linked_account(&mut conn, user_id).unwrap(), Some(account));
+
+    let other_user = new_user_id();
+    assert_eq!(redeem_telegram_link_code(&mut conn, &code, other_user, 43), Err(diesel::result::Error::NotFound));
+    assert_eq!(get_linked_account(&mut conn, other_user).unwrap(), None);
+}
+
+#[test]
+#[ignore = "needs a migrated database in TEST_DATABASE_URL"]
+fn expired_and_replaced_link_codes_are_not_found() {
+    let mut conn = test_connection();
+    let account = new_account(&mut conn);
+    let user_id = new_user_id();
+
+    let replaced = create_telegram_link_code(&mut conn, account).unwrap();
+    let expired = create_telegram_link_code(&mut conn, account).unwrap();
+    assert_eq!(redeem_telegram_link_code(&mut conn, &replaced, user_id, 42), Err(diesel::result::Error::NotFound));
+
+    {
+        use rust_eze::schema::telegram_link_codes::dsl::*;
+        diesel::update(telegram_link_codes.find(&expired))
+            .set(expires_at.eq(Utc::now() - Duration::minutes(LINK_CODE_TTL_MINUTES)))
+            .execute(&mut conn)
+            .unwrap();
+    }
+    assert_eq!(redeem_telegram_link_code(&mut conn, &expired, user_id, 42), Err(diesel::result::Error::NotFound));
+    assert_eq!(redeem_telegram_link_code(&mut conn, "NOSUCHCODE", user_id, 42), Err(diesel::result::Error::NotFound));
+    assert_eq!(get_linked_account(&mut conn, user_id).unwrap(), None);
+}
+
+#[test]
+#[ignore = "needs a migrated database in TEST_DATABASE_URL"]
+fn only_the_sender_can_decide_a_transfer() {
+    let mut conn = test_connection();
+    let (sender, recipient, stranger) = (new_account(&mut conn), new_account(&mut conn), new_account(&mut conn));
+    let transfer = create_telegram_transfer(&mut conn, sender, recipient, 10.0, "usd").unwrap();
+    assert_eq!(transfer.currency, "USD");
+
+    // Another account's transfer ID is treated as unknown, whoever it was sent to
+    for other in [stranger, recipient] {
+        assert_eq!(decline_telegram_transfer(&mut conn, other, transfer.id).map(|t| t.id), Err(diesel::result::Error::NotFound));
+        assert_eq!(confirm_telegram_transfer(&mut conn, other, transfer.id, MissingSubAccountPolicy::Reject).map(|t| t.transaction_id), Err(diesel::result::Error::NotFound));
+    }
+
+    let declined = decline_telegram_transfer(&mut conn, sender, transfer.id).unwrap();
+    assert_eq!(declined.status, TELEGRAM_TRANSFER_DECLINED);
+    // Decided transfers cannot be decided again
+    assert_eq!(decline_telegram_transfer(&mut conn, sender, transfer.id).map(|t| t.id), Err(diesel::result::Error::NotFound));
+    assert_eq!(confirm_telegram_transfer(&mut conn, sender, transfer.id, MissingSubAccountPolicy::Reject).map(|t| t.transaction_id), Err(diesel::result::Error::NotFound));
+}
+
+#[test]
+#[ignore = "needs a migrated database in TEST_DATABASE_URL"]
+fn expired_transfers_cannot_be_decided() {
+    let mut conn = test_connection();
+    let (sender, recipient) = (new_account(&mut conn), new_account(&mut conn));
+    let transfer = create_telegram_transfer(&mut conn, sender, recipient, 10.0, "USD").unwrap();
+    {
+        use rust_eze::schema::telegram_transfers::dsl::*;
+        diesel::update(telegram_transfers.find(transfer.id))
+            .set(created_at.eq(Utc::now() - Duration::minutes(TRANSFER_CONFIRM_MINUTES + 1)))
+            .execute(&mut conn)
+            .unwrap();
+    }
+    assert_eq!(confirm_telegram_transfer(&mut conn, sender, transfer.id, MissingSubAccountPolicy::Reject).map(|t| t.transaction_id), Err(diesel::result::Error::NotFound));
+    assert_eq!(decline_telegram_transfer(&mut conn, sender, transfer.id).map(|t| t.id), Err(diesel::result::Error::NotFound));
+}
+
+#[test]
+#[ignore = "needs a migrated database in TEST_DATABASE_URL"]
+fn transfers_to_yourself_or_of_nothing_are_refused() {
+    let mut conn = test_connection();
+    let (sender, recipient) = (new_account(&mut conn), new_account(&mut conn));
+    assert!(matches!(create_telegram_transfer(&mut conn, sender, sender, 10.0, "USD"), Err(diesel::result::Error::RollbackTransaction)));
+    assert!(matches!(create_telegram_transfer(&mut conn, sender, recipient, 0.0, "USD"), Err(diesel::result::Error::RollbackTransaction)));
+    assert!(matches!(create_telegram_transfer(&mut conn, sender, Uuid::new_v4(), 10.0, "USD"), Err(diesel::result::Error::NotFound)));
+}
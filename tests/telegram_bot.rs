@@ -0,0 +1,154 @@
+// Runs the telegram_bot binary against a local stub of the Telegram Bot API, with the migrated database in
+// TEST_DATABASE_URL. The bot has its own connections, so the accounts set up here are committed, and deleted again
+// when the test ends; run it with `cargo test -- --ignored`.
+use actix_web::{web, App, HttpResponse, HttpServer};
+use diesel::prelude::*;
+use rust_eze::database::{create_account, create_sub_account};
+use rust_eze::moneytransfer::{transfer_money, MissingSubAccountPolicy};
+use rust_eze::telegram::{create_telegram_link_code, redeem_telegram_link_code};
+use rust_eze::transactiondetails::PaymentDetails;
+use serde_json::{json, Value};
+use std::net::SocketAddr;
+use std::process::{Child, Command};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+fn database_url() -> String {
+    std::env::var("TEST_DATABASE_URL").expect("TEST_DATABASE_URL is set to a migrated database")
+}
+
+// The Bot API as the bot sees it: the queued updates are handed out once, and every message sent is recorded
+#[derive(Default)]
+struct BotApi {
+    updates: Mutex<Vec<Value>>,
+    sent: Mutex<Vec<Value>>,
+}
+
+async fn bot_method(path: web::Path<(String, String)>, body: web::Bytes, api: web::Data<Arc<BotApi>>) -> HttpResponse {
+    let request: Value = serde_json::from_slice(&body).unwrap_or(Value::Null);
+    let result = match path.1.to_lowercase().as_str() {
+        "getme" => json!({
+            "id": 1, "is_bot": true, "first_name": "Rust EZE", "username": "rust_eze_test_bot",
+            "can_join_groups": false, "can_read_all_group_messages": false, "supports_inline_queries": false,
+        }),
+        "getupdates" => {
+            let updates = std::mem::take(&mut *api.updates.lock().unwrap());
+            if updates.is_empty() {
+                // Stands in for long polling, so the bot does not spin
+                actix_web::rt::time::sleep(Duration::from_millis(200)).await;
+            }
+            json!(updates)
+        }
+        "sendmessage" => {
+            api.sent.lock().unwrap().push(request.clone());
+            json!({
+                "message_id": api.sent.lock().unwrap().len() + 100,
+                "date": chrono::Utc::now().timestamp(),
+                "chat": { "id": request["chat_id"], "type": "private", "first_name": "Test" },
+                "text": request["text"],
+            })
+        }
+        // setMyCommands, deleteWebhook and anything else the bot calls on startup
+        _ => json!(true),
+    };
+    HttpResponse::Ok().json(json!({ "ok": true, "result": result }))
+}
+
+// Serves the stub on a free local port for the rest of the test run
+fn start_bot_api(api: Arc<BotApi>) -> SocketAddr {
+    let (started, address) = mpsc::channel();
+    std::thread::spawn(move || {
+        actix_web::rt::System::new().block_on(async move {
+            let server = HttpServer::new(move || App::new().app_data(web::Data::new(api.clone())).route("/{bot}/{method}", web::post().to(bot_method)))
+                .workers(1)
+                .disable_signals()
+                .bind(("127.0.0.1", 0))
+                .unwrap();
+            started.send(server.addrs()[0]).unwrap();
+            server.run().await
+        })
+    });
+    address.recv().unwrap()
+}
+
+// Deletes the accounts, and with them their sub-accounts, transactions and Telegram link, and stops the bot
+struct Fixture {
+    accounts: Vec<Uuid>,
+    bot: Option<Child>,
+}
+
+impl Drop for Fixture {
+    fn drop(&mut self) {
+        if let Some(mut bot) = self.bot.take() {
+            let _ = bot.kill();
+            let _ = bot.wait();
+        }
+        use rust_eze::schema::accounts::dsl::*;
+        let mut conn = PgConnection::establish(&database_url()).unwrap();
+        diesel::delete(accounts.filter(id.eq_any(&self.accounts))).execute(&mut conn).unwrap();
+    }
+}
+
+fn text_update(user_id: u64, text: &str) -> Value {
+    json!({
+        "update_id": 1,
+        "message": {
+            "message_id": 1,
+            "date": chrono::Utc::now().timestamp(),
+            "chat": { "id": user_id, "type": "private", "first_name": "Test" },
+            "from": { "id": user_id, "is_bot": false, "first_name": "Test" },
+            "text": text,
+            "entities": [{ "type": "bot_command", "offset": 0, "length": text.len() }],
+        },
+    })
+}
+
+#[test]
+#[ignore = "needs a migrated database in TEST_DATABASE_URL"]
+fn history_shows_what_was_sent_and_received() {
+    let mut conn = PgConnection::establish(&database_url()).expect("TEST_DATABASE_URL is reachable");
+    let holder = create_account(&mut conn, &format!("Bot test {}", Uuid::new_v4())).unwrap().id;
+    let other = create_account(&mut conn, &format!("Bot test {}", Uuid::new_v4())).unwrap().id;
+    let mut fixture = Fixture { accounts: vec![holder, other], bot: None };
+    create_sub_account(&mut conn, holder, "USD", 100.0).unwrap();
+    create_sub_account(&mut conn, other, "USD", 100.0).unwrap();
+    transfer_money(&mut conn, holder, other, 10.0, "USD", &PaymentDetails::none(), MissingSubAccountPolicy::Reject).unwrap();
+    transfer_money(&mut conn, other, holder, 3.0, "USD", &PaymentDetails::none(), MissingSubAccountPolicy::Reject).unwrap();
+
+    // A Telegram user ID no real user has, small enough to survive JSON
+    let user_id = (Uuid::new_v4().as_u128() as u64 >> 12) + 1;
+    let code = create_telegram_link_code(&mut conn, holder).unwrap();
+    redeem_telegram_link_code(&mut conn, &code, user_id as i64, user_id as i64).unwrap();
+
+    let api = Arc::new(BotApi::default());
+    api.updates.lock().unwrap().push(text_update(user_id, "/history"));
+    let address = start_bot_api(api.clone());
+    let work_dir = std::env::temp_dir().join(format!("rust_eze_telegram_bot_{}", Uuid::new_v4()));
+    std::fs::create_dir_all(&work_dir).unwrap();
+    // Run outside the repository so its .env is not loaded
+    fixture.bot = Some(Command::new(env!("CARGO_BIN_EXE_telegram_bot"))
+        .current_dir(&work_dir)
+        .env("TELEGRAM_API_URL", format!("http://{}", address))
+        .env("TELEGRAM_BOT_TOKEN", "123456:test")
+        .env("DATABASE_URL", database_url())
+        .env_remove("TELEGRAM_OPERATOR_CHAT_ID")
+        .spawn()
+        .expect("telegram_bot starts"));
+
+    let started = Instant::now();
+    let reply = loop {
+        if let Some(reply) = api.sent.lock().unwrap().iter().find(|sent| sent["chat_id"] == json!(user_id)) {
+            break reply["text"].as_str().unwrap().to_string();
+        }
+        assert!(started.elapsed() < Duration::from_secs(30), "the bot did not answer /history");
+        sleep(Duration::from_millis(100));
+    };
+    let lines: Vec<&str> = reply.lines().collect();
+    assert_eq!(lines.len(), 2, "{}", reply);
+    assert!(lines[0].ends_with(" received 3.00 USD"), "{}", reply);
+    assert!(lines[1].ends_with(" sent 10.00 USD"), "{}", reply);
+    drop(fixture);
+    std::fs::remove_dir_all(work_dir).unwrap();
+}
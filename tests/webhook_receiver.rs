@@ -0,0 +1,113 @@
+// Delivers signed events to the webhook_receiver binary, as an integrator's endpoint would receive them
+use chrono::Utc;
+use rust_eze::models::{OutboxMessage, OUTBOX_STATUS_PENDING};
+use rust_eze::webhooks::WebhookSender;
+use serde_json::json;
+use std::io::{BufRead, BufReader};
+use std::net::{TcpListener, TcpStream};
+use std::process::{Child, Command, Stdio};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+const SECRET: &str = "whsec_integration";
+
+// A receiver on a free local port, stopped when dropped
+struct Receiver {
+    child: Child,
+    url: String,
+}
+
+impl Receiver {
+    fn start(extra_args: &[&str]) -> Receiver {
+        let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let listen = format!("127.0.0.1:{}", port);
+        let child = Command::new(env!("CARGO_BIN_EXE_webhook_receiver"))
+            .args(["--secret", SECRET, "--listen", &listen])
+            .args(extra_args)
+            .stdout(Stdio::piped())
+            .spawn()
+            .expect("webhook_receiver starts");
+        let started = Instant::now();
+        while TcpStream::connect(&listen).is_err() {
+            assert!(started.elapsed() < Duration::from_secs(10), "webhook_receiver did not start listening");
+            sleep(Duration::from_millis(50));
+        }
+        Receiver { child, url: format!("http://{}/hooks", listen) }
+    }
+
+    // Stops the receiver and returns what it printed
+    fn output(mut self) -> Vec<String> {
+        self.child.kill().unwrap();
+        let stdout = self.child.stdout.take().unwrap();
+        BufReader::new(stdout).lines().map_while(Result::ok).collect()
+    }
+}
+
+impl Drop for Receiver {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+fn event() -> OutboxMessage {
+    OutboxMessage {
+        id: Uuid::new_v4(),
+        event_type: "payment.received".to_string(),
+        account_id: Some(Uuid::new_v4()),
+        subject: "Payment received".to_string(),
+        message: "You received 10.00 USD".to_string(),
+        payload: json!({ "amount": 10.0, "currency": "USD" }),
+        status: OUTBOX_STATUS_PENDING.to_string(),
+        attempt_count: 0,
+        next_attempt_at: Utc::now(),
+        last_error: None,
+        created_at: Utc::now(),
+        delivered_at: None,
+        lease_expires_at: None,
+    }
+}
+
+fn allow_local_receiver() {
+    std::env::set_var("WEBHOOK_ALLOW_PRIVATE_TARGETS", "true");
+}
+
+#[test]
+fn receiver_accepts_a_signed_delivery() {
+    allow_local_receiver();
+    let receiver = Receiver::start(&[]);
+    let event = event();
+
+    let attempt = WebhookSender::new().send(&receiver.url, SECRET, &event);
+    assert!(attempt.succeeded, "{:?}", attempt);
+    assert_eq!(attempt.status_code, Some(200));
+
+    let output = receiver.output();
+    let line = output.iter().find(|line| line.contains(&event.id.to_string())).expect("receiver printed the event");
+    assert!(line.starts_with("payment.received "));
+    assert!(line.contains(r#""currency":"USD""#));
+}
+
+#[test]
+fn receiver_rejects_a_delivery_signed_with_another_secret() {
+    allow_local_receiver();
+    let receiver = Receiver::start(&[]);
+    let event = event();
+
+    let attempt = WebhookSender::new().send(&receiver.url, "whsec_wrong", &event);
+    assert!(!attempt.succeeded);
+    assert_eq!(attempt.status_code, Some(401));
+    assert!(receiver.output().iter().any(|line| line.starts_with("REJECTED") && line.contains(&event.id.to_string())));
+}
+
+#[test]
+fn failing_receiver_is_reported_as_a_failed_attempt() {
+    allow_local_receiver();
+    let receiver = Receiver::start(&["--fail-with", "500"]);
+
+    let attempt = WebhookSender::new().send(&receiver.url, SECRET, &event());
+    assert!(!attempt.succeeded);
+    assert_eq!(attempt.status_code, Some(500));
+    assert!(attempt.error.unwrap().contains("500"));
+}